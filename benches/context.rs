@@ -12,7 +12,8 @@ extern crate test;
 
 use test::Bencher;
 
-use context::{Context, Transfer};
+use context::{typed, Context, Transfer};
+use context::pingpong::{Peer, PingPong};
 use context::stack::FixedSizeStack;
 use std::mem;
 
@@ -65,3 +66,61 @@ fn resume_ontop(b: &mut Bencher) {
         t = mem::transmute_copy::<_, Transfer>(&t).context.resume_ontop(0, ontop_function);
     });
 }
+
+#[bench]
+fn pingpong_swap(b: &mut Bencher) {
+    fn echo(peer: &mut Peer, mut data: usize) -> ! {
+        loop {
+            data = peer.swap(data);
+        }
+    }
+
+    let mut pingpong = PingPong::new(FixedSizeStack::default(), echo);
+
+    b.iter(|| pingpong.swap(test::black_box(1)));
+}
+
+const BATCH_SIZE: usize = 64;
+
+#[bench]
+fn resume_per_item(b: &mut Bencher) {
+    extern "C" fn consumer(mut t: Transfer) -> ! {
+        loop {
+            test::black_box(t.data);
+            t = unsafe { t.context.resume(0) };
+        }
+    }
+
+    let stack = FixedSizeStack::default();
+    let mut t = Transfer::new(unsafe { Context::new(&stack, consumer) }, 0);
+    let items = [1usize; BATCH_SIZE];
+
+    b.iter(|| unsafe {
+        for &item in items.iter() {
+            t = mem::transmute_copy::<_, Transfer>(&t).context.resume(item);
+        }
+    });
+}
+
+#[bench]
+fn resume_batch(b: &mut Bencher) {
+    extern "C" fn consumer(t: Transfer) -> ! {
+        let mut t = unsafe { typed::Transfer::from_raw(t).context.resume(0) };
+
+        loop {
+            let batch = unsafe { typed::Batch::<usize>::from_transfer(t) };
+            for item in &batch {
+                test::black_box(item);
+            }
+            t = unsafe { batch.resume(0) };
+        }
+    }
+
+    let stack = FixedSizeStack::default();
+    let mut t = unsafe { typed::Context::new(&stack, consumer).resume(0) };
+    let items = [1usize; BATCH_SIZE];
+
+    b.iter(|| unsafe {
+        t = mem::transmute_copy::<_, typed::Transfer>(&t).context.resume_batch(&items);
+    });
+}
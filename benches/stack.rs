@@ -10,9 +10,13 @@
 extern crate context;
 extern crate test;
 
-use context::stack::{Stack, FixedSizeStack, ProtectedFixedSizeStack};
+use std::ptr;
+
+use context::stack::{AllocOptions, FixedSizeStack, HugePagePolicy, ProtectedFixedSizeStack, Stack};
 use test::Bencher;
 
+const LARGE_STACK_SIZE: usize = 8 << 20;
+
 #[bench]
 fn stack_alloc_reference_perf(b: &mut Bencher) {
     b.iter(|| test::black_box(Vec::<u8>::with_capacity(Stack::default_size())));
@@ -27,3 +31,27 @@ fn stack_alloc_fixed(b: &mut Bencher) {
 fn stack_alloc_protected_fixed(b: &mut Bencher) {
     b.iter(|| test::black_box(ProtectedFixedSizeStack::default()));
 }
+
+// Writes to a single byte in every page of the stack, which is dominated by dTLB misses.
+fn touch_pages(stack: &Stack, stride: usize) {
+    let bottom = stack.bottom() as *mut u8;
+    let mut offset = 0;
+
+    while offset < stack.len() {
+        unsafe { ptr::write_volatile(bottom.add(offset), 0x1d) };
+        offset += stride;
+    }
+}
+
+#[bench]
+fn stack_touch_large(b: &mut Bencher) {
+    let stack = FixedSizeStack::new(LARGE_STACK_SIZE).unwrap();
+    b.iter(|| touch_pages(&stack, 4096));
+}
+
+#[bench]
+fn stack_touch_large_huge_pages(b: &mut Bencher) {
+    let options = AllocOptions::new().huge_pages(HugePagePolicy::AtLeast(LARGE_STACK_SIZE));
+    let stack = FixedSizeStack::with_options(LARGE_STACK_SIZE, options).unwrap();
+    b.iter(|| touch_pages(&stack, 4096));
+}
@@ -79,8 +79,12 @@ fn main() {
 
         let mut path = base_path.clone();
         path.push(file_name);
+        println!("cargo:rerun-if-changed={}", path.display());
         config.file(path.to_str().unwrap());
     }
 
+    println!("cargo:rustc-env=CONTEXT_ASM_VARIANT={}_{}_{}_{}", arch, abi, format, asm);
+    println!("cargo:rerun-if-changed=build.rs");
+
     config.compile("libboost_context.a");
 }
@@ -0,0 +1,187 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use stack::RawStackError;
+
+static RESERVED: AtomicUsize = AtomicUsize::new(0);
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK_LIVE: AtomicUsize = AtomicUsize::new(0);
+static LIMIT: AtomicUsize = AtomicUsize::new(NO_LIMIT);
+static MAX_LIVE: AtomicUsize = AtomicUsize::new(NO_LIMIT);
+static CALLBACK: AtomicUsize = AtomicUsize::new(0);
+
+const NO_LIMIT: usize = usize::MAX;
+
+/// An event reported to the callback registered with `set_callback()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A stack of `size` bytes has been allocated.
+    /// `reserved` is the total amount of reserved bytes afterwards.
+    Allocated {
+        /// The size of the allocated stack including its guard page.
+        size: usize,
+        /// The total amount of reserved bytes including this stack.
+        reserved: usize,
+    },
+
+    /// A stack of `size` bytes has been deallocated.
+    Deallocated {
+        /// The size of the deallocated stack including its guard page.
+        size: usize,
+        /// The total amount of reserved bytes excluding this stack.
+        reserved: usize,
+    },
+
+    /// The allocation of a stack of `size` bytes was refused, because it would exceed `limit`.
+    LimitReached {
+        /// The size of the refused stack including its guard page.
+        size: usize,
+        /// The limit configured using `set_limit()`.
+        limit: usize,
+    },
+
+    /// The allocation of a stack of `size` bytes was refused, because it would exceed `limit`.
+    LiveLimitReached {
+        /// The size of the refused stack including its guard page.
+        size: usize,
+        /// The limit configured using `set_max_live_stacks()`.
+        limit: usize,
+    },
+}
+
+/// Returns the total amount of bytes currently reserved as stack memory, including guard pages.
+///
+/// This is the amount of virtual memory. On most platforms pages are only backed by physical
+/// memory once they are touched, so the resident size of the stacks is usually much lower.
+pub fn reserved_bytes() -> usize {
+    RESERVED.load(Ordering::Relaxed)
+}
+
+/// Returns the number of stacks which are currently allocated.
+pub fn live_stacks() -> usize {
+    LIVE.load(Ordering::Relaxed)
+}
+
+/// Returns the highest number of stacks which were allocated at the same time since the process
+/// started or `reset_peak_live_stacks()` was last called.
+///
+/// Alerting on this gauge approaching `max_live_stacks()` gives operators time to react before
+/// allocations start to fail.
+pub fn peak_live_stacks() -> usize {
+    PEAK_LIVE.load(Ordering::Relaxed)
+}
+
+/// Resets `peak_live_stacks()` to the number of stacks which are currently allocated.
+pub fn reset_peak_live_stacks() {
+    PEAK_LIVE.store(LIVE.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+/// Returns the limit for `reserved_bytes()` configured using `set_limit()`.
+pub fn limit() -> Option<usize> {
+    match LIMIT.load(Ordering::Relaxed) {
+        NO_LIMIT => None,
+        limit => Some(limit),
+    }
+}
+
+/// Sets a process-wide limit for `reserved_bytes()` or removes it if `None` is passed.
+///
+/// Allocating a stack which would exceed the limit fails with `StackErrorKind::LimitReached`.
+/// Lowering the limit below the currently reserved amount does not affect existing stacks.
+pub fn set_limit(limit: Option<usize>) {
+    LIMIT.store(limit.unwrap_or(NO_LIMIT), Ordering::Relaxed);
+}
+
+/// Returns the limit for `live_stacks()` configured using `set_max_live_stacks()`.
+pub fn max_live_stacks() -> Option<usize> {
+    match MAX_LIVE.load(Ordering::Relaxed) {
+        NO_LIMIT => None,
+        limit => Some(limit),
+    }
+}
+
+/// Sets a process-wide limit for `live_stacks()` or removes it if `None` is passed.
+///
+/// Allocating a stack which would exceed the limit fails with
+/// `StackErrorKind::LiveLimitReached`. This bounds the number of coroutines regardless of their
+/// stack sizes, which keeps a burst of them from driving the machine into swap. Lowering the
+/// limit below the number of live stacks does not affect existing stacks.
+pub fn set_max_live_stacks(limit: Option<usize>) {
+    MAX_LIVE.store(limit.unwrap_or(NO_LIMIT), Ordering::Relaxed);
+}
+
+/// Registers a function which is called for every `Event`, or removes it if `None` is passed.
+///
+/// The callback is invoked synchronously by the thread allocating or deallocating a stack
+/// and can be used to export the counters to a metrics system.
+pub fn set_callback(callback: Option<fn(Event)>) {
+    CALLBACK.store(callback.map_or(0, |f| f as usize), Ordering::Release);
+}
+
+fn notify(event: Event) {
+    let callback = CALLBACK.load(Ordering::Acquire);
+
+    if callback != 0 {
+        let callback: fn(Event) = unsafe { mem::transmute(callback) };
+        callback(event);
+    }
+}
+
+// Accounts for a stack of `size` bytes which has just been allocated.
+// Returns the limit which would be exceeded, in which case the stack must be deallocated again.
+pub(crate) fn reserve(size: usize) -> Result<(), RawStackError> {
+    // The stack is counted first, so that concurrent allocations can't exceed the limit together.
+    let max_live = MAX_LIVE.load(Ordering::Relaxed);
+    let live = match LIVE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+        if live < max_live { Some(live + 1) } else { None }
+    }) {
+        Ok(live) => live + 1,
+        Err(_) => {
+            notify(Event::LiveLimitReached {
+                size,
+                limit: max_live,
+            });
+            return Err(RawStackError::LiveLimitReached(max_live));
+        }
+    };
+
+    let mut reserved = RESERVED.load(Ordering::Relaxed);
+
+    loop {
+        let limit = LIMIT.load(Ordering::Relaxed);
+        let new = match reserved.checked_add(size) {
+            Some(new) if new <= limit => new,
+            _ => {
+                LIVE.fetch_sub(1, Ordering::Relaxed);
+                notify(Event::LimitReached { size, limit });
+                return Err(RawStackError::LimitReached(limit));
+            }
+        };
+
+        match RESERVED.compare_exchange_weak(reserved, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => {
+                PEAK_LIVE.fetch_max(live, Ordering::Relaxed);
+                notify(Event::Allocated {
+                    size,
+                    reserved: new,
+                });
+                return Ok(());
+            }
+            Err(current) => reserved = current,
+        }
+    }
+}
+
+// Accounts for a stack of `size` bytes which has been deallocated.
+pub(crate) fn release(size: usize) {
+    let reserved = RESERVED.fetch_sub(size, Ordering::Relaxed) - size;
+    LIVE.fetch_sub(1, Ordering::Relaxed);
+    notify(Event::Deallocated { size, reserved });
+}
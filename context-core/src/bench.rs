@@ -0,0 +1,99 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::hint;
+use std::time::{Duration, Instant};
+
+use context::{Context, Transfer};
+use stack::{ProtectedFixedSizeStack, Stack, StackError};
+
+// The number of round trips timed together, which keeps the overhead of reading the clock low.
+const WINDOW: u32 = 64;
+
+/// The latency of a single switch between two `Context`s, as measured by `switch_latency()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of windows of 64 round trips which were timed.
+    pub windows: usize,
+
+    /// The latency in the fastest window.
+    pub min: Duration,
+
+    /// The median latency of all windows.
+    pub median: Duration,
+
+    /// The mean latency of all windows.
+    pub mean: Duration,
+
+    /// The latency in the slowest window.
+    pub max: Duration,
+}
+
+/// Measures the latency of switching between two `Context`s on the current machine.
+///
+/// `iterations` round trips are made between the current thread and a `Context` in windows of
+/// 64 each, which are timed separately. The statistics of those windows are returned as the
+/// latency of a single switch, i.e. half of a round trip. This allows applications to calibrate
+/// parameters like batching thresholds at startup instead of hard-coding them.
+///
+/// # Examples
+///
+/// ```
+/// let stats = context_core::bench::switch_latency(10_000).unwrap();
+/// assert!(stats.min <= stats.median && stats.median <= stats.max);
+/// ```
+pub fn switch_latency(iterations: usize) -> Result<Stats, StackError> {
+    extern "C" fn echo(mut t: Transfer) -> ! {
+        loop {
+            t = unsafe { t.context.resume(t.data) };
+        }
+    }
+
+    let windows = (iterations / WINDOW as usize).max(1);
+    let stack = ProtectedFixedSizeStack::new(Stack::min_size())?;
+    let mut t = unsafe { Context::new(&stack, echo).resume(0) };
+    let mut samples = Vec::with_capacity(windows);
+
+    for _ in 0..windows {
+        let start = Instant::now();
+
+        for i in 0..WINDOW as usize {
+            t = unsafe { t.context.resume(hint::black_box(i)) };
+        }
+
+        samples.push(start.elapsed() / (2 * WINDOW));
+    }
+
+    samples.sort();
+
+    let total: Duration = samples.iter().sum();
+
+    Ok(Stats {
+        windows,
+        min: samples[0],
+        median: samples[windows / 2],
+        mean: total / windows as u32,
+        max: samples[windows - 1],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switch_latency() {
+        let stats = super::switch_latency(1000).unwrap();
+
+        assert_eq!(stats.windows, 1000 / 64);
+        assert!(stats.min <= stats.median && stats.median <= stats.max);
+        assert!(stats.min <= stats.mean && stats.mean <= stats.max);
+        assert!(stats.max > Duration::new(0, 0));
+
+        assert_eq!(super::switch_latency(0).unwrap().windows, 1);
+    }
+}
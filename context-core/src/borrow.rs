@@ -0,0 +1,128 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::backtrace::Backtrace;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+
+use stack::Stack;
+
+struct Borrow {
+    id: u64,
+    bottom: usize,
+    top: usize,
+    taken_at: Backtrace,
+}
+
+struct Registry {
+    next_id: u64,
+    borrows: Vec<Borrow>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    next_id: 0,
+    borrows: Vec::new(),
+});
+
+fn registry() -> MutexGuard<'static, Registry> {
+    // The registry is consistent at all times, even if a diagnostic panicked while holding it.
+    REGISTRY.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Records that a `Context` still references a stack, until it's dropped.
+///
+/// `Context::new()` only borrows its stack, so nothing prevents the owner of the stack from
+/// dropping it while the `Context` is still going to be resumed, which is a use-after-free.
+/// Take a token using `Stack::borrow_token()` when creating a `Context` and drop it once the
+/// `Context` finished. Dropping a `FixedSizeStack`, `ProtectedFixedSizeStack` or `AllocatorStack`
+/// while a token for it is alive then panics right away, with the backtrace of where the token
+/// was taken if `RUST_BACKTRACE` is set.
+///
+/// Requires the `borrow-tracking` feature.
+///
+/// # Examples
+///
+/// ```
+/// use context_core::{Context, Transfer};
+/// use context_core::stack::ProtectedFixedSizeStack;
+///
+/// extern "C" fn context_function(t: Transfer) -> ! {
+///     unsafe { t.context.finish(0) }
+/// }
+///
+/// let stack = ProtectedFixedSizeStack::default();
+/// let token = stack.borrow_token();
+/// let t = unsafe { Context::new(&stack, context_function).resume(0) };
+///
+/// // The context finished and doesn't reference the stack anymore.
+/// drop(token);
+/// drop(stack);
+/// ```
+#[must_use = "dropping a BorrowToken releases the borrow right away"]
+pub struct BorrowToken {
+    id: u64,
+}
+
+impl BorrowToken {
+    pub(crate) fn new(stack: &Stack) -> BorrowToken {
+        let mut registry = registry();
+        let id = registry.next_id;
+
+        registry.next_id += 1;
+        registry.borrows.push(Borrow {
+            id,
+            bottom: stack.bottom() as usize,
+            top: stack.top() as usize,
+            taken_at: Backtrace::capture(),
+        });
+
+        BorrowToken { id }
+    }
+}
+
+impl Drop for BorrowToken {
+    fn drop(&mut self) {
+        registry().borrows.retain(|borrow| borrow.id != self.id);
+    }
+}
+
+impl fmt::Debug for BorrowToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BorrowToken").field("id", &self.id).finish()
+    }
+}
+
+/// Returns the number of `BorrowToken`s which are alive in the process.
+pub fn live_borrows() -> usize {
+    registry().borrows.len()
+}
+
+// Panics if a token for (a part of) `stack` is alive. Called by the owners of stacks right
+// before they deallocate them, unless the thread is panicking already.
+pub(crate) fn check_released(stack: &Stack) {
+    if thread::panicking() {
+        return;
+    }
+
+    let (bottom, top) = (stack.bottom() as usize, stack.top() as usize);
+    let registry = registry();
+    let mut borrows = registry.borrows.iter().filter(|b| b.bottom < top && bottom < b.top);
+
+    if let Some(borrow) = borrows.next() {
+        let count = 1 + borrows.count();
+        let taken_at = borrow.taken_at.to_string();
+        drop(registry);
+
+        panic!("stack {:#x}..{:#x} dropped while {} context(s) still borrow it, \
+                the first one taken at:\n{}",
+               bottom,
+               top,
+               count,
+               taken_at);
+    }
+}
@@ -0,0 +1,1535 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::os::raw::c_void;
+use std::process;
+use std::ptr::{self, NonNull};
+
+#[cfg(feature = "debug-switch")]
+use debug;
+#[cfg(feature = "thread-migration")]
+use migration;
+#[cfg(any(all(target_arch = "x86_64", not(windows)), all(target_arch = "aarch64", not(windows))))]
+use record::Record;
+use smoke;
+use stack::{ProtectedFixedSizeStack, Stack, StackOwner};
+
+// Requires cdecl calling convention on x86, which is the default for "C" blocks.
+extern "C" {
+    /// Creates a new `Context` ontop of some stack.
+    ///
+    /// # Arguments
+    /// * `sp`   - A pointer to the bottom of the stack.
+    /// * `size` - The size of the stack.
+    /// * `f`    - A function to be invoked on the first call to jump_fcontext(this, _).
+    fn make_fcontext(sp: *mut c_void, size: usize, f: ContextFn) -> &'static c_void;
+
+    /// Yields the execution to another `Context`.
+    ///
+    /// # Arguments
+    /// * `to` - A pointer to the `Context` with whom we swap execution.
+    /// * `p`  - An arbitrary argument that will be set as the `data` field
+    ///   of the `Transfer` object passed to the other Context.
+    pub(crate) fn jump_fcontext(to: &'static c_void, p: usize) -> Transfer;
+
+    /// Yields the execution to another `Context` and executes a function "ontop" of it's stack.
+    ///
+    /// # Arguments
+    /// * `to` - A pointer to the `Context` with whom we swap execution.
+    /// * `p`  - An arbitrary argument that will be set as the `data` field
+    ///   of the `Transfer` object passed to the other Context.
+    /// * `f`  - A function to be invoked on `to` before returning.
+    fn ontop_fcontext(to: &'static c_void, p: usize, f: ResumeOntopFn) -> Transfer;
+}
+
+/// Functions of this signature are used as the entry point for a new `Context`.
+pub type ContextFn = extern "C" fn(t: Transfer) -> !;
+
+/// Functions of this signature are used as the callback while resuming ontop of a `Context`.
+pub type ResumeOntopFn = extern "C" fn(t: Transfer) -> Transfer;
+
+// The space make_fcontext() reserves below a 16 byte aligned stack top for the initial frame,
+// as computed by the assembly for each architecture and ABI.
+#[cfg(all(target_arch = "x86_64", windows))]
+const INITIAL_FRAME_OVERHEAD: usize = 0x150;
+#[cfg(all(target_arch = "x86_64", not(windows)))]
+const INITIAL_FRAME_OVERHEAD: usize = 0x40;
+// The first 0x10 bytes are the argument slot of the context function and its alignment.
+#[cfg(all(target_arch = "x86", windows))]
+const INITIAL_FRAME_OVERHEAD: usize = 0x10 + 0x40;
+#[cfg(all(target_arch = "x86", not(windows)))]
+const INITIAL_FRAME_OVERHEAD: usize = 0x10 + 0x28;
+#[cfg(target_arch = "aarch64")]
+const INITIAL_FRAME_OVERHEAD: usize = 0xb0;
+#[cfg(all(target_arch = "arm", windows))]
+const INITIAL_FRAME_OVERHEAD: usize = 0x48;
+#[cfg(all(target_arch = "arm", not(windows)))]
+const INITIAL_FRAME_OVERHEAD: usize = 124;
+#[cfg(target_arch = "mips")]
+const INITIAL_FRAME_OVERHEAD: usize = 112;
+#[cfg(target_arch = "powerpc")]
+const INITIAL_FRAME_OVERHEAD: usize = 336;
+#[cfg(target_arch = "powerpc64")]
+const INITIAL_FRAME_OVERHEAD: usize = 248;
+
+/// Returns the number of bytes `Context::new()` reserves at the start of a stack
+/// for the initial frame, before the `ContextFn` uses any of it.
+///
+/// The value is exact for stacks whose start is aligned to 16 bytes, which is the case for all
+/// stacks allocated by this crate. `Stack::usable_len_for_context()` accounts for it as well as
+/// for the padding of unaligned stacks.
+#[inline]
+pub fn initial_frame_overhead() -> usize {
+    INITIAL_FRAME_OVERHEAD
+}
+
+/// Returns the alignment in bytes `Context::new()` rounds the start of a stack down to.
+///
+/// This is 16 bytes on all supported architectures, which is the strictest alignment any of
+/// their ABIs requires for the stack pointer, for instance for SSE and NEON operations.
+#[inline]
+pub fn stack_alignment() -> usize {
+    16
+}
+
+/// Returns the name of the assembly variant `build.rs` selected for the target,
+/// composed of its architecture, ABI, binary format and assembler, like `x86_64_sysv_elf_gas`.
+///
+/// A variant which doesn't match the target is detected by a smoke test on the first call to
+/// `Context::new()`, whose panic message includes this name.
+#[inline]
+pub fn asm_variant() -> &'static str {
+    env!("CONTEXT_ASM_VARIANT")
+}
+
+/// A `Context` stores a `ContextFn`'s state of execution, for it to be resumed later.
+///
+/// If we have 2 or more `Context` instances, we can thus easily "freeze" the
+/// current state of execution and explicitely switch to another `Context`.
+/// This `Context` is then resumed exactly where it left of and
+/// can in turn "freeze" and switch to another `Context`.
+///
+/// # Examples
+///
+/// See [examples/basic.rs](https://github.com/zonyitoo/context-rs/blob/master/examples/basic.rs)
+///
+/// # Thread safety
+///
+/// A suspended `Context` may be resumed on another thread than the one it was suspended on.
+/// No additional fences are required for this: Switching to a `Context` is an opaque function
+/// call which the compiler can't reorder memory accesses across, and handing the `Context`
+/// itself over to another thread requires a synchronizing operation (a channel, a mutex,
+/// an atomic store with `Release` and a load with `Acquire`, ...) anyways.
+/// Since the suspending thread switches away *before* it hands the `Context` over, every write
+/// made before the suspension happens-before the resumption on the other thread.
+///
+/// Thread-local variables are not switched though. Code running inside of a `Context` which
+/// migrated to another thread will observe the thread-locals of that thread.
+///
+/// # Fork
+///
+/// A `Context` is nothing but a pointer into its stack, which is ordinary private memory.
+/// Calling `fork()` while contexts are suspended thus duplicates them together with their stacks,
+/// and each process may resume its own copies independently of the other one.
+/// The usual restrictions of `fork()` still apply to the code running inside of those contexts:
+/// Only the forking thread exists in the child, so locks held by other threads stay locked.
+///
+/// # Nesting
+///
+/// The `ContextFn` of a `Context` may itself create and resume other contexts, to any depth,
+/// with their stacks allocated from within it. The following invariants hold while doing so:
+///
+/// * `resume()` only returns once someone resumes the caller in turn. The `Transfer` it returns
+///   contains whichever `Context` did so, which is not necessarily the one the caller resumed.
+/// * A nested `Context` must not be resumed anymore once the frame owning its stack is gone.
+///   Dropping the stack of a suspended `Context` which is never resumed again is fine though.
+/// * The first switch to a nested `Context` must be a `resume()`, just like for any other one.
+///   `context::typed::Context` turns a `resume_ontop()` before it into a compile error.
+/// * Unwinding never crosses a switch. A panic has to be caught inside the `ContextFn` it was
+///   raised in and may then be passed on, for instance by resuming the parent.
+// The reference is using 'static because we can't possibly imply the
+// lifetime of the Context instances returned by resume() anyways.
+#[repr(C)]
+#[must_use = "dropping a Context leaks its stack and everything living on it"]
+pub struct Context(&'static c_void);
+
+// NOTE: Rustc is kinda dumb and introduces a overhead of up to 500% compared to the asm methods
+//       if we don't explicitely inline them or use LTO (e.g.: 3ns/iter VS. 18ns/iter on i7 3770).
+//       The `outline-switch` feature trades this overhead for smaller code by outlining
+//       resume() and resume_ontop() instead, which are otherwise inlined into every caller.
+impl Context {
+    /// Creates a new `Context` prepared to execute `f` at the beginning of `stack`.
+    ///
+    /// `f` is not executed until the first call to `resume()`.
+    ///
+    /// The start of `stack` doesn't need to be aligned: It is rounded down to `stack_alignment()`
+    /// before the initial frame is placed on it, which costs up to 15 bytes of the stack.
+    /// Use `new_strict()` to reject such stacks instead.
+    ///
+    /// # Safety
+    ///
+    /// It is unsafe because it only takes a reference of `Stack`. You have to make sure the
+    /// `Stack` lives longer than the generated `Context`.
+    ///
+    /// The first call in a process runs a smoke test of the compiled assembly, which panics
+    /// if it doesn't match the ABI of the target. See `asm_variant()`.
+    #[inline(always)]
+    pub unsafe fn new(stack: &Stack, f: ContextFn) -> Context {
+        smoke::check();
+        Context::make(stack, f)
+    }
+
+    // Creates a new `Context` like `new()`, but without running the smoke test.
+    #[inline(always)]
+    pub(crate) unsafe fn make(stack: &Stack, f: ContextFn) -> Context {
+        #[cfg(feature = "debug-switch")]
+        debug::register_stack(stack);
+        Context(make_fcontext(stack.start(), stack.len(), f))
+    }
+
+    /// Creates a new `Context` like `new()`, but returns `None` if the start of `stack`
+    /// is not aligned to `stack_alignment()`.
+    ///
+    /// This helps to find callers handing over stacks from foreign code which would
+    /// otherwise be silently realigned.
+    ///
+    /// # Safety
+    ///
+    /// See `new()`.
+    #[inline(always)]
+    pub unsafe fn new_strict(stack: &Stack, f: ContextFn) -> Option<Context> {
+        if (stack.start() as usize).is_multiple_of(stack_alignment()) {
+            Some(Context::new(stack, f))
+        } else {
+            None
+        }
+    }
+
+    /// Creates a new `Context` prepared to execute the closure `f` on `stack`, like `new()`.
+    ///
+    /// This saves passing the environment of a `ContextFn` through `Transfer::data`: `f` is
+    /// moved onto `stack` right away, by switching to the new `Context` once, and receives the
+    /// `Transfer` of the first call to `resume()`. Since the `Context` has already been resumed
+    /// once, the first switch to it may also be a `resume_ontop()`.
+    ///
+    /// Instead of never returning like a `ContextFn`, `f` returns the `Transfer` to end with:
+    /// Once `f` returned, the `Context` finishes by resuming its `context` with its `data` as
+    /// if by `finish()`. A panic escaping `f` aborts the process.
+    ///
+    /// `ScopedContext` wraps this in a safe interface for closures exchanging plain `usize`s.
+    ///
+    /// # Safety
+    ///
+    /// See `new()`. Additionally the returned `Context` mustn't be resumed anymore once any
+    /// data borrowed by `f` is gone, since nothing ties their lifetimes together.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use context_core::{Context, Transfer};
+    /// use context_core::stack::ProtectedFixedSizeStack;
+    ///
+    /// let greeting = String::from("Hello");
+    /// let stack = ProtectedFixedSizeStack::default();
+    ///
+    /// let context = unsafe {
+    ///     Context::with_closure(&stack, move |t| {
+    ///         Transfer::new(t.context, greeting.len() + t.data)
+    ///     })
+    /// };
+    ///
+    /// assert_eq!(unsafe { context.resume(2) }.data, 7);
+    /// ```
+    pub unsafe fn with_closure<F>(stack: &Stack, f: F) -> Context
+        where F: FnOnce(Transfer) -> Transfer
+    {
+        extern "C" fn entry<F>(t: Transfer) -> !
+            where F: FnOnce(Transfer) -> Transfer
+        {
+            let f = unsafe { (*(t.data as *mut Option<F>)).take().unwrap() };
+            // Wait on the new stack for the first resume() of the owner of the Context.
+            let t = f(unsafe { t.context.resume(0) });
+            unsafe { t.context.finish(t.data) }
+        }
+
+        let mut f = Some(f);
+        let t = Context::new(stack, entry::<F>).resume(&mut f as *mut Option<F> as usize);
+        t.context
+    }
+
+    /// Duplicates this suspended `Context` onto `into`, returning a `Context` which continues
+    /// from the same state of execution independently of the original one.
+    ///
+    /// `stack` must be the stack this `Context` runs on. The live region between `as_ptr()` and
+    /// its start is copied to the same offset from the start of `into`. Every word in the copy
+    /// which holds an address within `stack` is then relocated into `into`, which fixes up
+    /// saved frame pointers and references between locals. Returns `None` if `into` is too small.
+    ///
+    /// This is meant for pure computations like generators in search and backtracking
+    /// algorithms, where duplicating a suspended generator is far cheaper than replaying it.
+    ///
+    /// # Safety
+    ///
+    /// See `new()`. Additionally the suspended code must tolerate being duplicated:
+    ///
+    /// * It must not own anything outside of its stack, like heap allocations or OS handles,
+    ///   since both copies would then release it. `Rc`s, `Box`es, `Vec`s and files are all
+    ///   off-limits.
+    /// * It must not keep addresses within its stack anywhere else than on the stack itself,
+    ///   for instance in registers other than the saved ones or on the heap.
+    /// * It must not keep integers on its stack which happen to lie within the address range of
+    ///   `stack`, since they are relocated like addresses.
+    pub unsafe fn clone_suspended(&self, stack: &Stack, into: &Stack) -> Option<Context> {
+        let word = mem::size_of::<usize>();
+        let low = self.0 as *const c_void as usize;
+        let high = stack.start() as usize & !(stack_alignment() - 1);
+        let into_high = into.start() as usize & !(stack_alignment() - 1);
+        let live = high - low;
+
+        if into_high < into.bottom() as usize + live {
+            return None;
+        }
+
+        let into_low = into_high - live;
+        ptr::copy_nonoverlapping(low as *const u8, into_low as *mut u8, live);
+
+        let (bottom, top) = (stack.bottom() as usize, stack.top() as usize);
+        for addr in (into_low..into_high).step_by(word) {
+            let slot = addr as *mut usize;
+            let value = *slot;
+
+            if bottom <= value && value < top {
+                *slot = value.wrapping_sub(high).wrapping_add(into_high);
+            }
+        }
+
+        #[cfg(feature = "debug-switch")]
+        debug::register_stack(into);
+        Some(Context(&*(into_low as *const c_void)))
+    }
+
+    /// Yields the execution to another `Context`.
+    ///
+    /// The exact behaviour of this method is implementation defined, but the general mechanism is:
+    /// The current state of execution is preserved somewhere and the previously saved state
+    /// in the `Context` pointed to by `self` is restored and executed next.
+    ///
+    /// This behaviour is similiar in spirit to regular function calls with the difference
+    /// that the call to `resume()` only returns when someone resumes the caller in turn.
+    ///
+    /// The returned `Transfer` struct contains the previously active `Context` and
+    /// the `data` argument used to resume the current one.
+    ///
+    /// # Safety
+    ///
+    /// It is unsafe because it is your responsibility to make sure that all data that constructed in
+    /// this context have to be dropped properly when the last context is dropped.
+    #[cfg_attr(not(feature = "outline-switch"), inline(always))]
+    #[cfg_attr(feature = "outline-switch", inline(never))]
+    pub unsafe fn resume(self, data: usize) -> Transfer {
+        probe!(context, switch, self.0 as *const c_void, data);
+        #[cfg(feature = "debug-switch")]
+        debug::check(self.0, data, false);
+        #[cfg(feature = "thread-migration")]
+        let thread = migration::current_thread();
+        let t = jump_fcontext(self.0, data);
+        #[cfg(feature = "thread-migration")]
+        migration::resumed(thread);
+        t
+    }
+
+    /// Yields the execution to another `Context` and executes a function "ontop" of it's stack.
+    ///
+    /// This method identical to `resume()` with a minor difference:
+    ///
+    /// The argument `f` is executed right after the targeted `Context`, pointed to by `self`,
+    /// is woken up, but before it returns from it's call to `resume()`.
+    /// `f` now gets passed the `Transfer` struct which would normally be returned by `resume()`
+    /// and is allowed to inspect and modify it. The `Transfer` struct `f` returns is then
+    /// finally the one returned by `resume()` in the targeted `Context`.
+    ///
+    /// This behaviour can be used to either execute additional code or *map* the `Transfer` struct
+    /// to another one before it's returned, without the targeted `Context` giving it's consent.
+    /// For instance it can be used to unwind the stack of an unfinished `Context`,
+    /// by calling this method with a function that panics, or to deallocate the own stack,
+    /// by deferring the actual deallocation until we jumped to another, safe `Context`.
+    ///
+    /// # Safety
+    ///
+    /// It is unsafe because it is your responsibility to make sure that all data that constructed in
+    /// this context have to be dropped properly when the last context is dropped.
+    ///
+    /// Furthermore `self` must have been resumed at least once before: A `Context` returned by
+    /// `Context::new()` has not entered its `ContextFn` yet and calling this method on it will
+    /// skip the proper entry of that function, which will then be invoked with garbage arguments
+    /// or crash, depending on the architecture. `context::typed::Context` can be used to turn this
+    /// mistake into a compile error.
+    #[cfg_attr(not(feature = "outline-switch"), inline(always))]
+    #[cfg_attr(feature = "outline-switch", inline(never))]
+    pub unsafe fn resume_ontop(self, data: usize, f: ResumeOntopFn) -> Transfer {
+        probe!(context, switch, self.0 as *const c_void, data);
+        #[cfg(feature = "debug-switch")]
+        debug::check(self.0, data, true);
+        #[cfg(feature = "thread-migration")]
+        let thread = migration::current_thread();
+        let t = ontop_fcontext(self.0, data, f);
+        #[cfg(feature = "thread-migration")]
+        migration::resumed(thread);
+        t
+    }
+
+    /// Yields the execution to another `Context`, passing `data` converted into a `usize`.
+    ///
+    /// This is identical to `resume(data.into_transfer_data())` and the resumed `Context`
+    /// can convert the data back using `Transfer::data_as()`.
+    ///
+    /// # Safety
+    ///
+    /// See `resume()`.
+    #[inline(always)]
+    pub unsafe fn resume_typed<T: IntoTransferData>(self, data: T) -> Transfer {
+        self.resume(data.into_transfer_data())
+    }
+
+    /// Yields the execution to another `Context`, passing it `value` of an arbitrary type.
+    ///
+    /// Unlike `resume_typed()` this isn't limited to types which fit into a `usize`, and the
+    /// address of the value is passed as the `data` of the `Transfer` instead. Values of up to
+    /// `SMALL_TRANSFER_SIZE` bytes stay in a slot in the frame of the caller, which is suspended
+    /// until the resumed `Context` took the value, so passing them doesn't allocate. Larger
+    /// ones are moved into a `Box`. `TypedTransfer::<T>::BOXED` tells which applies to `T`.
+    ///
+    /// The resumed `Context` takes ownership of the value using `Transfer::into_typed()`.
+    /// If it never does, the value is leaked.
+    ///
+    /// # Safety
+    ///
+    /// See `resume()`. Additionally the resumed `Context` must convert the `Transfer` using
+    /// `into_typed()` with the same `T` before resuming the calling `Context` again, or leave
+    /// its `data` untouched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use context_core::{Context, Transfer};
+    /// use context_core::stack::ProtectedFixedSizeStack;
+    ///
+    /// extern "C" fn context_function(t: Transfer) -> ! {
+    ///     let t = unsafe { t.into_typed::<Vec<String>>() };
+    ///     let joined = t.value.join(" ");
+    ///     let _ = unsafe { t.context.resume_value(joined) };
+    ///     unreachable!();
+    /// }
+    ///
+    /// let stack = ProtectedFixedSizeStack::default();
+    /// let ctx = unsafe { Context::new(&stack, context_function) };
+    ///
+    /// let words = vec![String::from("hello"), String::from("world")];
+    /// let t = unsafe { ctx.resume_value(words).into_typed::<String>() };
+    /// assert_eq!(t.value, "hello world");
+    /// ```
+    #[inline(always)]
+    pub unsafe fn resume_value<T>(self, value: T) -> Transfer {
+        if TypedTransfer::<T>::BOXED {
+            self.resume(Box::into_raw(Box::new(value)) as usize)
+        } else {
+            // Taken by into_typed() while this frame is suspended, and never dropped here.
+            let mut slot = MaybeUninit::new(value);
+            self.resume(slot.as_mut_ptr() as usize)
+        }
+    }
+
+    /// Yields the execution to another `Context` for the last time, finishing the `ContextFn`
+    /// running on the current stack.
+    ///
+    /// A `ContextFn` may never return and thus has to end by resuming another `Context`
+    /// which won't resume it again. This method does so and doesn't rely on unwinding,
+    /// so it behaves identically under `panic = "unwind"` and `panic = "abort"`.
+    /// Should the finished `Context` be resumed nonetheless, the process is aborted.
+    ///
+    /// # Safety
+    ///
+    /// See `resume()`. Additionally nothing owned must be left on the current stack,
+    /// since the resumed `Context` may deallocate it right away.
+    #[inline(always)]
+    pub unsafe fn finish(self, data: usize) -> ! {
+        let _ = self.resume(data);
+        process::abort();
+    }
+
+    /// Creates a `Context` continuing at the register state stored in `record`.
+    ///
+    /// This is an escape hatch for code materializing continuations itself, like a JIT.
+    /// The first `resume()` restores the registers from `record` and continues at its program
+    /// counter with the stack pointer right past it. See `record::Record` for the layout and
+    /// `record::entry_position()` for where to place a record which enters a function.
+    ///
+    /// # Safety
+    ///
+    /// See `resume()`. Additionally `record` must lie on a stack which stays alive until the
+    /// `Context` finishes, as checked by `record::check()`, with all memory below it being free
+    /// for use by the continuation, and its registers must describe a state which is valid
+    /// to continue at.
+    ///
+    /// With the `debug-switch` feature the stack has to be registered using
+    /// `debug::register_stack()`, unless a `Context` has been created on it before.
+    #[cfg(any(all(target_arch = "x86_64", not(windows)),
+              all(target_arch = "aarch64", not(windows))))]
+    #[inline(always)]
+    pub unsafe fn from_record(record: NonNull<Record>) -> Context {
+        Context(&*(record.as_ptr() as *const c_void))
+    }
+
+    /// Returns the address of the register block saved when this `Context` was suspended.
+    ///
+    /// The registers are saved on the context's own stack right below the last frame of its
+    /// suspended function. The live region of the stack thus spans from this address up to
+    /// `Stack::start()`, which is what conservative stack scanners need to examine.
+    /// For a `Context` which has never been resumed this points to the initial frame prepared
+    /// by `Context::new()` instead.
+    #[inline(always)]
+    pub fn as_ptr(&self) -> *const c_void {
+        self.0 as *const c_void
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Context({:p})", self.0)
+    }
+}
+
+// Lives on the stack of the side resuming a ScopedContext for the duration of each resume.
+// Its address is the `data` of every switch into the context.
+struct Exchange {
+    data: usize,
+    returned: bool,
+}
+
+/// A `Context` executing a closure on a stack of its own, which can be resumed without `unsafe`.
+///
+/// The closure receives a `ScopedSuspender` to return to whoever resumed it, and exchanges a
+/// `usize` with it on every switch. It may borrow anything outliving `'a`, so nothing it borrows
+/// can go away while the context can be resumed:
+///
+/// ```compile_fail
+/// use context_core::ScopedContext;
+/// use context_core::stack::ProtectedFixedSizeStack;
+///
+/// let mut values = Vec::new();
+/// let mut context = ScopedContext::new(ProtectedFixedSizeStack::default(), |_, data| {
+///     values.push(data);
+///     data
+/// });
+///
+/// drop(values);
+/// context.resume(1);
+/// ```
+///
+/// The context owns its stack, so no other context can run on it meanwhile. A context which is
+/// suspended can't be unwound, so dropping it leaks its stack along with everything living on
+/// it, including the closure. The memory of values pinned on the stack is thus never reused
+/// without dropping them.
+///
+/// # Examples
+///
+/// ```
+/// use context_core::ScopedContext;
+/// use context_core::stack::ProtectedFixedSizeStack;
+///
+/// let mut values = vec![1, 2];
+///
+/// {
+///     let stack = ProtectedFixedSizeStack::default();
+///     let mut context = ScopedContext::new(stack, |suspender, mut data| {
+///         while data != 0 {
+///             values.push(data);
+///             data = suspender.suspend(values.iter().sum());
+///         }
+///         values.len()
+///     });
+///
+///     assert_eq!(context.resume(3), 6);
+///     assert_eq!(context.resume(4), 10);
+///     assert_eq!(context.resume(0), 4);
+///     assert!(context.is_finished());
+/// }
+///
+/// assert_eq!(values, [1, 2, 3, 4]);
+/// ```
+pub struct ScopedContext<'a, S: StackOwner = ProtectedFixedSizeStack> {
+    context: Option<Context>,
+    started: bool,
+    // Owned for as long as the context may run on it, and leaked if it never finishes.
+    stack: ManuallyDrop<S>,
+    // The data borrowed by the closure, which might not be Send.
+    marker: PhantomData<(&'a mut (), *mut ())>,
+}
+
+impl<'a, S: StackOwner> ScopedContext<'a, S> {
+    /// Creates a new `ScopedContext` prepared to execute `f` on `stack`, which it keeps until
+    /// it's dropped.
+    ///
+    /// `f` is moved onto `stack` right away, but not executed until the first call to
+    /// `resume()`, whose `data` it receives. A panic escaping `f` aborts the process.
+    pub fn new<F>(stack: S, f: F) -> ScopedContext<'a, S>
+        where F: for<'s> FnOnce(&mut ScopedSuspender<'s>, usize) -> usize + 'a
+    {
+        // Safe since the returned handle owns `stack`, can't outlive anything `f` borrows, and
+        // is the only way to resume the context.
+        let context = unsafe {
+            Context::with_closure(&stack, move |t| {
+                // Dropped before it was ever resumed, which drops `f` along with this closure.
+                if t.data == 0 {
+                    return Transfer::new(t.context, 0);
+                }
+
+                let mut suspender = ScopedSuspender {
+                    context: Some(t.context),
+                    exchange: t.data as *mut Exchange,
+                    brand: PhantomData,
+                };
+
+                let data = (*suspender.exchange).data;
+                let data = f(&mut suspender, data);
+
+                *suspender.exchange = Exchange {
+                    data,
+                    returned: true,
+                };
+                Transfer::new(suspender.context.take().unwrap(), 0)
+            })
+        };
+
+        ScopedContext {
+            context: Some(context),
+            started: false,
+            stack: ManuallyDrop::new(stack),
+            marker: PhantomData,
+        }
+    }
+
+    /// Resumes the context, passing `data` to it.
+    ///
+    /// On the first call `data` is passed as the argument of the closure, otherwise it's
+    /// returned by the call to `ScopedSuspender::suspend()` the context is suspended in.
+    /// Returns the `data` passed to `ScopedSuspender::suspend()`, or the value returned by the
+    /// closure, in which case the context is finished.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the context is finished.
+    pub fn resume(&mut self, data: usize) -> usize {
+        let context = self.context.take().expect("resumed a finished ScopedContext");
+        self.started = true;
+
+        let mut exchange = Exchange {
+            data,
+            returned: false,
+        };
+
+        let t = unsafe { context.resume(&mut exchange as *mut Exchange as usize) };
+
+        if !exchange.returned {
+            self.context = Some(t.context);
+        }
+        exchange.data
+    }
+
+    /// Returns whether the closure has returned.
+    pub fn is_finished(&self) -> bool {
+        self.context.is_none()
+    }
+}
+
+impl<'a, S: StackOwner> Drop for ScopedContext<'a, S> {
+    fn drop(&mut self) {
+        match self.context.take() {
+            Some(context) if !self.started => {
+                // Lets the closure drop `f` and finish.
+                let _ = unsafe { context.resume(0) };
+            },
+            Some(_) => return,
+            None => (),
+        }
+
+        unsafe { ManuallyDrop::drop(&mut self.stack) };
+    }
+}
+
+impl<'a, S: StackOwner> fmt::Debug for ScopedContext<'a, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScopedContext").field("context", &self.context).finish()
+    }
+}
+
+/// Passed to the closure of a `ScopedContext` to return to whoever resumed it.
+///
+/// Every suspender carries a lifetime of its own, which keeps the suspenders of nested contexts
+/// from being exchanged, since they would then return to the wrong context:
+///
+/// ```compile_fail
+/// use std::mem;
+///
+/// use context_core::ScopedContext;
+/// use context_core::stack::ProtectedFixedSizeStack;
+///
+/// let stack = ProtectedFixedSizeStack::default();
+/// let mut outer = ScopedContext::new(stack, |outer, data| {
+///     let stack = ProtectedFixedSizeStack::default();
+///     let mut inner = ScopedContext::new(stack, |inner, data| {
+///         mem::swap(outer, inner);
+///         data
+///     });
+///     inner.resume(data)
+/// });
+/// ```
+pub struct ScopedSuspender<'s> {
+    context: Option<Context>,
+    exchange: *mut Exchange,
+    // Invariant, so no two suspenders have the same type.
+    brand: PhantomData<fn(&'s ()) -> &'s ()>,
+}
+
+impl<'s> ScopedSuspender<'s> {
+    /// Suspends the context, returning `data` from the call to `ScopedContext::resume()`.
+    ///
+    /// Returns the `data` of the next call to `ScopedContext::resume()`.
+    pub fn suspend(&mut self, data: usize) -> usize {
+        unsafe { (*self.exchange).data = data };
+
+        let Transfer { context, data } = unsafe { self.context.take().unwrap().resume(0) };
+        self.context = Some(context);
+        self.exchange = data as *mut Exchange;
+
+        unsafe { (*self.exchange).data }
+    }
+}
+
+/// Contains the previously active `Context` and the `data` passed to resume the current one and
+/// is used as the return value by `Context::resume()` and `Context::resume_ontop()`
+#[repr(C)]
+#[derive(Debug)]
+#[must_use = "dropping a Transfer leaks the stack of its Context and everything living on it"]
+pub struct Transfer {
+    /// The previously executed `Context` which yielded to resume the current one.
+    pub context: Context,
+
+    /// The `data` which was passed to `Context::resume()` or
+    /// `Context::resume_ontop()` to resume the current `Context`.
+    pub data: usize,
+}
+
+impl Transfer {
+    /// Returns a new `Transfer` struct with the members set to their respective arguments.
+    #[inline(always)]
+    pub fn new(context: Context, data: usize) -> Transfer {
+        Transfer { context, data }
+    }
+
+    /// Destructures the `Transfer` into its `context` and `data`.
+    #[inline(always)]
+    pub fn into_parts(self) -> (Context, usize) {
+        (self.context, self.data)
+    }
+
+    /// Converts the `data` back into the value passed to `Context::resume_typed()`.
+    ///
+    /// # Safety
+    ///
+    /// `data` must have been created by `T::into_transfer_data()`.
+    #[inline(always)]
+    pub unsafe fn data_as<T: FromTransferData>(&self) -> T {
+        T::from_transfer_data(self.data)
+    }
+
+    /// Takes ownership of the value passed to `Context::resume_value()`.
+    ///
+    /// # Safety
+    ///
+    /// `data` must have been created by `Context::resume_value()` with the same `T`, and the
+    /// value mustn't have been taken before. Since small values are read from the frame of the
+    /// `context`, it mustn't have been resumed since it created the `Transfer`.
+    #[inline(always)]
+    pub unsafe fn into_typed<T>(self) -> TypedTransfer<T> {
+        TypedTransfer {
+            context: self.context,
+            value: TypedTransfer::decode(self.data),
+        }
+    }
+}
+
+/// The size up to which `Context::resume_value()` passes values without allocating.
+pub const SMALL_TRANSFER_SIZE: usize = 64;
+
+/// A `Transfer` whose `data` was converted back into the value passed to
+/// `Context::resume_value()`, as returned by `Transfer::into_typed()`.
+#[derive(Debug)]
+#[must_use = "dropping a TypedTransfer leaks the stack of its Context and everything living on it"]
+pub struct TypedTransfer<T> {
+    /// The previously executed `Context` which yielded to resume the current one.
+    pub context: Context,
+
+    /// The value passed to `Context::resume_value()` to resume the current `Context`.
+    pub value: T,
+}
+
+impl<T> TypedTransfer<T> {
+    /// Whether `Context::resume_value()` moves values of `T` into a `Box`, which is the case
+    /// if they are larger than `SMALL_TRANSFER_SIZE` bytes.
+    pub const BOXED: bool = mem::size_of::<T>() > SMALL_TRANSFER_SIZE;
+
+    unsafe fn decode(data: usize) -> T {
+        if Self::BOXED {
+            *Box::from_raw(data as *mut T)
+        } else {
+            ptr::read(data as *const T)
+        }
+    }
+}
+
+/// Types which can be converted into the `data` of a `Transfer` without any allocation.
+///
+/// This trait is only implemented for types which are guaranteed to fit into a `usize`
+/// on the current platform. For instance `u64` implements it only on 64-bit platforms.
+///
+/// # Examples
+///
+/// Fieldless enums can be passed by their discriminant:
+///
+/// ```
+/// use context_core::{FromTransferData, IntoTransferData};
+///
+/// #[derive(Clone, Copy, Debug, PartialEq)]
+/// enum Command {
+///     Continue,
+///     Stop,
+/// }
+///
+/// impl IntoTransferData for Command {
+///     fn into_transfer_data(self) -> usize {
+///         self as usize
+///     }
+/// }
+///
+/// impl FromTransferData for Command {
+///     unsafe fn from_transfer_data(data: usize) -> Command {
+///         match data {
+///             0 => Command::Continue,
+///             _ => Command::Stop,
+///         }
+///     }
+/// }
+///
+/// let data = Command::Stop.into_transfer_data();
+/// assert_eq!(unsafe { Command::from_transfer_data(data) }, Command::Stop);
+/// ```
+pub trait IntoTransferData: Copy {
+    /// Converts `self` into a `usize` suitable for `Context::resume()`.
+    fn into_transfer_data(self) -> usize;
+}
+
+/// Types which can be restored from the `data` of a `Transfer`.
+///
+/// See `IntoTransferData` for more information.
+pub trait FromTransferData: Copy {
+    /// Restores a value from a `usize` created by `IntoTransferData::into_transfer_data()`.
+    ///
+    /// # Safety
+    ///
+    /// `data` must have been created by `into_transfer_data()` of the same type,
+    /// since not every `usize` is necessarily a valid value of it.
+    unsafe fn from_transfer_data(data: usize) -> Self;
+}
+
+macro_rules! impl_transfer_data_int {
+    ($($t:ty)*) => ($(
+        impl IntoTransferData for $t {
+            #[inline(always)]
+            fn into_transfer_data(self) -> usize {
+                self as usize
+            }
+        }
+
+        impl FromTransferData for $t {
+            #[inline(always)]
+            unsafe fn from_transfer_data(data: usize) -> $t {
+                data as $t
+            }
+        }
+    )*)
+}
+
+impl_transfer_data_int!(u8 u16 u32 usize i8 i16 i32 isize);
+
+#[cfg(target_pointer_width = "64")]
+impl_transfer_data_int!(u64 i64);
+
+impl IntoTransferData for () {
+    #[inline(always)]
+    fn into_transfer_data(self) -> usize {
+        0
+    }
+}
+
+impl FromTransferData for () {
+    #[inline(always)]
+    unsafe fn from_transfer_data(_: usize) {}
+}
+
+impl IntoTransferData for bool {
+    #[inline(always)]
+    fn into_transfer_data(self) -> usize {
+        self as usize
+    }
+}
+
+impl FromTransferData for bool {
+    #[inline(always)]
+    unsafe fn from_transfer_data(data: usize) -> bool {
+        data != 0
+    }
+}
+
+impl IntoTransferData for char {
+    #[inline(always)]
+    fn into_transfer_data(self) -> usize {
+        self as usize
+    }
+}
+
+impl FromTransferData for char {
+    #[inline(always)]
+    unsafe fn from_transfer_data(data: usize) -> char {
+        char::from_u32_unchecked(data as u32)
+    }
+}
+
+impl<T> IntoTransferData for *const T {
+    #[inline(always)]
+    fn into_transfer_data(self) -> usize {
+        self as usize
+    }
+}
+
+impl<T> FromTransferData for *const T {
+    #[inline(always)]
+    unsafe fn from_transfer_data(data: usize) -> *const T {
+        data as *const T
+    }
+}
+
+impl<T> IntoTransferData for *mut T {
+    #[inline(always)]
+    fn into_transfer_data(self) -> usize {
+        self as usize
+    }
+}
+
+impl<T> FromTransferData for *mut T {
+    #[inline(always)]
+    unsafe fn from_transfer_data(data: usize) -> *mut T {
+        data as *mut T
+    }
+}
+
+impl<T> IntoTransferData for NonNull<T> {
+    #[inline(always)]
+    fn into_transfer_data(self) -> usize {
+        self.as_ptr() as usize
+    }
+}
+
+impl<T> FromTransferData for NonNull<T> {
+    #[inline(always)]
+    unsafe fn from_transfer_data(data: usize) -> NonNull<T> {
+        NonNull::new_unchecked(data as *mut T)
+    }
+}
+
+impl<T> IntoTransferData for Option<NonNull<T>> {
+    #[inline(always)]
+    fn into_transfer_data(self) -> usize {
+        self.map_or(0, |ptr| ptr.as_ptr() as usize)
+    }
+}
+
+impl<T> FromTransferData for Option<NonNull<T>> {
+    #[inline(always)]
+    unsafe fn from_transfer_data(data: usize) -> Option<NonNull<T>> {
+        NonNull::new(data as *mut T)
+    }
+}
+
+/// A pointer to `T` and a small tag packed into the `data` of a `Transfer`.
+///
+/// The tag is stored in the low bits of the pointer, which are always zero due to the alignment
+/// of `T`. `TAG_BITS` of them are available, so a `u64` leaves room for tags up to 7 on most
+/// platforms. Tags which don't fit and misaligned pointers are rejected by `new()` instead of
+/// silently corrupting the pointer.
+///
+/// # Examples
+///
+/// ```
+/// use context_core::{FromTransferData, IntoTransferData, TaggedData};
+///
+/// let mut value = 0u32;
+/// let data = TaggedData::new(&mut value, 3).unwrap().into_transfer_data();
+///
+/// let tagged = unsafe { TaggedData::<u32>::from_transfer_data(data) };
+/// assert_eq!(tagged.tag(), 3);
+/// assert_eq!(tagged.ptr(), &mut value as *mut u32);
+/// ```
+pub struct TaggedData<T> {
+    data: usize,
+    marker: PhantomData<*mut T>,
+}
+
+impl<T> TaggedData<T> {
+    /// The number of low bits available for the tag.
+    pub const TAG_BITS: u32 = mem::align_of::<T>().trailing_zeros();
+
+    /// The mask covering the bits of the tag.
+    pub const TAG_MASK: usize = mem::align_of::<T>() - 1;
+
+    /// Packs `ptr` and `tag` together.
+    ///
+    /// Returns `None` if `tag` exceeds `TAG_MASK` or `ptr` isn't aligned for `T`.
+    #[inline(always)]
+    pub fn new(ptr: *mut T, tag: usize) -> Option<TaggedData<T>> {
+        if tag & !Self::TAG_MASK != 0 || ptr as usize & Self::TAG_MASK != 0 {
+            return None;
+        }
+
+        Some(TaggedData {
+            data: ptr as usize | tag,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns the pointer without the tag.
+    #[inline(always)]
+    pub fn ptr(self) -> *mut T {
+        (self.data & !Self::TAG_MASK) as *mut T
+    }
+
+    /// Returns the tag.
+    #[inline(always)]
+    pub fn tag(self) -> usize {
+        self.data & Self::TAG_MASK
+    }
+}
+
+impl<T> Clone for TaggedData<T> {
+    #[inline(always)]
+    fn clone(&self) -> TaggedData<T> {
+        *self
+    }
+}
+
+impl<T> Copy for TaggedData<T> {}
+
+impl<T> fmt::Debug for TaggedData<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TaggedData")
+            .field("ptr", &self.ptr())
+            .field("tag", &self.tag())
+            .finish()
+    }
+}
+
+impl<T> IntoTransferData for TaggedData<T> {
+    #[inline(always)]
+    fn into_transfer_data(self) -> usize {
+        self.data
+    }
+}
+
+impl<T> FromTransferData for TaggedData<T> {
+    #[inline(always)]
+    unsafe fn from_transfer_data(data: usize) -> TaggedData<T> {
+        TaggedData {
+            data,
+            marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::mem;
+    use std::ops::Deref;
+    use std::os::raw::c_void;
+    #[cfg(panic = "unwind")]
+    use std::panic;
+    use std::ptr::NonNull;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use stack::{ProtectedFixedSizeStack, Stack};
+    use super::*;
+
+    #[test]
+    fn initial_frame_overhead() {
+        extern "C" fn context_function(_: Transfer) -> ! {
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let ctx = unsafe { Context::new(&stack, context_function) };
+        let used = stack.start() as usize - ctx.as_ptr() as usize;
+
+        assert_eq!(used, super::initial_frame_overhead());
+        assert_eq!(stack.usable_len_for_context(), stack.len() - used);
+    }
+
+    #[test]
+    fn finish() {
+        extern "C" fn context_function(t: Transfer) -> ! {
+            let value = Box::new(t.data * 2);
+            let data = *value;
+            drop(value);
+
+            unsafe { t.context.finish(data) }
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let t = unsafe { Context::new(&stack, context_function).resume(21) };
+        assert_eq!(t.data, 42);
+        drop(stack);
+    }
+
+    #[test]
+    fn with_closure() {
+        let stack = ProtectedFixedSizeStack::default();
+        let mut values = vec![1, 2];
+
+        // The closure owns `values`, which lives on `stack` from now on.
+        let context = unsafe {
+            Context::with_closure(&stack, move |mut t| loop {
+                values.push(t.data);
+                t = t.context.resume(values.iter().sum());
+            })
+        };
+        let mut t = Transfer::new(context, 0);
+
+        for &(data, sum) in &[(3, 6), (4, 10)] {
+            t = unsafe { t.context.resume(data) };
+            assert_eq!(t.data, sum);
+        }
+    }
+
+    #[test]
+    fn scoped_context() {
+        let mut sum = 0;
+
+        {
+            let stack = ProtectedFixedSizeStack::default();
+            let mut context = ScopedContext::new(stack, |suspender, mut data| {
+                while data != 0 {
+                    sum += data;
+                    data = suspender.suspend(data * 2);
+                }
+                42
+            });
+
+            assert!(!context.is_finished());
+            assert_eq!(context.resume(1), 2);
+            assert_eq!(context.resume(2), 4);
+            assert_eq!(context.resume(0), 42);
+            assert!(context.is_finished());
+        }
+
+        assert_eq!(sum, 3);
+    }
+
+    #[test]
+    fn scoped_context_drop() {
+        thread_local! {
+            static DROPPED: Cell<usize> = const { Cell::new(0) };
+        }
+
+        // Counts the stacks dropped on the current thread.
+        struct CountedStack(ProtectedFixedSizeStack);
+
+        impl Deref for CountedStack {
+            type Target = Stack;
+
+            fn deref(&self) -> &Stack {
+                &self.0
+            }
+        }
+
+        impl Drop for CountedStack {
+            fn drop(&mut self) {
+                DROPPED.with(|dropped| dropped.set(dropped.get() + 1));
+            }
+        }
+
+        unsafe impl StackOwner for CountedStack {}
+
+        let stack = || CountedStack(ProtectedFixedSizeStack::default());
+        let dropped = || DROPPED.with(Cell::get);
+
+        // A context which never ran drops its closure along with the stack.
+        let captured = Rc::new(());
+        let c = captured.clone();
+        drop(ScopedContext::new(stack(), move |_, data| data + Rc::strong_count(&c)));
+        assert_eq!((Rc::strong_count(&captured), dropped()), (1, 1));
+
+        let mut context = ScopedContext::new(stack(), |_, data| data + 1);
+        assert_eq!(context.resume(7), 8);
+        drop(context);
+        assert_eq!(dropped(), 2);
+
+        // A suspended context leaks its stack.
+        let mut context = ScopedContext::new(stack(), |suspender, data| suspender.suspend(data));
+        assert_eq!(context.resume(7), 7);
+        drop(context);
+        assert_eq!(dropped(), 2);
+    }
+
+    #[test]
+    fn clone_suspended() {
+        extern "C" fn context_function(mut t: Transfer) -> ! {
+            let mut n = t.data;
+            let r = &mut n;
+
+            loop {
+                t = unsafe { t.context.resume(*r) };
+                *r += t.data;
+            }
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let copy = ProtectedFixedSizeStack::default();
+        let t = unsafe { Context::new(&stack, context_function).resume(10) };
+        assert_eq!(t.data, 10);
+        let t = unsafe { t.context.resume(1) };
+        assert_eq!(t.data, 11);
+
+        let clone = unsafe { t.context.clone_suspended(&stack, &copy) }.unwrap();
+        let original = unsafe { t.context.resume(1) };
+        assert_eq!(original.data, 12);
+
+        let t = unsafe { clone.resume(100) };
+        assert_eq!(t.data, 111);
+        let t = unsafe { t.context.resume(1) };
+        assert_eq!(t.data, 112);
+        assert_eq!(unsafe { original.context.resume(1) }.data, 13);
+
+        let small = unsafe { Stack::new(copy.top(), (copy.top() as usize - 16) as *mut c_void) };
+        assert!(unsafe { t.context.clone_suspended(&copy, &small) }.is_none());
+    }
+
+    #[test]
+    fn stack_realignment() {
+        extern "C" fn context_function(t: Transfer) -> ! {
+            let _ = unsafe { t.context.resume(t.data + 1) };
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let aligned = unsafe { Context::new(&stack, context_function) };
+        assert!(unsafe { Context::new_strict(&stack, context_function) }.is_some());
+
+        // A start which is only 8 byte aligned is rounded down, keeping the initial frame aligned.
+        let top = (stack.top() as usize - 8) as *mut c_void;
+        let misaligned = unsafe { Stack::new(top, stack.bottom()) };
+        let ctx = unsafe { Context::new(&misaligned, context_function) };
+        assert!(ctx.as_ptr() < aligned.as_ptr());
+        assert_eq!((aligned.as_ptr() as usize - ctx.as_ptr() as usize) % stack_alignment(), 0);
+        assert_eq!(misaligned.usable_len_for_context(), stack.usable_len_for_context() - 16);
+        assert!(unsafe { Context::new_strict(&misaligned, context_function) }.is_none());
+
+        let t = unsafe { ctx.resume(1) };
+        assert_eq!(t.data, 2);
+    }
+
+    #[test]
+    fn type_sizes() {
+        assert_eq!(mem::size_of::<Context>(), mem::size_of::<usize>());
+        assert_eq!(mem::size_of::<Context>(), mem::size_of::<*const c_void>());
+    }
+
+    #[cfg(feature = "nightly")]
+    #[test]
+    fn stack_alignment() {
+        #[allow(non_camel_case_types)]
+        #[repr(simd)]
+        struct u8x16(u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8);
+
+        extern "C" fn context_function(t: Transfer) -> ! {
+            // If we do not use an array in combination with mem::uninitialized(),
+            // Rust will still generate SSE/NEON operations for the assignment
+            // and make the test crash with a segmentation fault due to misalignment.
+            let data: [u8x16; 1] = unsafe { mem::uninitialized() };
+            let addr = &data as *const _ as usize;
+
+            unsafe { t.context.resume(addr % mem::align_of::<u8x16>()) };
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let mut t = Transfer::new(unsafe { Context::new(&stack, context_function) }, 0);
+
+        t = unsafe { t.context.resume(0) };
+        assert_eq!(t.data, 0);
+    }
+
+    #[test]
+    fn number_generator() {
+        extern "C" fn context_function(mut t: Transfer) -> ! {
+            for i in 0usize.. {
+                assert_eq!(t.data, i);
+                t = unsafe { t.context.resume(i) };
+            }
+
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let mut t = Transfer::new(unsafe { Context::new(&stack, context_function) }, 0);
+
+        for i in 0..10usize {
+            t = unsafe { t.context.resume(i) };
+            assert_eq!(t.data, i);
+
+            if t.data == 9 {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn resume_ontop() {
+        extern "C" fn resume(t: Transfer) -> ! {
+            assert_eq!(t.data, 0);
+            let _ = unsafe { t.context.resume_ontop(1, resume_ontop) };
+            unreachable!();
+        }
+
+        extern "C" fn resume_ontop(mut t: Transfer) -> Transfer {
+            assert_eq!(t.data, 1);
+            t.data = 123;
+            t
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let mut t = Transfer::new(unsafe { Context::new(&stack, resume) }, 0);
+
+        t = unsafe { t.context.resume(0) };
+        assert_eq!(t.data, 123);
+    }
+
+    #[test]
+    fn nested() {
+        const DONE: usize = usize::MAX;
+
+        // Every level creates a child on a stack allocated from within it, forwards all values
+        // yielded by the child to its parent and yields its own depth afterwards.
+        extern "C" fn level(t: Transfer) -> ! {
+            let depth = t.data;
+            let mut parent = t.context;
+
+            if depth > 0 {
+                let stack = ProtectedFixedSizeStack::default();
+                let mut child = unsafe { Context::new(&stack, level) };
+                let mut data = depth - 1;
+
+                loop {
+                    let t = unsafe { child.resume(data) };
+                    child = t.context;
+                    data = 0;
+
+                    if t.data == DONE {
+                        break;
+                    }
+
+                    parent = unsafe { parent.resume(t.data) }.context;
+                }
+            }
+
+            parent = unsafe { parent.resume(depth) }.context;
+            let _ = unsafe { parent.resume(DONE) };
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let mut t = unsafe { Context::new(&stack, level).resume(3) };
+        let mut values = Vec::new();
+
+        while t.data != DONE {
+            values.push(t.data);
+            t = unsafe { t.context.resume(0) };
+        }
+
+        assert_eq!(values, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn typed_values() {
+        struct Dropper(Rc<Cell<usize>>);
+
+        impl Drop for Dropper {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        extern "C" fn context_function(t: Transfer) -> ! {
+            let t = unsafe { t.into_typed::<(u8, u16)>() };
+            let t = unsafe { t.context.resume_value([t.value.1 as u64; 4]) };
+
+            let t = unsafe { t.into_typed::<[u64; 16]>() };
+            let t = unsafe { t.context.resume_value(t.value.iter().sum::<u64>()) };
+
+            let t = unsafe { t.into_typed::<Dropper>() };
+            let dropped = t.value.0.clone();
+            drop(t.value);
+            let _ = unsafe { t.context.resume_value(Some(dropped)) };
+
+            unreachable!();
+        }
+
+        const _: () = assert!(!TypedTransfer::<[u64; 8]>::BOXED &&
+                              TypedTransfer::<[u64; 16]>::BOXED);
+
+        let stack = ProtectedFixedSizeStack::default();
+        let ctx = unsafe { Context::new(&stack, context_function) };
+
+        let t = unsafe { ctx.resume_value((1u8, 500u16)).into_typed::<[u64; 4]>() };
+        assert_eq!(t.value, [500; 4]);
+
+        let t = unsafe { t.context.resume_value([3u64; 16]).into_typed::<u64>() };
+        assert_eq!(t.value, 48);
+
+        let dropped = Rc::new(Cell::new(0));
+        let t = unsafe { t.context.resume_value(Dropper(dropped.clone())) };
+        let t = unsafe { t.into_typed::<Option<Rc<Cell<usize>>>>() };
+        assert_eq!(t.value.map(|dropped| dropped.get()), Some(1));
+        assert_eq!(dropped.get(), 1);
+    }
+
+    #[test]
+    fn typed_data() {
+        extern "C" fn context_function(t: Transfer) -> ! {
+            let c: char = unsafe { t.data_as() };
+            let t = unsafe { t.context.resume_typed(c.is_alphabetic()) };
+
+            let i: i32 = unsafe { t.data_as() };
+            let t = unsafe { t.context.resume_typed(i.wrapping_neg()) };
+
+            let mut ptr: Option<NonNull<u32>> = unsafe { t.data_as() };
+            unsafe { *ptr.as_mut().unwrap().as_mut() += 1 };
+            let _ = unsafe { t.context.resume_typed(ptr) };
+
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let ctx = unsafe { Context::new(&stack, context_function) };
+
+        let t = unsafe { ctx.resume_typed('ä') };
+        assert!(unsafe { t.data_as::<bool>() });
+
+        let t = unsafe { t.context.resume_typed(-123i32) };
+        assert_eq!(unsafe { t.data_as::<i32>() }, 123);
+
+        let mut value = 41u32;
+        let t = unsafe { t.context.resume_typed(NonNull::new(&mut value as *mut u32)) };
+        let ptr: Option<NonNull<u32>> = unsafe { t.data_as() };
+        assert_eq!(ptr.map(NonNull::as_ptr), Some(&mut value as *mut u32));
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn tagged_data() {
+        let mut value = 0u64;
+        let ptr = &mut value as *mut u64;
+        let tag_mask = mem::align_of::<u64>() - 1;
+
+        assert_eq!(TaggedData::<u64>::TAG_MASK, tag_mask);
+        assert_eq!(1 << TaggedData::<u64>::TAG_BITS, tag_mask + 1);
+        assert_eq!(TaggedData::<u8>::TAG_BITS, 0);
+
+        for tag in 0..tag_mask + 1 {
+            let data = TaggedData::new(ptr, tag).unwrap().into_transfer_data();
+            let tagged = unsafe { TaggedData::<u64>::from_transfer_data(data) };
+            assert_eq!((tagged.ptr(), tagged.tag()), (ptr, tag));
+        }
+
+        assert!(TaggedData::new(ptr, tag_mask + 1).is_none());
+        assert!(TaggedData::new((ptr as usize + 1) as *mut u64, 0).is_none());
+        assert!(TaggedData::new(&mut 0u8, 1).is_none());
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn practical_min_stack_size() {
+        extern "C" fn context_function(mut t: Transfer) -> ! {
+            loop {
+                let data = t.data;
+                let result = panic::catch_unwind(|| {
+                    let values = [data as f64, 2.0, 3.0, 4.0];
+                    let sum: f64 = values.iter().map(|v| v.sqrt()).sum();
+
+                    if sum > 0.0 {
+                        panic::resume_unwind(Box::new(sum));
+                    }
+                });
+
+                t = unsafe { t.context.resume(result.is_err() as usize) };
+            }
+        }
+
+        let stack = ProtectedFixedSizeStack::new(Stack::practical_min_size()).unwrap();
+        let mut t = Transfer::new(unsafe { Context::new(&stack, context_function) }, 0);
+
+        for i in 0..3usize {
+            t = unsafe { t.context.resume(i) };
+            assert_eq!(t.data, 1);
+        }
+    }
+
+    #[test]
+    fn resume_on_other_thread() {
+        extern "C" fn context_function(mut t: Transfer) -> ! {
+            let mut sum = 0;
+
+            for i in 0usize.. {
+                sum += i;
+                t = unsafe { t.context.resume(sum) };
+            }
+
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let mut t = Transfer::new(unsafe { Context::new(&stack, context_function) }, 0);
+
+        t = unsafe { t.context.resume(0) };
+        assert_eq!(t.data, 0);
+
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let (t, stack): (Transfer, ProtectedFixedSizeStack) = rx.recv().unwrap();
+            let t = unsafe { t.context.resume(0) };
+            (t.data, stack)
+        });
+
+        tx.send((t, stack)).unwrap();
+
+        let (data, _stack) = handle.join().unwrap();
+        assert_eq!(data, 1);
+    }
+}
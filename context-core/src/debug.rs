@@ -0,0 +1,537 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Bound;
+use std::os::raw::c_void;
+use std::process;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+use stack::Stack;
+use sys;
+
+const RING_SIZE: usize = 16;
+
+/// A switch recorded by `Context::resume()` or `Context::resume_ontop()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Switch {
+    /// The address of the `Context` which was resumed.
+    pub target: *const c_void,
+
+    /// The `data` passed to it.
+    pub data: usize,
+
+    /// Whether the switch was made using `resume_ontop()`.
+    pub ontop: bool,
+}
+
+/// A copy of the memory a suspended `Context` was saved on, taken right before it was resumed.
+///
+/// See `enable_snapshots()` for more information.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The number of the switch on the current thread, counting from 1.
+    pub sequence: u64,
+
+    /// The switch the snapshot was taken for.
+    pub switch: Switch,
+
+    /// The memory starting at `switch.target`, which holds the registers saved by the context
+    /// followed by the frames it was suspended in.
+    pub stack: Vec<u8>,
+}
+
+struct Snapshots {
+    every: u64,
+    bytes: usize,
+    capacity: usize,
+    ring: VecDeque<Snapshot>,
+}
+
+// The stacks a suspended Context may have been saved on, each as a map from the top of a stack
+// to its bottom. The stacks within each map never overlap. Thread stacks are kept apart, as the
+// stack of a Context may be carved out of one.
+struct Stacks {
+    contexts: BTreeMap<usize, usize>,
+    threads: BTreeMap<usize, usize>,
+}
+
+impl Stacks {
+    // Returns the bounds of the innermost known stack `addr` lies within.
+    fn containing(&self, addr: usize) -> Option<(usize, usize)> {
+        containing(&self.contexts, addr).or_else(|| containing(&self.threads, addr))
+    }
+}
+
+static STACKS: Mutex<Stacks> = Mutex::new(Stacks {
+    contexts: BTreeMap::new(),
+    threads: BTreeMap::new(),
+});
+
+// The registration of the stack of a thread, removed once the thread exits.
+struct ThreadStack(Option<(usize, usize)>);
+
+impl ThreadStack {
+    fn register() -> ThreadStack {
+        let bounds = sys::thread_stack_bounds();
+        if let Some((bottom, top)) = bounds {
+            insert(&mut stacks().threads, bottom, top);
+        }
+        ThreadStack(bounds)
+    }
+}
+
+impl Drop for ThreadStack {
+    fn drop(&mut self) {
+        if let Some((bottom, top)) = self.0 {
+            remove(&mut stacks().threads, bottom, top);
+        }
+    }
+}
+
+struct Ring {
+    switches: [Option<Switch>; RING_SIZE],
+    next: usize,
+}
+
+thread_local! {
+    static RING: RefCell<Ring> = const {
+        RefCell::new(Ring {
+            switches: [None; RING_SIZE],
+            next: 0,
+        })
+    };
+    static SWITCHES: Cell<u64> = const { Cell::new(0) };
+    static SNAPSHOTS: RefCell<Option<Snapshots>> = const { RefCell::new(None) };
+    static THREAD_STACK: ThreadStack = ThreadStack::register();
+}
+
+/// Registers `stack` as memory a suspended `Context` may be saved on.
+///
+/// `Context::resume()` and `Context::resume_ontop()` refuse any `Context` which doesn't lie
+/// within a registered stack or the stack of a running thread. Stacks passed to `Context::new()`
+/// and `Context::clone_suspended()` are registered automatically, and those allocated by this
+/// crate are unregistered when dropped. A stack which a `Context` is rebuilt on using
+/// `Context::from_record()` has to be registered explicitly, unless a context was created on it
+/// before.
+///
+/// Registering a stack replaces the registrations of all stacks it overlaps.
+pub fn register_stack(stack: &Stack) {
+    insert(&mut stacks().contexts, stack.bottom() as usize, stack.top() as usize);
+}
+
+/// Removes the registration of `stack` made by `register_stack()`.
+///
+/// Does nothing if `stack` isn't registered with exactly these bounds.
+pub fn unregister_stack(stack: &Stack) {
+    remove(&mut stacks().contexts, stack.bottom() as usize, stack.top() as usize);
+}
+
+fn stacks() -> MutexGuard<'static, Stacks> {
+    // The map is consistent after every operation, so a panic while holding the lock is harmless.
+    STACKS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn insert(stacks: &mut BTreeMap<usize, usize>, bottom: usize, top: usize) {
+    // Overlapping stacks are the remains of stacks which were freed without unregistering them.
+    while let Some((&other_top, &other_bottom)) =
+        stacks.range((Bound::Excluded(bottom), Bound::Unbounded)).next() {
+        if other_bottom >= top {
+            break;
+        }
+        stacks.remove(&other_top);
+    }
+
+    stacks.insert(top, bottom);
+}
+
+fn remove(stacks: &mut BTreeMap<usize, usize>, bottom: usize, top: usize) {
+    if stacks.get(&top) == Some(&bottom) {
+        stacks.remove(&top);
+    }
+}
+
+// Returns the bounds of the registered stack `addr` lies within.
+fn containing(stacks: &BTreeMap<usize, usize>, addr: usize) -> Option<(usize, usize)> {
+    stacks.range((Bound::Excluded(addr), Bound::Unbounded))
+        .next()
+        .filter(|&(_, &bottom)| bottom <= addr)
+        .map(|(&top, &bottom)| (bottom, top))
+}
+
+/// Returns the most recent switches made on the current thread, oldest first.
+///
+/// Up to 16 switches are retained, including one which was refused as invalid.
+pub fn recent_switches() -> Vec<Switch> {
+    let mut switches = Vec::with_capacity(RING_SIZE);
+    for_recent_switches(|switch| switches.push(*switch));
+    switches
+}
+
+// Calls `f` with the most recent switches made on the current thread, oldest first.
+fn for_recent_switches<F: FnMut(&Switch)>(mut f: F) {
+    let _ = RING.try_with(|ring| {
+        if let Ok(ring) = ring.try_borrow() {
+            let (newer, older) = ring.switches.split_at(ring.next);
+            older.iter().chain(newer).filter_map(|switch| switch.as_ref()).for_each(&mut f);
+        }
+    });
+}
+
+/// Takes a snapshot of the stack of every `every`th `Context` resumed on the current thread,
+/// keeping the most recent `capacity` ones, until `disable_snapshots()` is called.
+///
+/// Each snapshot copies up to `bytes` bytes starting at the suspended context, which are the
+/// registers it saved followed by the innermost frames it was suspended in, along with the
+/// `data` passed to it. Comparing the snapshots retrieved using `snapshots()`, or printed by
+/// `dump_snapshots()` once a fault was detected, with the state of the coroutine at the time of
+/// the fault helps to reconstruct what it was doing right before. A process aborted by an
+/// invalid switch dumps them along with the recent switches.
+///
+/// The extent of the stack of a `Context` isn't known at this level, so a snapshot ends at the
+/// end of the memory page the context was saved on, which may yield fewer than `bytes` bytes.
+/// Copying the memory slows down switching considerably, so keep `every` large in anything but
+/// targeted debugging sessions.
+///
+/// # Panics
+///
+/// Panics if `every` or `capacity` is 0.
+pub fn enable_snapshots(every: u64, bytes: usize, capacity: usize) {
+    assert!(every > 0 && capacity > 0, "snapshots require a positive interval and capacity");
+
+    SNAPSHOTS.with(|snapshots| {
+        *snapshots.borrow_mut() = Some(Snapshots {
+            every,
+            bytes,
+            capacity,
+            ring: VecDeque::with_capacity(capacity),
+        })
+    });
+}
+
+/// Stops taking snapshots on the current thread and discards the ones taken so far.
+pub fn disable_snapshots() {
+    SNAPSHOTS.with(|snapshots| *snapshots.borrow_mut() = None);
+}
+
+/// Returns the snapshots taken on the current thread, oldest first.
+pub fn snapshots() -> Vec<Snapshot> {
+    SNAPSHOTS.try_with(|snapshots| match *snapshots.borrow() {
+            Some(ref snapshots) => snapshots.ring.iter().cloned().collect(),
+            None => Vec::new(),
+        })
+        .unwrap_or_default()
+}
+
+/// Writes the snapshots taken on the current thread to `out` as a hex dump, oldest first.
+///
+/// This allocates nothing, which makes it usable from a panic hook or a fault handler which
+/// detected a corruption.
+pub fn dump_snapshots<W: Write>(out: &mut W) -> io::Result<()> {
+    SNAPSHOTS.try_with(|snapshots| {
+            // The ring is borrowed if a snapshot is being taken while the fault is detected.
+            let snapshots = match snapshots.try_borrow() {
+                Ok(snapshots) => snapshots,
+                Err(_) => return Ok(()),
+            };
+            let ring = match *snapshots {
+                Some(ref snapshots) => &snapshots.ring,
+                None => return Ok(()),
+            };
+
+            for snapshot in ring {
+                let switch = &snapshot.switch;
+                writeln!(out,
+                         "switch #{}: {} {:p} with data {:#x}",
+                         snapshot.sequence,
+                         if switch.ontop { "resume_ontop" } else { "resume" },
+                         switch.target,
+                         switch.data)?;
+
+                for (i, line) in snapshot.stack.chunks(16).enumerate() {
+                    write!(out, "  {:#x}:", switch.target as usize + i * 16)?;
+                    for byte in line {
+                        write!(out, " {:02x}", byte)?;
+                    }
+                    writeln!(out)?;
+                }
+            }
+
+            Ok(())
+        })
+        .unwrap_or(Ok(()))
+}
+
+// Copies the memory `target` points to if a snapshot is due for the current switch.
+fn snapshot(switch: &Switch) {
+    let sequence = match SWITCHES.try_with(|switches| {
+        switches.set(switches.get() + 1);
+        switches.get()
+    }) {
+        Ok(sequence) => sequence,
+        Err(_) => return,
+    };
+
+    let _ = SNAPSHOTS.try_with(|snapshots| {
+        let mut snapshots = match snapshots.try_borrow_mut() {
+            Ok(snapshots) => snapshots,
+            Err(_) => return,
+        };
+        let snapshots = match *snapshots {
+            Some(ref mut snapshots) if sequence % snapshots.every == 0 => snapshots,
+            _ => return,
+        };
+
+        // The page the registers were saved on is mapped, while the memory beyond it may not be.
+        let page_size = sys::page_size();
+        let target = switch.target as usize;
+        let len = snapshots.bytes.min(page_size - target % page_size);
+
+        // Reuse the buffer of the oldest snapshot once the ring is full.
+        let mut stack = if snapshots.ring.len() == snapshots.capacity {
+            snapshots.ring.pop_front().map(|snapshot| snapshot.stack).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        stack.clear();
+        stack.extend_from_slice(unsafe { slice::from_raw_parts(target as *const u8, len) });
+        snapshots.ring.push_back(Snapshot {
+            sequence,
+            switch: *switch,
+            stack,
+        });
+    });
+}
+
+// Returns why `target` can't be a suspended Context, if it can be told.
+fn validate(target: *const c_void) -> Option<&'static str> {
+    let addr = target as usize;
+
+    // The registers are saved on the stack using word sized stores on all architectures.
+    if target.is_null() {
+        return Some("it is null");
+    }
+    if !addr.is_multiple_of(mem::align_of::<usize>()) {
+        return Some("it is misaligned");
+    }
+
+    // Without the bounds of the stack of this thread, contexts saved on it can't be told apart
+    // from garbage. This is also the case while the thread exits.
+    if !THREAD_STACK.try_with(|stack| stack.0.is_some()).unwrap_or(false) {
+        return None;
+    }
+
+    // The registers are saved below the top of the stack the context was suspended on.
+    let stacks = stacks();
+    let stack = match stacks.containing(addr) {
+        Some((bottom, top)) if addr + mem::size_of::<usize>() <= top => (bottom, top),
+        _ => return Some("it lies outside of every known stack"),
+    };
+
+    // Only one context runs on a stack at a time, so any context saved on the running stack has
+    // been resumed already and what it saved is being overwritten.
+    let sp = &addr as *const usize as usize;
+    if stacks.containing(sp) == Some(stack) {
+        return Some("it lies on the running stack, which means it is running already");
+    }
+
+    None
+}
+
+// Records the switch and aborts the process with a report if `target` can't be a valid
+// Context, instead of letting the assembly jump to garbage.
+pub(crate) fn check(target: *const c_void, data: usize, ontop: bool) {
+    let switch = Switch { target, data, ontop };
+
+    let _ = RING.try_with(|ring| {
+        let mut ring = ring.borrow_mut();
+        let next = ring.next;
+        ring.switches[next] = Some(switch);
+        ring.next = (next + 1) % RING_SIZE;
+    });
+
+    if let Some(reason) = validate(target) {
+        report(&switch, reason);
+        process::abort();
+    }
+
+    snapshot(&switch);
+}
+
+// Prints the refused switch along with the recent ones and the snapshots. This allocates
+// nothing, as the heap may be corrupted just as well.
+fn report(switch: &Switch, reason: &str) {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+
+    let _ = writeln!(stderr,
+                     "context: refusing to resume the invalid Context {:p} with data {:#x}, as \
+                      {}",
+                     switch.target,
+                     switch.data,
+                     reason);
+    let _ = writeln!(stderr, "context: recent switches on this thread, oldest first:");
+
+    for_recent_switches(|switch| {
+        let _ = writeln!(stderr,
+                         "context:   {} {:p} with data {:#x}",
+                         if switch.ontop { "resume_ontop" } else { "resume" },
+                         switch.target,
+                         switch.data);
+    });
+
+    let _ = writeln!(stderr, "context: snapshots taken on this thread, oldest first:");
+    let _ = dump_snapshots(&mut stderr);
+
+    let _ = writeln!(stderr,
+                     "context: aborting; enable core dumps (e.g. `ulimit -c unlimited`) to \
+                      inspect the corrupted state");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use context::{Context, Transfer};
+    use stack::ProtectedFixedSizeStack;
+    use super::*;
+
+    #[test]
+    fn recent_switches() {
+        // Any word on a registered stack other than the running one passes the validation.
+        let stack = ProtectedFixedSizeStack::default();
+        register_stack(&stack);
+        for i in 0..RING_SIZE + 2 {
+            check((stack.top() as usize - 8) as *const c_void, i, i % 2 == 1);
+        }
+
+        let switches = super::recent_switches();
+        assert_eq!(switches.len(), RING_SIZE);
+        assert_eq!(switches[0].data, 2);
+        assert_eq!(switches[RING_SIZE - 1].data, RING_SIZE + 1);
+        assert!(switches[RING_SIZE - 1].ontop);
+    }
+
+    #[test]
+    fn validate() {
+        let boxed = Box::new(0usize);
+        let addr = &*boxed as *const usize as usize;
+
+        assert_eq!(super::validate((addr + 1) as *const c_void), Some("it is misaligned"));
+        assert_eq!(super::validate(ptr::null()), Some("it is null"));
+        assert_eq!(super::validate(&*boxed as *const usize as *const c_void),
+                   Some("it lies outside of every known stack"));
+
+        let stack = ProtectedFixedSizeStack::default();
+        let top = (stack.top() as usize - 8) as *const c_void;
+        assert_eq!(super::validate(top), Some("it lies outside of every known stack"));
+        register_stack(&stack);
+        assert_eq!(super::validate(top), None);
+        drop(stack);
+        assert_eq!(super::validate(top), Some("it lies outside of every known stack"));
+    }
+
+    #[test]
+    fn nested_stacks() {
+        extern "C" fn context_function(t: Transfer) -> ! {
+            unsafe { t.context.finish(t.data) }
+        }
+
+        // A stack carved out of the stack of the thread leaves the latter registered.
+        let mut memory = [0usize; 2048];
+        let slot = 0usize;
+        let slot = &slot as *const usize as usize;
+        THREAD_STACK.with(|_| ());
+        let thread_stack = stacks().containing(slot);
+        assert!(thread_stack.is_some());
+        unsafe {
+            let bottom = memory.as_mut_ptr() as *mut c_void;
+            let stack = Stack::new(bottom.add(mem::size_of_val(&memory)), bottom);
+            assert_eq!(Context::new(&stack, context_function).resume(7).data, 7);
+            assert_eq!(stacks().containing(bottom as usize),
+                       Some((bottom as usize, stack.top() as usize)));
+        }
+        assert_eq!(stacks().containing(slot), thread_stack);
+    }
+
+    #[test]
+    fn self_resume() {
+        extern "C" fn context_function(t: Transfer) -> ! {
+            // Suspend, to be resumed with the address this context was saved at.
+            let t = unsafe { t.context.resume(0) };
+            let reason = super::validate(t.data as *const c_void);
+            let running = reason == Some("it lies on the running stack, which means it is \
+                                          running already");
+            assert_eq!(super::validate(t.context.as_ptr()), None);
+            unsafe { t.context.finish(running as usize) }
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let t = unsafe { Context::new(&stack, context_function).resume(0) };
+        let saved = t.context.as_ptr() as usize;
+        assert_eq!(unsafe { t.context.resume(saved).data }, 1);
+    }
+
+    #[test]
+    fn overlapping_stacks() {
+        let mut stacks = BTreeMap::new();
+        insert(&mut stacks, 0x1000, 0x2000);
+        insert(&mut stacks, 0x3000, 0x4000);
+        assert_eq!(containing(&stacks, 0x1000), Some((0x1000, 0x2000)));
+        assert_eq!(containing(&stacks, 0x2000), None);
+        assert_eq!(containing(&stacks, 0x3fff), Some((0x3000, 0x4000)));
+
+        // A stack reusing freed memory replaces the stale registrations it overlaps.
+        insert(&mut stacks, 0x1800, 0x3800);
+        assert_eq!(containing(&stacks, 0x1000), None);
+        assert_eq!(containing(&stacks, 0x3900), None);
+        assert_eq!(containing(&stacks, 0x2000), Some((0x1800, 0x3800)));
+
+        remove(&mut stacks, 0x1000, 0x3800);
+        assert_eq!(stacks.len(), 1);
+        remove(&mut stacks, 0x1800, 0x3800);
+        assert!(stacks.is_empty());
+    }
+
+    #[test]
+    fn snapshots() {
+        extern "C" fn context_function(mut t: Transfer) -> ! {
+            loop {
+                t = unsafe { t.context.resume(t.data + 1) };
+            }
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let mut t = Transfer::new(unsafe { Context::new(&stack, context_function) }, 0);
+
+        enable_snapshots(2, 64, 2);
+        for _ in 0..6 {
+            t = unsafe { t.context.resume(t.data) };
+        }
+
+        // Both directions are switches, and earlier tests on this thread may have switched too.
+        let snapshots = super::snapshots();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].sequence % 2, 0);
+        assert_eq!(snapshots[1].sequence, snapshots[0].sequence + 2);
+        assert_eq!(snapshots[1].switch.data, snapshots[0].switch.data + 1);
+        assert!(!snapshots[1].stack.is_empty() && snapshots[1].stack.len() <= 64);
+
+        let mut dump = Vec::new();
+        dump_snapshots(&mut dump).unwrap();
+        let header = format!("switch #{}: resume ", snapshots[0].sequence);
+        assert!(String::from_utf8(dump).unwrap().starts_with(&header));
+
+        disable_snapshots();
+        assert!(super::snapshots().is_empty());
+    }
+}
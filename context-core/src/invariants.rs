@@ -0,0 +1,41 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use context;
+use stack::Stack;
+
+/// Asserts the invariants the raw switching layer relies on, if debug assertions are enabled.
+///
+/// This checks that:
+///
+/// * `context::stack_alignment()` is a power of two, to which `Context::new()` realigns stacks.
+/// * `Stack::min_size()`, `Stack::practical_min_size()`, `Stack::default_size()` and
+///   `Stack::max_size()` are ordered ascendingly, so that every size suggested by this crate
+///   can actually be allocated.
+///
+/// Integrators can call it at checkpoints in their own test suites. A violated invariant
+/// panics just like `debug_assert!()` does.
+pub fn debug_assert_context_invariants() {
+    let alignment = context::stack_alignment();
+    debug_assert!(alignment.is_power_of_two(),
+                  "stack alignment {} is not a power of two",
+                  alignment);
+
+    let sizes = [Stack::min_size(), Stack::practical_min_size(), Stack::default_size(),
+                 Stack::max_size()];
+    debug_assert!(sizes.windows(2).all(|pair| pair[0] <= pair[1]),
+                  "stack sizes (min, practical min, default, max) {:?} are not ordered",
+                  sizes);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn debug_assert_context_invariants() {
+        super::debug_assert_context_invariants();
+    }
+}
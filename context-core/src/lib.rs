@@ -0,0 +1,75 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#![cfg_attr(feature = "nightly", feature(repr_simd, allocator_api))]
+#![deny(missing_docs)]
+
+//! The raw switching layer of the `context` crate: the **Boost.Context** assembly,
+//! `Context`, `Transfer` and the stack allocators.
+//!
+//! Most users should depend on the `context` crate, which re-exports everything from here
+//! under the same paths and adds higher-level abstractions on top.
+
+extern crate libc;
+#[cfg(windows)]
+extern crate kernel32;
+#[cfg(windows)]
+extern crate winapi;
+
+#[macro_use]
+mod probes;
+
+/// Provides the `Context` and `Transfer` types for
+/// saving and restoring the current state of execution.
+///
+/// See the `Context` struct for more information.
+pub mod context;
+
+/// Provides utilities to allocate memory suitable as stack memory for `Context`.
+pub mod stack;
+
+/// Provides a self-benchmark of the switching cost on the current machine.
+pub mod bench;
+
+/// Provides process-wide accounting of the memory reserved for stacks.
+///
+/// Requires the `accounting` feature.
+#[cfg(feature = "accounting")]
+pub mod accounting;
+
+/// Provides the tracking of stacks borrowed by contexts enabled by the `borrow-tracking` feature.
+///
+/// Requires the `borrow-tracking` feature.
+#[cfg(feature = "borrow-tracking")]
+pub mod borrow;
+
+/// Provides the switch validation and stack snapshots enabled by the `debug-switch` feature.
+///
+/// Requires the `debug-switch` feature.
+#[cfg(feature = "debug-switch")]
+pub mod debug;
+
+/// Provides callbacks for contexts migrating between threads.
+///
+/// Requires the `thread-migration` feature.
+#[cfg(feature = "thread-migration")]
+pub mod migration;
+
+/// Provides the layout of the register block saved for a suspended `Context`.
+///
+/// Only available on x86_64 and AArch64, except for Windows.
+#[cfg(any(all(target_arch = "x86_64", not(windows)), all(target_arch = "aarch64", not(windows))))]
+pub mod record;
+
+mod invariants;
+mod smoke;
+mod sys;
+
+pub use invariants::debug_assert_context_invariants;
+pub use context::{Context, Transfer, ContextFn, ResumeOntopFn, IntoTransferData, FromTransferData,
+                  TaggedData, TypedTransfer, ScopedContext, ScopedSuspender,
+                  SMALL_TRANSFER_SIZE};
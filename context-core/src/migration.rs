@@ -0,0 +1,63 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::mem;
+use std::sync::atomic::{compiler_fence, AtomicUsize, Ordering};
+
+/// The maximum number of callbacks which can be registered using `register()`.
+pub const MAX_CALLBACKS: usize = 8;
+
+static CALLBACKS: [AtomicUsize; MAX_CALLBACKS] = [const { AtomicUsize::new(0) }; MAX_CALLBACKS];
+
+thread_local! {
+    static MARKER: u8 = const { 0 };
+}
+
+/// Registers `callback` to be called whenever a `Context` returns from a call to `resume()` or
+/// `resume_ontop()` on another thread than the one it made the call on.
+///
+/// The callback runs on the new thread, right after the switch, and can be used to refresh
+/// state derived from thread-local storage, like the per-thread caches of an allocator.
+/// Returns `false` if `MAX_CALLBACKS` callbacks are registered already.
+pub fn register(callback: fn()) -> bool {
+    let callback = callback as *const () as usize;
+
+    CALLBACKS.iter().any(|slot| {
+        slot.compare_exchange(0, callback, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+    })
+}
+
+// Returns an address identifying the current thread.
+//
+// This must never be inlined: The compiler may reuse the address of a thread-local variable
+// computed before a switch within the same function, which is exactly what this detects.
+#[inline(never)]
+pub(crate) fn current_thread() -> usize {
+    MARKER.with(|marker| marker as *const u8 as usize)
+}
+
+// Called after every switch back into a Context which was suspended on `thread`.
+#[inline(always)]
+pub(crate) fn resumed(thread: usize) {
+    compiler_fence(Ordering::SeqCst);
+
+    if current_thread() != thread {
+        migrated();
+    }
+}
+
+#[cold]
+fn migrated() {
+    for slot in CALLBACKS.iter() {
+        let callback = slot.load(Ordering::Acquire);
+
+        if callback != 0 {
+            let callback: fn() = unsafe { mem::transmute(callback) };
+            callback();
+        }
+    }
+}
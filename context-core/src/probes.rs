@@ -0,0 +1,85 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// USDT probes in the SystemTap SDT format, which is understood by bpftrace, perf and systemtap.
+//
+// Every probe is a single `nop`, whose address is recorded in a `.note.stapsdt` ELF note
+// together with the location of its arguments. Tracers replace the `nop` with a breakpoint
+// once they attach to the probe, so the only cost while detached is the `nop` itself and
+// moving the arguments into registers.
+//
+// The probes can be listed using `readelf -n` or `bpftrace -l 'usdt:<binary>:*'`:
+//   context:switch  arg0 = context being resumed, arg1 = data passed to it
+//   stack:alloc     arg0 = bottom of the stack,   arg1 = top of the stack
+//   stack:free      arg0 = bottom of the stack,   arg1 = top of the stack
+
+#[cfg(all(feature = "usdt", target_os = "linux", target_arch = "x86_64"))]
+macro_rules! probe {
+    ($provider:ident, $name:ident, $arg0:expr, $arg1:expr) => {
+        unsafe {
+            ::std::arch::asm!(
+                probe_template!($provider, $name),
+                in(reg) $arg0 as usize,
+                in(reg) $arg1 as usize,
+                options(att_syntax, nomem, nostack, preserves_flags),
+            )
+        }
+    };
+}
+
+#[cfg(all(feature = "usdt", target_os = "linux", target_arch = "aarch64"))]
+macro_rules! probe {
+    ($provider:ident, $name:ident, $arg0:expr, $arg1:expr) => {
+        unsafe {
+            ::std::arch::asm!(
+                probe_template!($provider, $name),
+                in(reg) $arg0 as usize,
+                in(reg) $arg1 as usize,
+                options(nomem, nostack, preserves_flags),
+            )
+        }
+    };
+}
+
+#[cfg(all(feature = "usdt", target_os = "linux",
+          any(target_arch = "x86_64", target_arch = "aarch64")))]
+macro_rules! probe_template {
+    ($provider:ident, $name:ident) => {
+        concat!(
+            "990: nop\n",
+            ".pushsection .note.stapsdt, \"?\", \"note\"\n",
+            ".balign 4\n",
+            ".4byte 992f-991f, 994f-993f, 3\n",
+            "991: .asciz \"stapsdt\"\n",
+            "992: .balign 4\n",
+            "993: .8byte 990b\n",
+            ".8byte _.stapsdt.base\n",
+            ".8byte 0\n",
+            ".asciz \"", stringify!($provider), "\"\n",
+            ".asciz \"", stringify!($name), "\"\n",
+            ".asciz \"8@{0} 8@{1}\"\n",
+            "994: .balign 4\n",
+            ".popsection\n",
+            ".ifndef _.stapsdt.base\n",
+            ".pushsection .stapsdt.base, \"aG\", \"progbits\", .stapsdt.base, comdat\n",
+            ".weak _.stapsdt.base\n",
+            ".hidden _.stapsdt.base\n",
+            "_.stapsdt.base: .space 1\n",
+            ".size _.stapsdt.base, 1\n",
+            ".popsection\n",
+            ".endif\n",
+        )
+    };
+}
+
+#[cfg(not(all(feature = "usdt", target_os = "linux",
+              any(target_arch = "x86_64", target_arch = "aarch64"))))]
+macro_rules! probe {
+    ($provider:ident, $name:ident, $arg0:expr, $arg1:expr) => {{
+        let _ = ($arg0, $arg1);
+    }};
+}
@@ -0,0 +1,223 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::mem;
+
+use stack::Stack;
+
+/// The register block `jump_fcontext()` saves on the stack of a suspended `Context`
+/// for x86_64 System V.
+///
+/// Resuming a `Context` made from a `Record` restores the callee-saved registers from it,
+/// points `rsp` right past it and jumps to `rip`. `rdi` and `rax` then contain the suspended
+/// `Context` of the resumer, `rsi` and `rdx` the data passed to `resume()`, so that the code at
+/// `rip` receives them as a `Transfer` if it is the entry point of a `ContextFn`.
+#[cfg(all(target_arch = "x86_64", not(windows)))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The MXCSR control and status register.
+    pub mxcsr: u32,
+    /// The x87 FPU control word, in the lower 16 bits.
+    pub x87_cw: u32,
+    /// The `r12` register.
+    pub r12: u64,
+    /// The `r13` register.
+    pub r13: u64,
+    /// The `r14` register.
+    pub r14: u64,
+    /// The `r15` register.
+    pub r15: u64,
+    /// The `rbx` register.
+    pub rbx: u64,
+    /// The `rbp` register.
+    pub rbp: u64,
+    /// The address execution continues at.
+    pub rip: u64,
+}
+
+#[cfg(all(target_arch = "x86_64", not(windows)))]
+impl Record {
+    /// Creates a `Record` continuing at `rip` with the default floating point environment
+    /// and all other registers zeroed.
+    pub fn new(rip: usize) -> Record {
+        Record {
+            mxcsr: 0x1f80,
+            x87_cw: 0x037f,
+            r12: 0,
+            r13: 0,
+            r14: 0,
+            r15: 0,
+            rbx: 0,
+            rbp: 0,
+            rip: rip as u64,
+        }
+    }
+
+    /// Returns the address execution continues at.
+    #[inline]
+    pub fn pc(&self) -> usize {
+        self.rip as usize
+    }
+}
+
+/// The register block `jump_fcontext()` saves on the stack of a suspended `Context`
+/// for AArch64 AAPCS.
+///
+/// Resuming a `Context` made from a `Record` restores the callee-saved registers from it,
+/// points `sp` right past it and branches to `pc`. `x0` then contains the suspended `Context`
+/// of the resumer and `x1` the data passed to `resume()`, so that the code at `pc` receives
+/// them as a `Transfer` if it is the entry point of a `ContextFn`.
+#[cfg(all(target_arch = "aarch64", not(windows)))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The lower halves of the `v8` to `v15` registers, i.e. `d8` to `d15`.
+    pub d: [u64; 8],
+    /// The `x19` to `x28` registers.
+    pub x: [u64; 10],
+    /// The frame pointer `x29`.
+    pub fp: u64,
+    /// The link register `x30`.
+    pub lr: u64,
+    /// The address execution continues at.
+    pub pc: u64,
+    /// Padding keeping the stack pointer aligned to 16 bytes.
+    pub padding: u64,
+}
+
+#[cfg(all(target_arch = "aarch64", not(windows)))]
+impl Record {
+    /// Creates a `Record` continuing at `pc` with all other registers zeroed.
+    pub fn new(pc: usize) -> Record {
+        Record {
+            d: [0; 8],
+            x: [0; 10],
+            fp: 0,
+            lr: 0,
+            pc: pc as u64,
+            padding: 0,
+        }
+    }
+
+    /// Returns the address execution continues at.
+    #[inline]
+    pub fn pc(&self) -> usize {
+        self.pc as usize
+    }
+}
+
+// The misalignment of the stack pointer on entry of a function, i.e. after a call pushed the
+// return address, which the stack pointer right past a `Record` has to have for it to enter one.
+#[cfg(target_arch = "x86_64")]
+const ENTRY_MISALIGNMENT: usize = 8;
+#[cfg(target_arch = "aarch64")]
+const ENTRY_MISALIGNMENT: usize = 0;
+
+/// Error type returned by `check()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordError {
+    /// The `Record` isn't aligned to `mem::align_of::<Record>()`.
+    Misaligned,
+
+    /// The `Record` doesn't lie completely between `bottom()` and `top()` of the stack.
+    OutOfBounds,
+}
+
+impl Display for RecordError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            RecordError::Misaligned => write!(fmt, "Register record is misaligned"),
+            RecordError::OutOfBounds => write!(fmt, "Register record lies outside of its stack"),
+        }
+    }
+}
+
+impl Error for RecordError {}
+
+/// Checks whether `record` may be turned into a `Context` running on `stack`
+/// using `Context::from_record()`.
+///
+/// Only the placement of the record is checked. Whether its registers describe a state
+/// which is safe to continue at can't be verified and remains the caller's responsibility.
+pub fn check(stack: &Stack, record: *const Record) -> Result<(), RecordError> {
+    let addr = record as usize;
+
+    if !addr.is_multiple_of(mem::align_of::<Record>()) {
+        return Err(RecordError::Misaligned);
+    }
+
+    match addr.checked_add(mem::size_of::<Record>()) {
+        Some(end) if addr >= stack.bottom() as usize && end <= stack.top() as usize => Ok(()),
+        _ => Err(RecordError::OutOfBounds),
+    }
+}
+
+/// Returns the highest address at the start of `stack` a `Record` may be written to,
+/// such that its program counter is entered with the stack pointer aligned as a function
+/// entry point expects it.
+///
+/// This is what `Context::new()` does for the initial frame of a `ContextFn`.
+/// Any stack the continuation needs beyond that lies below the returned address.
+pub fn entry_position(stack: &Stack) -> *mut Record {
+    let sp = (stack.start() as usize & !15) - ENTRY_MISALIGNMENT;
+    (sp - mem::size_of::<Record>()) as *mut Record
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::ptr::{self, NonNull};
+
+    use context::{Context, Transfer};
+    #[cfg(feature = "debug-switch")]
+    use debug;
+    use stack::ProtectedFixedSizeStack;
+    use super::*;
+
+    #[test]
+    fn layout() {
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(mem::size_of::<Record>(), 0x40);
+        #[cfg(target_arch = "aarch64")]
+        assert_eq!(mem::size_of::<Record>(), 0xb0);
+    }
+
+    #[test]
+    fn check_placement() {
+        let stack = ProtectedFixedSizeStack::default();
+        let top = stack.top() as usize;
+
+        assert_eq!(check(&stack, entry_position(&stack)), Ok(()));
+        assert_eq!(check(&stack, (top - 0x101) as *const Record), Err(RecordError::Misaligned));
+        assert_eq!(check(&stack, (top - 8) as *const Record), Err(RecordError::OutOfBounds));
+        assert_eq!(check(&stack, (stack.bottom() as usize - 0x100) as *const Record),
+                   Err(RecordError::OutOfBounds));
+    }
+
+    #[test]
+    fn from_record() {
+        extern "C" fn context_function(t: Transfer) -> ! {
+            unsafe { t.context.finish(t.data * 2) }
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let record = entry_position(&stack);
+        assert_eq!(check(&stack, record), Ok(()));
+        #[cfg(feature = "debug-switch")]
+        debug::register_stack(&stack);
+
+        unsafe {
+            ptr::write(record, Record::new(context_function as *const () as usize));
+            let ctx = Context::from_record(NonNull::new(record).unwrap());
+            assert_eq!(ctx.as_ptr() as usize, record as usize);
+            assert_eq!(ctx.resume(21).data, 42);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A smoke test of the assembly selected by build.rs, run once before the first Context is
+// created. An assembly variant for the wrong ABI usually doesn't crash right away, but corrupts
+// registers or misaligns the stack, which only shows up much later deep in user code.
+
+#[cfg(any(all(target_arch = "x86_64", not(windows)),
+          all(target_arch = "aarch64", not(windows))))]
+use std::arch::asm;
+use std::hint;
+#[cfg(any(all(target_arch = "x86_64", not(windows)),
+          all(target_arch = "aarch64", not(windows))))]
+use std::ptr::NonNull;
+use std::sync::Once;
+
+use context::{self, Context, Transfer};
+#[cfg(any(all(target_arch = "x86_64", not(windows)),
+          all(target_arch = "aarch64", not(windows))))]
+use context::jump_fcontext;
+#[cfg(any(all(target_arch = "x86_64", not(windows)),
+          all(target_arch = "aarch64", not(windows))))]
+use record::Record;
+use stack::{ProtectedFixedSizeStack, Stack};
+
+const PATTERN: usize = usize::MAX / 0xff * 0xa5;
+
+// The failures detected within the context, passed back as the data of a switch.
+const ERR_DATA: usize = 1;
+const ERR_ALIGNMENT: usize = 2;
+const ERR_REGISTERS: usize = 3;
+
+#[repr(align(16))]
+struct Aligned {
+    _byte: u8,
+}
+
+// The number of callee-saved general purpose registers pinned by resume_pinned():
+// rbx and r12-r15 on x86_64, x19-x28 on AArch64.
+#[cfg(all(target_arch = "x86_64", not(windows)))]
+const PINNED: usize = 5;
+#[cfg(all(target_arch = "aarch64", not(windows)))]
+const PINNED: usize = 10;
+// Other targets only check the data, the alignment and the stack of the switch.
+#[cfg(not(any(all(target_arch = "x86_64", not(windows)),
+              all(target_arch = "aarch64", not(windows)))))]
+const PINNED: usize = 0;
+
+fn cookies(seed: usize) -> [usize; PINNED] {
+    let mut cookies = [0; PINNED];
+    for (i, cookie) in cookies.iter_mut().enumerate() {
+        *cookie = seed.rotate_left(i as u32 * 5) ^ i;
+    }
+    cookies
+}
+
+// Resumes `context` with `cookies` loaded into the callee-saved registers right before calling
+// jump_fcontext and returns the values these registers hold right after it returned, which
+// checks the registers themselves instead of values the compiler may keep anywhere.
+#[cfg(all(target_arch = "x86_64", not(windows)))]
+unsafe fn resume_pinned(context: Context,
+                        data: usize,
+                        cookies: [usize; PINNED])
+                        -> (Transfer, [usize; PINNED]) {
+    let [rbx, mut r12, mut r13, mut r14, mut r15] = cookies;
+    let (fctx, received, rbx_after): (usize, usize, usize);
+
+    // rbx is reserved by LLVM and rbp may be the frame pointer, so both are saved manually.
+    // rbp holds the unaligned stack pointer, since the call requires a 16 byte aligned one.
+    asm!("push rbp",
+         "mov rbp, rsp",
+         "and rsp, -16",
+         "push rbx",
+         "sub rsp, 8",
+         "mov rbx, {rbx}",
+         "call {jump}",
+         "mov rcx, rbx",
+         "add rsp, 8",
+         "pop rbx",
+         "mov rsp, rbp",
+         "pop rbp",
+         rbx = in(reg) rbx,
+         jump = in(reg) jump_fcontext as *const (),
+         in("rdi") context.as_ptr(),
+         in("rsi") data,
+         inout("r12") r12,
+         inout("r13") r13,
+         inout("r14") r14,
+         inout("r15") r15,
+         lateout("rax") fctx,
+         lateout("rdx") received,
+         lateout("rcx") rbx_after,
+         clobber_abi("C"));
+
+    let context = Context::from_record(NonNull::new_unchecked(fctx as *mut Record));
+    (Transfer::new(context, received), [rbx_after, r12, r13, r14, r15])
+}
+
+#[cfg(all(target_arch = "aarch64", not(windows)))]
+unsafe fn resume_pinned(context: Context,
+                        data: usize,
+                        cookies: [usize; PINNED])
+                        -> (Transfer, [usize; PINNED]) {
+    let [x19, mut x20, mut x21, mut x22, mut x23, mut x24, mut x25, mut x26, mut x27, mut x28] =
+        cookies;
+    let mut fctx = context.as_ptr() as usize;
+    let mut data = data;
+    let x19_after: usize;
+
+    // x19 is reserved by LLVM and is thus saved manually.
+    asm!("str x19, [sp, #-16]!",
+         "mov x19, {x19}",
+         "blr {jump}",
+         "mov x9, x19",
+         "ldr x19, [sp], #16",
+         x19 = in(reg) x19,
+         jump = in(reg) jump_fcontext as *const (),
+         inout("x0") fctx,
+         inout("x1") data,
+         inout("x20") x20,
+         inout("x21") x21,
+         inout("x22") x22,
+         inout("x23") x23,
+         inout("x24") x24,
+         inout("x25") x25,
+         inout("x26") x26,
+         inout("x27") x27,
+         inout("x28") x28,
+         lateout("x9") x19_after,
+         clobber_abi("C"));
+
+    let context = Context::from_record(NonNull::new_unchecked(fctx as *mut Record));
+    (Transfer::new(context, data), [x19_after, x20, x21, x22, x23, x24, x25, x26, x27, x28])
+}
+
+#[cfg(not(any(all(target_arch = "x86_64", not(windows)),
+              all(target_arch = "aarch64", not(windows)))))]
+unsafe fn resume_pinned(context: Context,
+                        data: usize,
+                        cookies: [usize; PINNED])
+                        -> (Transfer, [usize; PINNED]) {
+    (context.resume(data), cookies)
+}
+
+extern "C" fn context_function(t: Transfer) -> ! {
+    let local = Aligned { _byte: 0 };
+
+    if t.data != PATTERN {
+        unsafe { t.context.finish(ERR_DATA) }
+    }
+    if !(hint::black_box(&local) as *const Aligned as usize).is_multiple_of(16) {
+        unsafe { t.context.finish(ERR_ALIGNMENT) }
+    }
+
+    let (t, restored) = unsafe { resume_pinned(t.context, !PATTERN, cookies(PATTERN)) };
+    let code = if restored == cookies(PATTERN) { PATTERN } else { ERR_REGISTERS };
+    unsafe { t.context.finish(code) }
+}
+
+fn describe(code: usize) -> &'static str {
+    match code {
+        ERR_DATA => "the data passed to the context was corrupted",
+        ERR_ALIGNMENT => "the stack of the context is misaligned",
+        ERR_REGISTERS => "callee-saved registers of the context were not restored",
+        _ => "the data passed back from the context was corrupted",
+    }
+}
+
+// Runs the smoke test, returning a description of the first failure.
+fn run() -> Result<(), &'static str> {
+    // The switches back to the resumer may validate it first, which takes more than a page.
+    let stack = match ProtectedFixedSizeStack::new(Stack::practical_min_size()) {
+        Ok(stack) => stack,
+        // Without a stack nothing can be checked, but nothing can crash either.
+        Err(_) => return Ok(()),
+    };
+
+    let context = unsafe { Context::make(&stack, context_function) };
+    let (t, restored) = unsafe { resume_pinned(context, PATTERN, cookies(!PATTERN)) };
+    let ptr = t.context.as_ptr() as usize;
+
+    if t.data != !PATTERN {
+        return Err(describe(t.data));
+    }
+    if ptr < stack.bottom() as usize || ptr >= stack.top() as usize {
+        return Err("the context suspended outside of its stack");
+    }
+    if restored != cookies(!PATTERN) {
+        return Err("callee-saved registers of the resumer were not restored");
+    }
+
+    let t = unsafe { t.context.resume(0) };
+    if t.data != PATTERN {
+        return Err(describe(t.data));
+    }
+
+    Ok(())
+}
+
+// Runs the smoke test once per process and panics if it fails.
+#[inline]
+pub(crate) fn check() {
+    static CHECK: Once = Once::new();
+
+    CHECK.call_once(|| {
+        if let Err(msg) = run() {
+            panic!("ABI smoke check of the compiled assembly variant {} failed: {}. \
+                    build.rs most likely selected the wrong assembly for this target.",
+                   context::asm_variant(),
+                   msg);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn run() {
+        assert_eq!(super::run(), Ok(()));
+    }
+}
@@ -0,0 +1,1319 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#[cfg(feature = "nightly")]
+use std::alloc::{Allocator, Layout};
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io;
+use std::ops::Deref;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::NonNull;
+use std::thread;
+
+#[cfg(feature = "accounting")]
+use accounting;
+#[cfg(feature = "borrow-tracking")]
+use borrow::{self, BorrowToken};
+use context::{self, Context, Transfer};
+#[cfg(feature = "debug-switch")]
+use debug;
+use sys;
+
+/// Error type returned by stack allocation methods.
+///
+/// Besides the `kind()` of the failure it records the `stage()` of the allocation which failed
+/// and the sizes involved, as far as they are known. Use `is_retryable()` to decide whether
+/// retrying the allocation later may succeed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct StackError {
+    kind: StackErrorKind,
+    stage: AllocStage,
+    requested_size: Option<usize>,
+    rounded_size: Option<usize>,
+    source: Option<io::Error>,
+}
+
+/// The kind of a `StackError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StackErrorKind {
+    /// Contains the maximum amount of memory allowed to be allocated as stack space.
+    ExceedsMaximumSize(usize),
+
+    /// Returned if some kind of I/O error happens during allocation.
+    /// The error itself is available through `StackError::io_error()`.
+    Io,
+
+    /// Contains the process-wide limit of memory reserved for stacks which would be exceeded.
+    ///
+    /// This error is only returned if a limit has been configured using the `accounting` feature.
+    LimitReached(usize),
+
+    /// Contains the process-wide limit of live stacks which would be exceeded.
+    ///
+    /// This error is only returned if a limit has been configured using the `accounting` feature.
+    LiveLimitReached(usize),
+}
+
+/// The stage of a stack allocation a `StackError` occurred in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AllocStage {
+    /// Rounding the requested size up to whole pages and checking it against the maximum size.
+    Sizing,
+
+    /// Reserving the memory of the stack from the operating system or an allocator.
+    Mapping,
+
+    /// Protecting the guard page of the stack.
+    GuardPage,
+
+    /// Reserving the memory of the stack against the limit of the `accounting` feature.
+    Accounting,
+}
+
+impl StackError {
+    fn new(kind: StackErrorKind, stage: AllocStage) -> StackError {
+        StackError {
+            kind,
+            stage,
+            requested_size: None,
+            rounded_size: None,
+            source: None,
+        }
+    }
+
+    fn io(err: io::Error, stage: AllocStage) -> StackError {
+        StackError { source: Some(err), ..StackError::new(StackErrorKind::Io, stage) }
+    }
+
+    fn with_sizes(mut self, requested: usize, rounded: Option<usize>) -> StackError {
+        self.requested_size = Some(requested);
+        self.rounded_size = rounded;
+        self
+    }
+
+    /// Returns the kind of the failure.
+    pub fn kind(&self) -> StackErrorKind {
+        self.kind
+    }
+
+    /// Returns the stage of the allocation which failed.
+    pub fn stage(&self) -> AllocStage {
+        self.stage
+    }
+
+    /// Returns the size the caller requested, or `None` if the error has been converted from a
+    /// `RawStackError`, which doesn't record it.
+    pub fn requested_size(&self) -> Option<usize> {
+        self.requested_size
+    }
+
+    /// Returns the size including the guard page the requested size was rounded up to,
+    /// or `None` if the allocation failed before, or the size isn't recorded.
+    pub fn rounded_size(&self) -> Option<usize> {
+        self.rounded_size
+    }
+
+    /// Returns the I/O error if the kind of the failure is `StackErrorKind::Io`.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        self.source.as_ref()
+    }
+
+    /// Returns whether retrying the allocation later may succeed, without changing its size.
+    ///
+    /// This is the case if memory was exhausted temporarily or an `accounting` limit was reached,
+    /// which other stacks may be released in the meantime for, but not if the requested size is
+    /// invalid or exceeds the maximum.
+    pub fn is_retryable(&self) -> bool {
+        match self.kind {
+            StackErrorKind::ExceedsMaximumSize(_) => false,
+            StackErrorKind::LimitReached(_) | StackErrorKind::LiveLimitReached(_) => true,
+            StackErrorKind::Io => {
+                matches!(self.source.as_ref().map(io::Error::kind),
+                         Some(io::ErrorKind::OutOfMemory) |
+                         Some(io::ErrorKind::WouldBlock) |
+                         Some(io::ErrorKind::Interrupted))
+            },
+        }
+    }
+}
+
+impl Display for StackError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self.kind {
+            StackErrorKind::ExceedsMaximumSize(size) => {
+                write!(fmt, "Requested more than max size of {} bytes for a stack", size)
+            },
+            StackErrorKind::Io => {
+                match self.source {
+                    Some(ref e) => e.fmt(fmt),
+                    None => write!(fmt, "I/O error while allocating a stack"),
+                }
+            },
+            StackErrorKind::LimitReached(limit) => {
+                write!(fmt, "Reached the limit of {} bytes reserved for stacks", limit)
+            },
+            StackErrorKind::LiveLimitReached(limit) => {
+                write!(fmt, "Reached the limit of {} live stacks", limit)
+            },
+        }
+    }
+}
+
+impl Error for StackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+impl From<RawStackError> for StackError {
+    fn from(err: RawStackError) -> StackError {
+        match err {
+            RawStackError::ExceedsMaximumSize(size) => {
+                StackError::new(StackErrorKind::ExceedsMaximumSize(size), AllocStage::Sizing)
+            },
+            RawStackError::Os(code) => {
+                StackError::io(io::Error::from_raw_os_error(code), AllocStage::Mapping)
+            },
+            RawStackError::LimitReached(limit) => {
+                StackError::new(StackErrorKind::LimitReached(limit), AllocStage::Accounting)
+            },
+            RawStackError::LiveLimitReached(limit) => {
+                StackError::new(StackErrorKind::LiveLimitReached(limit), AllocStage::Accounting)
+            },
+        }
+    }
+}
+
+// A failed allocation, along with the context `StackError` records but `RawStackError` doesn't.
+#[derive(Clone, Copy, Debug)]
+struct AllocFailure {
+    raw: RawStackError,
+    stage: AllocStage,
+    // The sizes of the attempt which failed, which differ from the original request once
+    // GuardFailurePolicy::Shrink took effect.
+    requested_size: usize,
+    rounded_size: Option<usize>,
+}
+
+impl AllocFailure {
+    fn new(raw: RawStackError,
+           stage: AllocStage,
+           requested_size: usize,
+           rounded_size: Option<usize>)
+           -> AllocFailure {
+        AllocFailure {
+            raw,
+            stage,
+            requested_size,
+            rounded_size,
+        }
+    }
+
+    fn into_error(self) -> StackError {
+        let mut err = StackError::from(self.raw).with_sizes(self.requested_size, self.rounded_size);
+        err.stage = self.stage;
+        err
+    }
+}
+
+/// Error type returned by the allocation-free stack constructors,
+/// like `ProtectedFixedSizeStack::try_new_nonalloc()`.
+///
+/// Contrary to `StackError` it's a plain value which never allocates,
+/// which makes it suitable for real-time threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawStackError {
+    /// Contains the maximum amount of memory allowed to be allocated as stack space.
+    ExceedsMaximumSize(usize),
+
+    /// Contains the raw error code of the operating system, i.e. `errno` or `GetLastError()`.
+    Os(i32),
+
+    /// Contains the process-wide limit of memory reserved for stacks which would be exceeded.
+    ///
+    /// This error is only returned if a limit has been configured using the `accounting` feature.
+    LimitReached(usize),
+
+    /// Contains the process-wide limit of live stacks which would be exceeded.
+    ///
+    /// This error is only returned if a limit has been configured using the `accounting` feature.
+    LiveLimitReached(usize),
+}
+
+impl Display for RawStackError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match *self {
+            RawStackError::Os(code) => write!(fmt, "OS error {} while allocating a stack", code),
+            err => StackError::from(err).fmt(fmt),
+        }
+    }
+}
+
+impl Error for RawStackError {}
+
+// Extracts the error code from the errors returned by sys, which are all created from it.
+fn raw_os_error(err: io::Error) -> RawStackError {
+    RawStackError::Os(err.raw_os_error().unwrap_or(0))
+}
+
+/// Controls whether the memory of a stack should be backed by (transparent) huge pages.
+///
+/// Huge pages can noticeably reduce TLB misses for large stacks, but are only ever requested
+/// as an advice to the operating system. If the platform does not support them the stack
+/// is silently allocated using regular pages instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HugePagePolicy {
+    /// Never request huge pages. This is the default.
+    #[default]
+    Never,
+
+    /// Request huge pages for every stack, regardless of its size.
+    Always,
+
+    /// Request huge pages only for stacks of at least the contained amount of bytes.
+    AtLeast(usize),
+}
+
+/// Additional options used when allocating stack memory.
+///
+/// # Examples
+///
+/// ```
+/// use context_core::stack::{AllocOptions, FixedSizeStack, HugePagePolicy};
+///
+/// let options = AllocOptions::new().huge_pages(HugePagePolicy::AtLeast(8 << 20));
+/// let stack = FixedSizeStack::with_options(8 << 20, options).unwrap();
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocOptions {
+    huge_pages: HugePagePolicy,
+}
+
+impl AllocOptions {
+    /// Returns the default options, which are used by `FixedSizeStack::new()` and
+    /// `ProtectedFixedSizeStack::new()` as well.
+    pub fn new() -> AllocOptions {
+        AllocOptions::default()
+    }
+
+    /// Sets the policy for requesting huge pages from the operating system.
+    pub fn huge_pages(mut self, policy: HugePagePolicy) -> AllocOptions {
+        self.huge_pages = policy;
+        self
+    }
+
+    fn wants_huge_pages(&self, size: usize) -> bool {
+        match self.huge_pages {
+            HugePagePolicy::Never => false,
+            HugePagePolicy::Always => true,
+            HugePagePolicy::AtLeast(min) => size >= min,
+        }
+    }
+}
+
+/// The direction a stack grows in as functions are called.
+///
+/// All architectures currently supported by Boost.Context grow their stack downwards.
+/// Code which needs to know where a `Context` starts executing or which end of a stack
+/// needs to be guarded should nevertheless use these methods instead of assuming downward growth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrowthDirection {
+    /// The stack grows from `Stack::top()` towards `Stack::bottom()`.
+    Downward,
+
+    /// The stack grows from `Stack::bottom()` towards `Stack::top()`, like on HP PA-RISC.
+    Upward,
+}
+
+impl GrowthDirection {
+    /// Returns the direction stacks grow in on the current architecture.
+    #[inline]
+    pub fn native() -> GrowthDirection {
+        GrowthDirection::Downward
+    }
+
+    /// Returns the end of `stack` a `Context` starts executing at.
+    #[inline]
+    pub fn start(self, stack: &Stack) -> *mut c_void {
+        match self {
+            GrowthDirection::Downward => stack.top(),
+            GrowthDirection::Upward => stack.bottom(),
+        }
+    }
+
+    /// Returns the end of `stack` it grows towards, which must never be crossed.
+    #[inline]
+    pub fn limit(self, stack: &Stack) -> *mut c_void {
+        match self {
+            GrowthDirection::Downward => stack.bottom(),
+            GrowthDirection::Upward => stack.top(),
+        }
+    }
+
+    // Splits `size` bytes off the limit of `mapping` to be used as its guard.
+    // Returns the guard and the remaining, usable stack.
+    pub(crate) fn split_guard(self, mapping: &Stack, size: usize) -> (Stack, Stack) {
+        debug_assert!(size <= mapping.len());
+
+        unsafe {
+            match self {
+                GrowthDirection::Downward => {
+                    let boundary = (mapping.bottom() as usize + size) as *mut c_void;
+                    (Stack::new(boundary, mapping.bottom()), Stack::new(mapping.top(), boundary))
+                }
+                GrowthDirection::Upward => {
+                    let boundary = (mapping.top() as usize - size) as *mut c_void;
+                    (Stack::new(mapping.top(), boundary), Stack::new(boundary, mapping.bottom()))
+                }
+            }
+        }
+    }
+
+    // The inverse of split_guard(): Returns the whole mapping of the `usable` stack
+    // whose guard is `size` bytes large.
+    pub(crate) fn join_guard(self, usable: &Stack, size: usize) -> Stack {
+        unsafe {
+            match self {
+                GrowthDirection::Downward => {
+                    Stack::new(usable.top(), (usable.bottom() as usize - size) as *mut c_void)
+                }
+                GrowthDirection::Upward => {
+                    Stack::new((usable.top() as usize + size) as *mut c_void, usable.bottom())
+                }
+            }
+        }
+    }
+}
+
+/// Represents any kind of stack memory.
+///
+/// `FixedSizeStack` as well as `ProtectedFixedSizeStack`
+/// can be used to allocate actual stack space.
+#[derive(Debug)]
+pub struct Stack {
+    top: *mut c_void,
+    bottom: *mut c_void,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl Stack {
+    /// Creates a (non-owning) representation of some stack memory.
+    ///
+    /// # Safety
+    ///
+    /// It is unsafe because it is your reponsibility to make sure that `top` and `buttom` are valid
+    /// addresses.
+    #[inline]
+    pub unsafe fn new(top: *mut c_void, bottom: *mut c_void) -> Stack {
+        debug_assert!(top >= bottom);
+
+        Stack { top, bottom }
+    }
+
+    /// Creates a (non-owning) representation of the memory `slice` points to.
+    ///
+    /// # Safety
+    ///
+    /// See `new()`.
+    #[inline]
+    pub unsafe fn from_non_null(slice: NonNull<[u8]>) -> Stack {
+        let bottom = slice.cast::<u8>().as_ptr();
+        Stack::new(bottom.add(slice.len()) as *mut c_void, bottom as *mut c_void)
+    }
+
+    /// Returns the memory between `bottom()` and `top()` as a slice pointer,
+    /// for instance to pass it to an `Allocator`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bottom()` is null.
+    #[inline]
+    pub fn as_non_null(&self) -> NonNull<[u8]> {
+        let bottom = NonNull::new(self.bottom as *mut u8).expect("stack located at null");
+        NonNull::slice_from_raw_parts(bottom, self.len())
+    }
+
+    /// Returns the top of the stack, which is its highest address.
+    #[inline]
+    pub fn top(&self) -> *mut c_void {
+        self.top
+    }
+
+    /// Returns the bottom of the stack, which is its lowest address.
+    #[inline]
+    pub fn bottom(&self) -> *mut c_void {
+        self.bottom
+    }
+
+    /// Returns the end of the stack a `Context` starts executing at.
+    ///
+    /// This is `top()` since stacks grow downwards on all supported architectures.
+    /// See `GrowthDirection` for more information.
+    #[inline]
+    pub fn start(&self) -> *mut c_void {
+        GrowthDirection::native().start(self)
+    }
+
+    /// Returns the end of the stack it grows towards.
+    ///
+    /// This is `bottom()` since stacks grow downwards on all supported architectures.
+    /// See `GrowthDirection` for more information.
+    #[inline]
+    pub fn limit(&self) -> *mut c_void {
+        GrowthDirection::native().limit(self)
+    }
+
+    /// Returns the size of the stack between top() and bottom().
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.top as usize - self.bottom as usize
+    }
+
+    /// Returns the number of bytes which remain usable by the `ContextFn` of a `Context`
+    /// created on this stack.
+    ///
+    /// This is `len()` minus `context::initial_frame_overhead()` and the padding
+    /// `Context::new()` inserts to align the start of the stack to `context::stack_alignment()`.
+    #[inline]
+    pub fn usable_len_for_context(&self) -> usize {
+        let padding = self.start() as usize % context::stack_alignment();
+        self.len().saturating_sub(context::initial_frame_overhead() + padding)
+    }
+
+    /// Returns the number of bytes of the pages overlapping the stack which are resident in
+    /// physical memory.
+    ///
+    /// Stacks are mapped lazily, so this estimates the memory they actually use, as opposed to
+    /// `len()`, which is the address space they reserve. Pages which have been touched once stay
+    /// resident until the stack is deallocated, so it's a high-water mark rather than the depth
+    /// of the stack at the time of the call. The estimate is obtained using `mincore()` on UNIX
+    /// and `QueryWorkingSetEx()` on Windows, and is racy by nature: The kernel may page memory
+    /// in or out at any time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Unsupported` on platforms without such an API, or the error of
+    /// the underlying call, for instance if the stack isn't mapped.
+    pub fn resident_size(&self) -> io::Result<usize> {
+        sys::resident_size(self)
+    }
+
+    /// Returns a token recording that a `Context` references this stack until it's dropped.
+    ///
+    /// See `BorrowToken` for more information. Requires the `borrow-tracking` feature.
+    #[cfg(feature = "borrow-tracking")]
+    pub fn borrow_token(&self) -> BorrowToken {
+        BorrowToken::new(self)
+    }
+
+    /// Returns the minimal stack size allowed by the current platform.
+    #[inline]
+    pub fn min_size() -> usize {
+        sys::min_stack_size()
+    }
+
+    /// Returns the minimal stack size which is actually usable on the current architecture.
+    ///
+    /// While `min_size()` is the smallest size the platform allows to allocate, it's usually
+    /// only a single memory page, which isn't necessarily enough to run even trivial functions
+    /// inside a `Context`. This size on the other hand is enough to run a trivial function and
+    /// to unwind its stack by panicking.
+    #[inline]
+    pub fn practical_min_size() -> usize {
+        sys::practical_min_stack_size()
+    }
+
+    /// Returns the maximum stack size allowed by the current platform.
+    ///
+    /// This doesn't depend on `RLIMIT_STACK` on UNIX, which only governs the stack of the
+    /// main thread, but is a fixed cap of 1 GiB.
+    #[inline]
+    pub fn max_size() -> usize {
+        sys::max_stack_size()
+    }
+
+    /// Returns a implementation defined default stack size.
+    ///
+    /// This value can vary greatly between platforms, but is usually only a couple
+    /// memory pages in size and enough for most use-cases with little recursion.
+    /// It's usually a better idea to specifiy an explicit stack size instead.
+    #[inline]
+    pub fn default_size() -> usize {
+        sys::default_stack_size()
+    }
+
+    /// Allocates a new stack of `size`.
+    fn allocate(size: usize, protected: bool, options: &AllocOptions) -> Result<Stack, StackError> {
+        Stack::allocate_guarded(size, protected, options, GuardFailurePolicy::Fail)
+            .map(|(stack, _)| stack)
+            .map_err(AllocFailure::into_error)
+    }
+
+    /// Allocates a new stack of `size` without allocating any heap memory, even on failure.
+    fn allocate_nonalloc(size: usize,
+                         protected: bool,
+                         options: &AllocOptions)
+                         -> Result<Stack, RawStackError> {
+        Stack::allocate_guarded(size, protected, options, GuardFailurePolicy::Fail)
+            .map(|(stack, _)| stack)
+            .map_err(|failure| failure.raw)
+    }
+
+    // Like allocate_nonalloc(), but applies `policy` if protecting the guard page fails.
+    // Returns whether the guard page ended up being protected along with the stack.
+    fn allocate_guarded(mut size: usize,
+                        protected: bool,
+                        options: &AllocOptions,
+                        policy: GuardFailurePolicy)
+                        -> Result<(Stack, bool), AllocFailure> {
+        let page_size = sys::page_size();
+        let add = page_size << if protected { 1 } else { 0 };
+
+        let (stack, rounded, guarded) = loop {
+            let rounded = Stack::round_size(size, add)
+                .map_err(|raw| AllocFailure::new(raw, AllocStage::Sizing, size, None))?;
+            let mut stack = unsafe { sys::allocate_stack(rounded) }.map_err(|err| {
+                AllocFailure::new(raw_os_error(err), AllocStage::Mapping, size, Some(rounded))
+            })?;
+
+            if options.wants_huge_pages(rounded) {
+                // Huge pages are merely an optimization and thus any failure is ignored.
+                let _ = unsafe { sys::advise_huge_pages(&stack) };
+            }
+
+            if !protected {
+                break (stack, rounded, false);
+            }
+
+            let err = match unsafe { sys::protect_stack(&stack) } {
+                Ok(usable) => break (usable, rounded, true),
+                Err(err) => err,
+            };
+
+            match policy {
+                GuardFailurePolicy::Unprotected(warn) => {
+                    warn(&err);
+                    stack = GrowthDirection::native().split_guard(&stack, page_size).1;
+                    break (stack, rounded, false);
+                },
+                GuardFailurePolicy::Shrink(min_size) => {
+                    unsafe { sys::deallocate_stack(stack.bottom(), stack.len()) };
+
+                    match Stack::shrunk_size(size, rounded, min_size, add) {
+                        Some(shrunk) => size = shrunk,
+                        None => {
+                            let raw = raw_os_error(err);
+                            let stage = AllocStage::GuardPage;
+                            return Err(AllocFailure::new(raw, stage, size, Some(rounded)));
+                        },
+                    }
+                },
+                GuardFailurePolicy::Fail => {
+                    unsafe { sys::deallocate_stack(stack.bottom(), stack.len()) };
+                    let raw = raw_os_error(err);
+                    let stage = AllocStage::GuardPage;
+                    return Err(AllocFailure::new(raw, stage, size, Some(rounded)));
+                },
+            }
+        };
+
+        #[cfg(not(feature = "accounting"))]
+        let _ = rounded;
+
+        #[cfg(feature = "accounting")]
+        {
+            if let Err(raw) = accounting::reserve(rounded) {
+                let guard_size = rounded - stack.len();
+                let mapping = GrowthDirection::native().join_guard(&stack, guard_size);
+                unsafe { sys::deallocate_stack(mapping.bottom(), mapping.len()) };
+                return Err(AllocFailure::new(raw, AllocStage::Accounting, size, Some(rounded)));
+            }
+        }
+
+        probe!(stack, alloc, stack.bottom(), stack.top());
+
+        Ok((stack, guarded))
+    }
+
+    // Returns the size to retry with after an attempt with `size`, rounded to `rounded`, failed
+    // under GuardFailurePolicy::Shrink(min_size), or `None` if halving wouldn't shrink the
+    // stack any further. Sizes below the minimum are rounded up to it again, so they'd never
+    // reduce the rounded size.
+    fn shrunk_size(size: usize, rounded: usize, min_size: usize, add: usize) -> Option<usize> {
+        let shrunk = size / 2;
+
+        if shrunk < min_size || shrunk < Stack::min_size() {
+            return None;
+        }
+
+        match Stack::round_size(shrunk, add) {
+            Ok(next) if next < rounded => Some(shrunk),
+            _ => None,
+        }
+    }
+
+    // Rounds `size` up to at least the minimum stack size and a multiple of the page size
+    // and adds `add` bytes, which must not exceed the maximum stack size.
+    fn round_size(mut size: usize, add: usize) -> Result<usize, RawStackError> {
+        let page_size = sys::page_size();
+        let min_stack_size = sys::min_stack_size();
+        let max_stack_size = sys::max_stack_size();
+
+        if size < min_stack_size {
+            size = min_stack_size;
+        }
+
+        size = (size - 1) & !(page_size - 1);
+
+        match size.checked_add(add) {
+            Some(size) if size <= max_stack_size => Ok(size),
+            _ => Err(RawStackError::ExceedsMaximumSize(max_stack_size - add)),
+        }
+    }
+}
+
+unsafe impl Send for Stack {}
+
+/// The stack sizes used on the current platform, as returned by `limits()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The size of a memory page, to a multiple of which all stack sizes are rounded.
+    pub page_size: usize,
+
+    /// See `Stack::min_size()`.
+    pub min_size: usize,
+
+    /// See `Stack::practical_min_size()`.
+    pub practical_min_size: usize,
+
+    /// See `Stack::default_size()`.
+    pub default_size: usize,
+
+    /// See `Stack::max_size()`.
+    pub max_size: usize,
+}
+
+/// Returns the stack sizes used on the current platform, for instance to log them at startup.
+pub fn limits() -> Limits {
+    Limits {
+        page_size: sys::page_size(),
+        min_size: Stack::min_size(),
+        practical_min_size: Stack::practical_min_size(),
+        default_size: Stack::default_size(),
+        max_size: Stack::max_size(),
+    }
+}
+
+/// Stacks which exclusively own the memory they dereference to.
+///
+/// Safe APIs which take ownership of a stack and run a context on it, like
+/// `compat::corosensei::Coroutine::with_stack()`, require this trait. Any other handle to the
+/// same memory, like a `&Stack` or a clone of an `Rc`, could be used to start a second context
+/// on it, whose frames would overwrite the ones of the first.
+///
+/// # Safety
+///
+/// Implementors must dereference to the same `Stack` for as long as they are alive, and no
+/// other value may hand out that memory, as a `Stack` or otherwise, until they are dropped.
+pub unsafe trait StackOwner: Deref<Target = Stack> {}
+
+unsafe impl StackOwner for FixedSizeStack {}
+
+unsafe impl StackOwner for ProtectedFixedSizeStack {}
+
+#[cfg(feature = "nightly")]
+unsafe impl<A: Allocator> StackOwner for AllocatorStack<A> {}
+
+/// A very simple and straightforward implementation of `Stack`.
+///
+/// Allocates stack space using virtual memory, whose pages will
+/// only be mapped to physical memory if they are used.
+///
+/// _As a general rule it is recommended to use `ProtectedFixedSizeStack` instead._
+#[derive(Debug)]
+pub struct FixedSizeStack(Stack);
+
+impl FixedSizeStack {
+    /// Allocates a new stack of **at least** `size` bytes.
+    ///
+    /// `size` is rounded up to a multiple of the size of a memory page.
+    pub fn new(size: usize) -> Result<FixedSizeStack, StackError> {
+        FixedSizeStack::with_options(size, AllocOptions::new())
+    }
+
+    /// Allocates a new stack of **at least** `size` bytes using the given `options`.
+    ///
+    /// `size` is rounded up to a multiple of the size of a memory page.
+    pub fn with_options(size: usize, options: AllocOptions) -> Result<FixedSizeStack, StackError> {
+        Stack::allocate(size, false, &options).map(FixedSizeStack)
+    }
+
+    /// Allocates a new stack of **at least** `size` bytes like `new()`,
+    /// but never allocates any heap memory, not even for the error.
+    pub fn try_new_nonalloc(size: usize) -> Result<FixedSizeStack, RawStackError> {
+        Stack::allocate_nonalloc(size, false, &AllocOptions::new()).map(FixedSizeStack)
+    }
+}
+
+impl Deref for FixedSizeStack {
+    type Target = Stack;
+
+    fn deref(&self) -> &Stack {
+        &self.0
+    }
+}
+
+impl Default for FixedSizeStack {
+    fn default() -> FixedSizeStack {
+        FixedSizeStack::new(Stack::default_size())
+            .unwrap_or_else(|err| panic!("Failed to allocate FixedSizeStack with {:?}", err))
+    }
+}
+
+impl Drop for FixedSizeStack {
+    fn drop(&mut self) {
+        #[cfg(feature = "borrow-tracking")]
+        borrow::check_released(&self.0);
+        #[cfg(feature = "debug-switch")]
+        debug::unregister_stack(&self.0);
+        probe!(stack, free, self.0.bottom(), self.0.top());
+
+        unsafe {
+            sys::deallocate_stack(self.0.bottom(), self.0.len());
+        }
+
+        #[cfg(feature = "accounting")]
+        accounting::release(self.0.len());
+    }
+}
+
+/// Controls how `ProtectedFixedSizeStack::new_with_policy()` reacts if the stack memory could be
+/// allocated, but protecting its guard page failed.
+///
+/// This mostly happens under memory pressure or if the process reached its limit of memory
+/// mappings, since protecting the guard page splits the mapping of the stack in two.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum GuardFailurePolicy {
+    /// Return the error, like `ProtectedFixedSizeStack::new()`. This is the default.
+    #[default]
+    Fail,
+
+    /// Return the stack with its guard page left accessible, after passing the error to the
+    /// contained callback, for instance to log a warning.
+    ///
+    /// `ProtectedFixedSizeStack::is_guard_protected()` returns `false` for such a stack.
+    Unprotected(fn(&io::Error)),
+
+    /// Retry with half the size each time, as long as that is at least the contained amount of
+    /// bytes and `Stack::min_size()` and still results in a smaller stack, and return the error
+    /// of the last attempt otherwise.
+    Shrink(usize),
+}
+
+/// A more secure, but slightly slower version of `FixedSizeStack`.
+///
+/// Allocates stack space using virtual memory, whose pages will
+/// only be mapped to physical memory if they are used.
+///
+/// The additional guard page is made protected and inaccessible.
+/// Now if a stack overflow occurs it should (hopefully) hit this guard page and
+/// cause a segmentation fault instead letting the memory being overwritten silently.
+///
+/// _As a general rule it is recommended to use **this** struct to create stack memory._
+#[derive(Debug)]
+pub struct ProtectedFixedSizeStack {
+    stack: Stack,
+    protected: bool,
+}
+
+impl ProtectedFixedSizeStack {
+    /// Allocates a new stack of **at least** `size` bytes + one additional guard page.
+    ///
+    /// `size` is rounded up to a multiple of the size of a memory page and
+    /// does not include the size of the guard page itself.
+    pub fn new(size: usize) -> Result<ProtectedFixedSizeStack, StackError> {
+        ProtectedFixedSizeStack::with_options(size, AllocOptions::new())
+    }
+
+    /// Allocates a new stack of **at least** `size` bytes + one additional guard page
+    /// using the given `options`.
+    ///
+    /// `size` is rounded up to a multiple of the size of a memory page and
+    /// does not include the size of the guard page itself.
+    pub fn with_options(size: usize,
+                        options: AllocOptions)
+                        -> Result<ProtectedFixedSizeStack, StackError> {
+        Stack::allocate(size, true, &options).map(ProtectedFixedSizeStack::from_usable)
+    }
+
+    /// Allocates a new stack of **at least** `size` bytes + one additional guard page like
+    /// `new()`, but never allocates any heap memory, not even for the error.
+    pub fn try_new_nonalloc(size: usize) -> Result<ProtectedFixedSizeStack, RawStackError> {
+        Stack::allocate_nonalloc(size, true, &AllocOptions::new())
+            .map(ProtectedFixedSizeStack::from_usable)
+    }
+
+    /// Allocates a new stack of **at least** `size` bytes + one additional guard page like
+    /// `new()`, but applies `policy` if the guard page can't be protected.
+    ///
+    /// This allows long-running servers to degrade predictably under memory pressure,
+    /// instead of failing to spawn new contexts outright. Check `len()` and
+    /// `is_guard_protected()` to find out which degradation, if any, took place.
+    pub fn new_with_policy(size: usize,
+                           policy: GuardFailurePolicy)
+                           -> Result<ProtectedFixedSizeStack, StackError> {
+        let (stack, protected) = Stack::allocate_guarded(size, true, &AllocOptions::new(), policy)
+            .map_err(AllocFailure::into_error)?;
+        Ok(ProtectedFixedSizeStack { stack, protected })
+    }
+
+    fn from_usable(stack: Stack) -> ProtectedFixedSizeStack {
+        ProtectedFixedSizeStack {
+            stack,
+            protected: true,
+        }
+    }
+
+    /// Returns the guard page, which lies right beyond the limit of the stack.
+    pub fn guard_range(&self) -> Stack {
+        let page_size = sys::page_size();
+        let mapping = GrowthDirection::native().join_guard(&self.stack, page_size);
+        GrowthDirection::native().split_guard(&mapping, page_size).0
+    }
+
+    /// Returns whether the guard page is protected, which it is unless `unprotect_guard()`
+    /// has been called.
+    ///
+    /// On Windows the guard page is unprotected by the system once it's accessed the first time,
+    /// which is not reflected by this method.
+    pub fn is_guard_protected(&self) -> bool {
+        self.protected
+    }
+
+    /// Makes the guard page accessible like the rest of the stack.
+    ///
+    /// This allows using the guard page temporarily, for instance as a red zone. A stack overflow
+    /// silently overwrites the memory beyond the guard page while it's unprotected though.
+    pub fn unprotect_guard(&mut self) -> io::Result<()> {
+        unsafe { sys::set_guard_protection(&self.guard_range(), false)? };
+        self.protected = false;
+        Ok(())
+    }
+
+    /// Protects the guard page again after a call to `unprotect_guard()`.
+    pub fn reprotect_guard(&mut self) -> io::Result<()> {
+        unsafe { sys::set_guard_protection(&self.guard_range(), true)? };
+        self.protected = true;
+        Ok(())
+    }
+}
+
+impl Deref for ProtectedFixedSizeStack {
+    type Target = Stack;
+
+    fn deref(&self) -> &Stack {
+        &self.stack
+    }
+}
+
+impl Default for ProtectedFixedSizeStack {
+    fn default() -> ProtectedFixedSizeStack {
+        ProtectedFixedSizeStack::new(Stack::default_size()).unwrap_or_else(|err| {
+            panic!("Failed to allocate ProtectedFixedSizeStack with {:?}", err)
+        })
+    }
+}
+
+impl Drop for ProtectedFixedSizeStack {
+    fn drop(&mut self) {
+        #[cfg(feature = "borrow-tracking")]
+        borrow::check_released(&self.stack);
+        #[cfg(feature = "debug-switch")]
+        debug::unregister_stack(&self.stack);
+        probe!(stack, free, self.stack.bottom(), self.stack.top());
+
+        let page_size = sys::page_size();
+        let mapping = GrowthDirection::native().join_guard(&self.stack, page_size);
+        unsafe {
+            sys::deallocate_stack(mapping.bottom(), mapping.len());
+        }
+
+        #[cfg(feature = "accounting")]
+        accounting::release(mapping.len());
+    }
+}
+
+/// A stack allocated using an `Allocator` instead of the operating system.
+///
+/// This allows backing stacks with arenas or the allocators of sanitizers, for instance.
+/// The size is rounded up like for `FixedSizeStack` and the memory is aligned to a page,
+/// but no guard page is set up, since the memory is owned by the allocator.
+///
+/// Requires the `nightly` feature.
+///
+/// # Examples
+///
+/// ```
+/// #![feature(allocator_api)]
+///
+/// use std::alloc::Global;
+/// use context_core::stack::AllocatorStack;
+///
+/// let stack = AllocatorStack::new_in(64 * 1024, Global).unwrap();
+/// assert!(stack.len() >= 64 * 1024);
+/// ```
+#[cfg(feature = "nightly")]
+#[derive(Debug)]
+pub struct AllocatorStack<A: Allocator> {
+    stack: Stack,
+    alloc: A,
+}
+
+#[cfg(feature = "nightly")]
+impl<A: Allocator> AllocatorStack<A> {
+    /// Allocates a new stack of **at least** `size` bytes from `alloc`.
+    ///
+    /// `size` is rounded up to a multiple of the size of a memory page.
+    pub fn new_in(size: usize, alloc: A) -> Result<AllocatorStack<A>, StackError> {
+        let rounded = Stack::round_size(size, sys::page_size())
+            .map_err(|raw| StackError::from(raw).with_sizes(size, None))?;
+        let failed = |kind: io::ErrorKind, stage| {
+            StackError::io(kind.into(), stage).with_sizes(size, Some(rounded))
+        };
+        let layout = Layout::from_size_align(rounded, sys::page_size())
+            .map_err(|_| failed(io::ErrorKind::InvalidInput, AllocStage::Sizing))?;
+        let memory = alloc.allocate(layout)
+            .map_err(|_| failed(io::ErrorKind::OutOfMemory, AllocStage::Mapping))?;
+        let stack = unsafe { Stack::from_non_null(memory) };
+
+        #[cfg(feature = "accounting")]
+        {
+            if let Err(raw) = accounting::reserve(stack.len()) {
+                unsafe { alloc.deallocate(memory.cast(), layout) };
+                return Err(StackError::from(raw).with_sizes(size, Some(rounded)));
+            }
+        }
+
+        probe!(stack, alloc, stack.bottom(), stack.top());
+
+        Ok(AllocatorStack { stack, alloc })
+    }
+
+    /// Returns the `Allocator` the stack has been allocated from.
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<A: Allocator> Deref for AllocatorStack<A> {
+    type Target = Stack;
+
+    fn deref(&self) -> &Stack {
+        &self.stack
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<A: Allocator> Drop for AllocatorStack<A> {
+    fn drop(&mut self) {
+        #[cfg(feature = "borrow-tracking")]
+        borrow::check_released(&self.stack);
+        #[cfg(feature = "debug-switch")]
+        debug::unregister_stack(&self.stack);
+        probe!(stack, free, self.stack.bottom(), self.stack.top());
+
+        // The layout has already been validated by new_in().
+        let size = self.stack.len();
+        let layout = unsafe { Layout::from_size_align_unchecked(size, sys::page_size()) };
+        unsafe {
+            self.alloc.deallocate(self.stack.as_non_null().cast(), layout);
+        }
+
+        #[cfg(feature = "accounting")]
+        accounting::release(self.stack.len());
+    }
+}
+
+/// Executes `f` on a newly allocated stack of **at least** `extra_size` bytes
+/// and returns its result once it finished.
+///
+/// This is an escape hatch for contexts which are about to overflow their own stack,
+/// for instance right before a deep recursion. The current stack is linked to a new
+/// `ProtectedFixedSizeStack`, which is deallocated again as soon as `f` returns.
+///
+/// Panics inside `f` are caught on the new stack and resumed on the current one,
+/// and thus unwind through the caller as if `f` had been called directly.
+/// Backtraces captured inside `f` end at the beginning of the new stack however.
+pub fn grow_linked<F, R>(extra_size: usize, f: F) -> Result<R, StackError>
+    where F: FnOnce() -> R
+{
+    struct Linked<F, R> {
+        f: Option<F>,
+        result: Option<thread::Result<R>>,
+    }
+
+    extern "C" fn linked_function<F, R>(t: Transfer) -> !
+        where F: FnOnce() -> R
+    {
+        {
+            let linked = unsafe { &mut *(t.data as *mut Linked<F, R>) };
+            let f = linked.f.take().unwrap();
+            linked.result = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+        }
+
+        // Nothing is left on this stack which would need to be dropped.
+        unsafe { t.context.finish(0) }
+    }
+
+    let stack = ProtectedFixedSizeStack::new(extra_size)?;
+    let mut linked = Linked {
+        f: Some(f),
+        result: None,
+    };
+
+    unsafe {
+        let context = Context::new(&stack, linked_function::<F, R>);
+        let _ = context.resume(&mut linked as *mut Linked<F, R> as usize);
+    }
+
+    match linked.result.take().unwrap() {
+        Ok(result) => Ok(result),
+        Err(err) => panic::resume_unwind(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(panic = "unwind")]
+    use std::panic;
+    use std::ptr::write_bytes;
+
+    use super::*;
+    use sys;
+
+    #[test]
+    fn resident_size() {
+        let stack = ProtectedFixedSizeStack::new(64 * 1024).unwrap();
+        let touched = 16 * 1024;
+
+        unsafe { write_bytes((stack.top() as usize - touched) as *mut u8, 0x1d, touched) };
+
+        let resident = stack.resident_size().unwrap();
+        assert!(resident >= touched, "{} < {}", resident, touched);
+        assert!(resident <= stack.len());
+    }
+
+    #[test]
+    fn growth_direction() {
+        let mut memory = [0u8; 64];
+        let bottom = memory.as_mut_ptr() as *mut c_void;
+        let top = (bottom as usize + memory.len()) as *mut c_void;
+        let mapping = unsafe { Stack::new(top, bottom) };
+
+        for &direction in &[GrowthDirection::Downward, GrowthDirection::Upward] {
+            let (guard, usable) = direction.split_guard(&mapping, 16);
+            assert_eq!(guard.len(), 16);
+            assert_eq!(usable.len(), 48);
+
+            // The guard sits at the limit of the stack, while the start remains untouched.
+            assert_eq!(direction.limit(&guard), direction.limit(&mapping));
+            assert_eq!(direction.start(&usable), direction.start(&mapping));
+            assert_eq!(direction.start(&guard), direction.limit(&usable));
+
+            let joined = direction.join_guard(&usable, 16);
+            assert_eq!((joined.top(), joined.bottom()), (top, bottom));
+        }
+
+        assert_eq!(GrowthDirection::Downward.start(&mapping), top);
+        assert_eq!(GrowthDirection::Upward.start(&mapping), bottom);
+        assert_eq!(mapping.start(), GrowthDirection::native().start(&mapping));
+        assert_eq!(mapping.limit(), GrowthDirection::native().limit(&mapping));
+    }
+
+    #[test]
+    fn platform_conformance() {
+        let page_size = sys::page_size();
+        assert!(page_size.is_power_of_two());
+        assert!(sys::min_stack_size() >= page_size);
+        assert!(sys::min_stack_size().is_multiple_of(page_size));
+        assert!(sys::min_stack_size() <= sys::default_stack_size());
+        assert!(sys::default_stack_size() <= sys::max_stack_size());
+
+        let stack = ProtectedFixedSizeStack::default();
+        assert_eq!(stack.len(), sys::default_stack_size());
+        assert_eq!(stack.top() as usize % page_size, 0);
+        assert_eq!(stack.bottom() as usize % page_size, 0);
+
+        unsafe { write_bytes(stack.bottom() as *mut u8, 0x1d, stack.len()) };
+    }
+
+    #[test]
+    fn grow_linked() {
+        fn local_address() -> usize {
+            let local = 0u8;
+            &local as *const u8 as usize
+        }
+
+        let outer = local_address();
+        let inner = super::grow_linked(64 * 1024, local_address).unwrap();
+        assert!(inner.abs_diff(outer) > 64 * 1024);
+
+        let data = [1usize, 2, 3];
+        let sum = super::grow_linked(64 * 1024, || data.iter().sum::<usize>()).unwrap();
+        assert_eq!(sum, 6);
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn grow_linked_panic() {
+        let err = panic::catch_unwind(|| {
+                super::grow_linked(64 * 1024, || panic!("linked")).unwrap();
+            })
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"linked"));
+    }
+
+    #[test]
+    fn guard_protection() {
+        let mut stack = ProtectedFixedSizeStack::new(0).unwrap();
+        let guard = stack.guard_range();
+        assert_eq!(guard.len(), sys::page_size());
+        assert_eq!(guard.top(), stack.bottom());
+        assert!(stack.is_guard_protected());
+
+        stack.unprotect_guard().unwrap();
+        assert!(!stack.is_guard_protected());
+        unsafe { write_bytes(guard.bottom() as *mut u8, 0x1d, guard.len()) };
+
+        stack.reprotect_guard().unwrap();
+        assert!(stack.is_guard_protected());
+    }
+
+    #[test]
+    fn guard_failure_policy() {
+        fn warn(_: &io::Error) {}
+
+        let expected = ProtectedFixedSizeStack::new(0).unwrap().len();
+
+        for &policy in &[GuardFailurePolicy::Fail,
+                         GuardFailurePolicy::Unprotected(warn),
+                         GuardFailurePolicy::Shrink(0)] {
+            // Protecting the guard page succeeds here, so no policy has any effect.
+            let stack = ProtectedFixedSizeStack::new_with_policy(0, policy).unwrap();
+            assert_eq!(stack.len(), expected);
+            assert_eq!(stack.guard_range().top(), stack.bottom());
+            assert!(stack.is_guard_protected());
+        }
+    }
+
+    #[test]
+    fn shrunk_size() {
+        let page_size = sys::page_size();
+        let min_size = Stack::min_size();
+        let rounded = |size| Stack::round_size(size, page_size).unwrap();
+
+        let size = 64 * page_size;
+        assert_eq!(Stack::shrunk_size(size, rounded(size), 0, page_size), Some(size / 2));
+        assert_eq!(Stack::shrunk_size(size, rounded(size), size, page_size), None);
+
+        // Sizes at or below the minimum are rounded up to it, which would never terminate.
+        assert_eq!(Stack::shrunk_size(min_size, rounded(min_size), 0, page_size), None);
+        assert_eq!(Stack::shrunk_size(1, rounded(1), 0, page_size), None);
+
+        // Halving until the minimum is reached ends after a bounded number of attempts.
+        let mut size = Stack::max_size() / 2;
+        let mut attempts = 0;
+        while let Some(shrunk) = Stack::shrunk_size(size, rounded(size), 0, page_size) {
+            size = shrunk;
+            attempts += 1;
+        }
+        assert!(attempts < usize::BITS);
+        assert!(size >= min_size);
+    }
+
+    #[test]
+    fn non_null() {
+        let stack = FixedSizeStack::new(0).unwrap();
+        let slice = stack.as_non_null();
+        assert_eq!(slice.len(), stack.len());
+
+        let copy = unsafe { Stack::from_non_null(slice) };
+        assert_eq!((copy.top(), copy.bottom()), (stack.top(), stack.bottom()));
+    }
+
+    #[cfg(feature = "nightly")]
+    #[test]
+    fn allocator_stack() {
+        use std::alloc::Global;
+
+        extern "C" fn context_function(t: Transfer) -> ! {
+            unsafe { t.context.finish(t.data + 1) }
+        }
+
+        let stack = AllocatorStack::new_in(0, Global).unwrap();
+        assert_eq!(stack.len(), sys::page_size());
+        assert_eq!(stack.bottom() as usize % sys::page_size(), 0);
+
+        let t = unsafe { Context::new(&stack, context_function).resume(1) };
+        assert_eq!(t.data, 2);
+
+        assert!(AllocatorStack::new_in(usize::MAX / 2, Global).is_err());
+    }
+
+    #[test]
+    fn stack_size_too_small() {
+        let stack = FixedSizeStack::new(0).unwrap();
+        assert_eq!(stack.len(), sys::min_stack_size());
+
+        unsafe { write_bytes(stack.bottom() as *mut u8, 0x1d, stack.len()) };
+
+        let stack = ProtectedFixedSizeStack::new(0).unwrap();
+        assert_eq!(stack.len(), sys::min_stack_size());
+
+        unsafe { write_bytes(stack.bottom() as *mut u8, 0x1d, stack.len()) };
+    }
+
+    #[test]
+    fn stack_size_too_large() {
+        let stack_size = sys::max_stack_size() & !(sys::page_size() - 1);
+
+        if let Err(err) = FixedSizeStack::new(stack_size) {
+            assert!(!matches!(err.kind(), StackErrorKind::ExceedsMaximumSize(..)));
+        }
+
+        let stack_size = stack_size + 1;
+
+        let err = FixedSizeStack::new(stack_size).unwrap_err();
+        assert!(matches!(err.kind(), StackErrorKind::ExceedsMaximumSize(..)));
+        assert_eq!(err.stage(), AllocStage::Sizing);
+        assert_eq!(err.requested_size(), Some(stack_size));
+        assert_eq!(err.rounded_size(), None);
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn stack_huge_pages() {
+        let size = 4 << 20;
+        let options = AllocOptions::new().huge_pages(HugePagePolicy::Always);
+
+        let stack = FixedSizeStack::with_options(size, options).unwrap();
+        assert!(stack.len() >= size);
+
+        unsafe { write_bytes(stack.bottom() as *mut u8, 0x1d, stack.len()) };
+
+        let stack = ProtectedFixedSizeStack::with_options(size, options).unwrap();
+        assert!(stack.len() >= size);
+
+        unsafe { write_bytes(stack.bottom() as *mut u8, 0x1d, stack.len()) };
+    }
+}
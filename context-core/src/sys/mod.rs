@@ -0,0 +1,70 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::cmp;
+
+#[cfg(unix)]
+mod unix;
+
+#[cfg(unix)]
+pub use self::unix::{
+    advise_huge_pages,
+    allocate_stack,
+    deallocate_stack,
+    max_stack_size,
+    min_stack_size,
+    page_size,
+    protect_stack,
+    resident_size,
+    set_guard_protection,
+};
+
+#[cfg(all(unix, feature = "debug-switch"))]
+pub use self::unix::thread_stack_bounds;
+
+#[cfg(windows)]
+mod windows;
+
+#[cfg(windows)]
+pub use self::windows::{
+    advise_huge_pages,
+    allocate_stack,
+    deallocate_stack,
+    max_stack_size,
+    min_stack_size,
+    page_size,
+    protect_stack,
+    resident_size,
+    set_guard_protection,
+};
+
+#[cfg(all(windows, feature = "debug-switch"))]
+pub use self::windows::thread_stack_bounds;
+
+pub fn default_stack_size() -> usize {
+    let size = self::min_stack_size() * 8;
+    let max_stack_size = self::max_stack_size();
+
+    cmp::min(size, max_stack_size)
+}
+
+// The stack space required to run a trivial function inside a `Context` and to unwind it
+// by panicking, including the frame reserved by make_fcontext().
+// The value for x86_64 has been measured to be slightly above 5 KiB, with and without
+// optimizations. The others are conservative estimates based on the frame sizes of their ABI.
+#[cfg(target_arch = "x86_64")]
+const PRACTICAL_MIN_STACK_SIZE: usize = 8 * 1024;
+
+#[cfg(any(target_arch = "aarch64", target_arch = "powerpc64"))]
+const PRACTICAL_MIN_STACK_SIZE: usize = 16 * 1024;
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "powerpc64")))]
+const PRACTICAL_MIN_STACK_SIZE: usize = 8 * 1024;
+
+pub fn practical_min_stack_size() -> usize {
+    cmp::max(self::min_stack_size(), PRACTICAL_MIN_STACK_SIZE)
+}
@@ -0,0 +1,172 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::io;
+#[cfg(all(feature = "debug-switch", any(target_os = "linux", target_os = "android")))]
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc;
+
+use stack::{GrowthDirection, Stack};
+
+#[cfg(any(target_os = "openbsd", target_os = "macos", target_os = "ios", target_os = "android",
+          target_os = "vxworks"))]
+const MAP_STACK: libc::c_int = 0;
+
+#[cfg(not(any(target_os = "openbsd", target_os = "macos", target_os = "ios",
+              target_os = "android", target_os = "vxworks")))]
+const MAP_STACK: libc::c_int = libc::MAP_STACK;
+
+// The largest stack worth reserving address space for, even on 32-bit platforms.
+const MAX_STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+pub unsafe fn allocate_stack(size: usize) -> io::Result<Stack> {
+    const PROT: libc::c_int = libc::PROT_READ | libc::PROT_WRITE;
+    const TYPE: libc::c_int = libc::MAP_PRIVATE | libc::MAP_ANON | MAP_STACK;
+
+    let ptr = libc::mmap(ptr::null_mut(), size, PROT, TYPE, -1, 0);
+
+    if ptr == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(Stack::new((ptr as usize + size) as *mut c_void, ptr as *mut c_void))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub unsafe fn advise_huge_pages(stack: &Stack) -> io::Result<()> {
+    if libc::madvise(stack.bottom(), stack.len(), libc::MADV_HUGEPAGE) != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub unsafe fn advise_huge_pages(_: &Stack) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// Returns the bounds of the stack of the current thread as (bottom, top).
+#[cfg(all(feature = "debug-switch", any(target_os = "linux", target_os = "android")))]
+pub fn thread_stack_bounds() -> Option<(usize, usize)> {
+    unsafe {
+        let mut attr = mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+
+        let mut addr = ptr::null_mut();
+        let mut size = 0;
+        let ret = libc::pthread_attr_getstack(&attr, &mut addr, &mut size);
+        libc::pthread_attr_destroy(&mut attr);
+
+        if ret == 0 {
+            Some((addr as usize, addr as usize + size))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(all(feature = "debug-switch", any(target_os = "macos", target_os = "ios")))]
+pub fn thread_stack_bounds() -> Option<(usize, usize)> {
+    unsafe {
+        let thread = libc::pthread_self();
+        let top = libc::pthread_get_stackaddr_np(thread) as usize;
+        Some((top - libc::pthread_get_stacksize_np(thread), top))
+    }
+}
+
+#[cfg(all(feature = "debug-switch",
+          not(any(target_os = "linux", target_os = "android", target_os = "macos",
+                  target_os = "ios"))))]
+pub fn thread_stack_bounds() -> Option<(usize, usize)> {
+    None
+}
+
+pub unsafe fn protect_stack(stack: &Stack) -> io::Result<Stack> {
+    let page_size = page_size();
+
+    debug_assert!(stack.len().is_multiple_of(page_size) && stack.len() != 0);
+
+    let (guard, usable) = GrowthDirection::native().split_guard(stack, page_size);
+    set_guard_protection(&guard, true).map(|_| usable)
+}
+
+pub unsafe fn set_guard_protection(guard: &Stack, protected: bool) -> io::Result<()> {
+    let prot = if protected {
+        libc::PROT_NONE
+    } else {
+        libc::PROT_READ | libc::PROT_WRITE
+    };
+
+    if libc::mprotect(guard.bottom(), guard.len(), prot) != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios",
+          target_os = "freebsd", target_os = "dragonfly", target_os = "netbsd",
+          target_os = "openbsd", target_os = "solaris", target_os = "illumos"))]
+pub fn resident_size(stack: &Stack) -> io::Result<usize> {
+    let page_size = page_size();
+    let start = stack.bottom() as usize & !(page_size - 1);
+    let len = stack.top() as usize - start;
+    let mut pages = vec![0u8; len.div_ceil(page_size)];
+
+    if unsafe { libc::mincore(start as *mut c_void, len, pages.as_mut_ptr() as *mut _) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Only the lowest bit is defined as "resident" on all platforms.
+    Ok(pages.iter().filter(|&&page| page & 1 != 0).count() * page_size)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios",
+              target_os = "freebsd", target_os = "dragonfly", target_os = "netbsd",
+              target_os = "openbsd", target_os = "solaris", target_os = "illumos")))]
+pub fn resident_size(_: &Stack) -> io::Result<usize> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+pub unsafe fn deallocate_stack(ptr: *mut c_void, size: usize) {
+    libc::munmap(ptr, size);
+}
+
+pub fn page_size() -> usize {
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    let mut ret = PAGE_SIZE.load(Ordering::Relaxed);
+
+    if ret == 0 {
+        unsafe {
+            ret = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+        }
+
+        PAGE_SIZE.store(ret, Ordering::Relaxed);
+    }
+
+    ret
+}
+
+pub fn min_stack_size() -> usize {
+    // Previously libc::SIGSTKSZ has been used for this, but it proofed to be very unreliable,
+    // because the resulting values varied greatly between platforms.
+    page_size()
+}
+
+// RLIMIT_STACK only governs the stack of the main thread, not stacks mapped using mmap(),
+// and is commonly either unlimited or tiny in containers. The maximum is thus a fixed cap.
+pub fn max_stack_size() -> usize {
+    MAX_STACK_SIZE
+}
@@ -0,0 +1,152 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::io;
+use std::mem;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use kernel32;
+use winapi;
+
+use stack::{GrowthDirection, Stack};
+
+extern "system" {
+    // TODO: kernel32-sys has currently (0.2.1) a bug where lpflOldProtect
+    // is declared as a DWORD, but should be PDWORD instead.
+    pub fn VirtualProtect(lpAddress: winapi::LPVOID,
+                          dwSize: winapi::SIZE_T,
+                          flNewProtect: winapi::DWORD,
+                          lpflOldProtect: winapi::PDWORD)
+                          -> winapi::BOOL;
+
+    // Exported by kernel32 since Windows 7, which avoids linking psapi.
+    fn K32QueryWorkingSetEx(hProcess: winapi::HANDLE,
+                            pv: winapi::PVOID,
+                            cb: winapi::DWORD)
+                            -> winapi::BOOL;
+}
+
+// PSAPI_WORKING_SET_EX_INFORMATION, whose attributes have the "valid" flag in their lowest bit.
+#[repr(C)]
+struct WorkingSetExInformation {
+    virtual_address: winapi::PVOID,
+    virtual_attributes: winapi::ULONG_PTR,
+}
+
+pub unsafe fn allocate_stack(size: usize) -> io::Result<Stack> {
+    const NULL: winapi::LPVOID = 0 as winapi::LPVOID;
+    const PROT: winapi::DWORD = winapi::PAGE_READWRITE;
+    const TYPE: winapi::DWORD = winapi::MEM_COMMIT | winapi::MEM_RESERVE;
+
+    let ptr = kernel32::VirtualAlloc(NULL, size as winapi::SIZE_T, TYPE, PROT);
+
+    if ptr == NULL {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(Stack::new((ptr as usize + size) as *mut c_void, ptr as *mut c_void))
+    }
+}
+
+// Large pages on Windows require the SeLockMemoryPrivilege and
+// can't be requested for already allocated memory.
+pub unsafe fn advise_huge_pages(_: &Stack) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+pub unsafe fn protect_stack(stack: &Stack) -> io::Result<Stack> {
+    let page_size = page_size();
+
+    debug_assert!(stack.len() % page_size == 0 && stack.len() != 0);
+
+    let (guard, usable) = GrowthDirection::native().split_guard(stack, page_size);
+    set_guard_protection(&guard, true).map(|_| usable)
+}
+
+pub unsafe fn set_guard_protection(guard: &Stack, protected: bool) -> io::Result<()> {
+    let prot = if protected {
+        winapi::PAGE_READWRITE | winapi::PAGE_GUARD
+    } else {
+        winapi::PAGE_READWRITE
+    };
+
+    let mut old_prot: winapi::DWORD = 0;
+    let ret = {
+        let guard_size = guard.len() as winapi::SIZE_T;
+        VirtualProtect(guard.bottom(), guard_size, prot, &mut old_prot)
+    };
+
+    if ret == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn resident_size(stack: &Stack) -> io::Result<usize> {
+    let page_size = page_size();
+    let start = stack.bottom() as usize & !(page_size - 1);
+    let count = (stack.top() as usize - start).div_ceil(page_size);
+    let mut pages: Vec<WorkingSetExInformation> = (0..count)
+        .map(|i| {
+            WorkingSetExInformation {
+                virtual_address: (start + i * page_size) as winapi::PVOID,
+                virtual_attributes: 0,
+            }
+        })
+        .collect();
+
+    let ret = unsafe {
+        let info = pages.as_mut_ptr() as winapi::PVOID;
+        let size = mem::size_of_val(&pages[..]) as winapi::DWORD;
+        K32QueryWorkingSetEx(kernel32::GetCurrentProcess(), info, size)
+    };
+
+    if ret == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(pages.iter().filter(|page| page.virtual_attributes & 1 != 0).count() * page_size)
+}
+
+pub unsafe fn deallocate_stack(ptr: *mut c_void, _: usize) {
+    kernel32::VirtualFree(ptr as winapi::LPVOID, 0, winapi::MEM_RELEASE);
+}
+
+pub fn page_size() -> usize {
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    let mut ret = PAGE_SIZE.load(Ordering::Relaxed);
+
+    if ret == 0 {
+        ret = unsafe {
+            let mut info = mem::zeroed();
+            kernel32::GetSystemInfo(&mut info);
+            info.dwPageSize as usize
+        };
+
+        PAGE_SIZE.store(ret, Ordering::Relaxed);
+    }
+
+    ret
+}
+
+// GetCurrentThreadStackLimits() requires Windows 8 and isn't bound by winapi 0.2.
+#[cfg(feature = "debug-switch")]
+pub fn thread_stack_bounds() -> Option<(usize, usize)> {
+    None
+}
+
+// Windows does not seem to provide a stack limit API
+pub fn min_stack_size() -> usize {
+    page_size()
+}
+
+// Windows does not seem to provide a stack limit API
+pub fn max_stack_size() -> usize {
+    usize::MAX
+}
@@ -0,0 +1,76 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Compares the initial fcontext record prepared by make_fcontext against a golden file per
+// architecture and OS in tests/golden, so that changes to the layout in asm edits or
+// new ports show up in review. Run with CONTEXT_BLESS=1 to (re)write the golden file.
+
+extern crate context_core;
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+
+use context_core::{Context, Transfer};
+use context_core::stack::ProtectedFixedSizeStack;
+
+extern "C" {
+    // Only used for its address, since trampoline and finish are placed right after it.
+    fn make_fcontext();
+}
+
+extern "C" fn context_function(_: Transfer) -> ! {
+    unreachable!();
+}
+
+// Describes every word of the record, from the address of the Context up to the stack start.
+fn dump_initial_record() -> String {
+    let stack = ProtectedFixedSizeStack::default();
+    let ctx = unsafe { Context::new(&stack, context_function) };
+
+    let record = ctx.as_ptr() as usize;
+    let start = stack.start() as usize;
+    let bottom = stack.bottom() as usize;
+    let asm = make_fcontext as *const () as usize;
+
+    let mut dump = String::new();
+
+    for offset in (0..start - record).step_by(mem::size_of::<usize>()) {
+        let value = unsafe { *((record + offset) as *const usize) };
+
+        let _ = if value == context_function as *const () as usize {
+            writeln!(dump, "{:#06x} context-fn", offset)
+        } else if value.wrapping_sub(asm) < 0x1000 {
+            writeln!(dump, "{:#06x} make_fcontext{:+#x}", offset, value - asm)
+        } else if bottom <= value && value < start {
+            writeln!(dump, "{:#06x} record{:+#x}", offset, value as isize - record as isize)
+        } else {
+            writeln!(dump, "{:#06x} {:#x}", offset, value)
+        };
+    }
+
+    dump
+}
+
+#[test]
+fn initial_record() {
+    let name = format!("{}-{}.txt", env::consts::ARCH, env::consts::OS);
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", &name].iter().collect();
+    let dump = dump_initial_record();
+
+    if env::var_os("CONTEXT_BLESS").is_some() {
+        fs::write(&path, &dump).unwrap();
+        return;
+    }
+
+    match fs::read_to_string(&path) {
+        Ok(golden) => assert_eq!(dump, golden, "the layout differs from {}", path.display()),
+        Err(_) => println!("no golden file at {}, the layout is:\n{}", path.display(), dump),
+    }
+}
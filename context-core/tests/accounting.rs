@@ -0,0 +1,90 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The accounting counters are process-wide and are thus
+// tested in their own binary using a single test function.
+#![cfg(feature = "accounting")]
+
+extern crate context_core;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use context_core::accounting::{self, Event};
+use context_core::stack::{AllocStage, FixedSizeStack, ProtectedFixedSizeStack, RawStackError,
+                          Stack, StackErrorKind};
+
+static REFUSED: AtomicUsize = AtomicUsize::new(0);
+static REFUSED_LIVE: AtomicUsize = AtomicUsize::new(0);
+
+fn callback(event: Event) {
+    match event {
+        Event::LimitReached { .. } => REFUSED.fetch_add(1, Ordering::Relaxed),
+        Event::LiveLimitReached { .. } => REFUSED_LIVE.fetch_add(1, Ordering::Relaxed),
+        _ => 0,
+    };
+}
+
+#[test]
+fn accounting() {
+    assert_eq!(accounting::reserved_bytes(), 0);
+    assert_eq!(accounting::live_stacks(), 0);
+
+    accounting::set_callback(Some(callback));
+
+    let fixed = FixedSizeStack::default();
+    let protected = ProtectedFixedSizeStack::default();
+    // The guard page of the protected stack is accounted for as well.
+    let reserved = accounting::reserved_bytes();
+    assert!(reserved > fixed.len() + protected.len());
+    assert_eq!(accounting::live_stacks(), 2);
+
+    accounting::set_limit(Some(reserved));
+    assert_eq!(accounting::limit(), Some(reserved));
+
+    let err = FixedSizeStack::new(Stack::default_size()).unwrap_err();
+    assert_eq!(err.kind(), StackErrorKind::LimitReached(reserved));
+    assert_eq!(err.stage(), AllocStage::Accounting);
+    assert!(err.is_retryable());
+
+    assert_eq!(REFUSED.load(Ordering::Relaxed), 1);
+    assert_eq!(accounting::reserved_bytes(), reserved);
+    assert_eq!(accounting::live_stacks(), 2);
+
+    drop(fixed);
+    let fixed = FixedSizeStack::default();
+    assert_eq!(accounting::reserved_bytes(), reserved);
+
+    accounting::set_limit(None);
+    assert_eq!(accounting::peak_live_stacks(), 2);
+
+    accounting::set_max_live_stacks(Some(3));
+    assert_eq!(accounting::max_live_stacks(), Some(3));
+
+    let third = FixedSizeStack::default();
+    let err = FixedSizeStack::new(Stack::default_size()).unwrap_err();
+    assert_eq!(err.kind(), StackErrorKind::LiveLimitReached(3));
+    assert_eq!(err.stage(), AllocStage::Accounting);
+    assert!(err.is_retryable());
+    assert_eq!(ProtectedFixedSizeStack::try_new_nonalloc(Stack::default_size()).unwrap_err(),
+               RawStackError::LiveLimitReached(3));
+
+    assert_eq!(REFUSED_LIVE.load(Ordering::Relaxed), 2);
+    assert_eq!(accounting::reserved_bytes(), reserved + third.len());
+    assert_eq!(accounting::live_stacks(), 3);
+    assert_eq!(accounting::peak_live_stacks(), 3);
+
+    accounting::set_max_live_stacks(None);
+    drop(third);
+    drop(fixed);
+    drop(protected);
+
+    assert_eq!(accounting::reserved_bytes(), 0);
+    assert_eq!(accounting::live_stacks(), 0);
+    assert_eq!(accounting::peak_live_stacks(), 3);
+    accounting::reset_peak_live_stacks();
+    assert_eq!(accounting::peak_live_stacks(), 0);
+}
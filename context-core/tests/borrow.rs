@@ -0,0 +1,56 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The borrow registry is process-wide and is thus
+// tested in its own binary using a single test function.
+#![cfg(feature = "borrow-tracking")]
+
+extern crate context_core;
+
+#[cfg(panic = "unwind")]
+use std::panic::{self, AssertUnwindSafe};
+
+use context_core::borrow;
+use context_core::stack::{FixedSizeStack, ProtectedFixedSizeStack};
+use context_core::{Context, Transfer};
+
+extern "C" fn context_function(t: Transfer) -> ! {
+    let t = unsafe { t.context.resume(t.data) };
+    unsafe { t.context.finish(t.data) }
+}
+
+#[test]
+fn borrow_tracking() {
+    // A context which finished releases its borrow before the stack is dropped.
+    let stack = ProtectedFixedSizeStack::default();
+    let token = stack.borrow_token();
+    assert_eq!(borrow::live_borrows(), 1);
+
+    let t = unsafe { Context::new(&stack, context_function).resume(1) };
+    let t = unsafe { t.context.resume(2) };
+    assert_eq!(t.data, 2);
+    drop(token);
+    assert_eq!(borrow::live_borrows(), 0);
+    drop(stack);
+
+    // Dropping another stack isn't affected by a live borrow.
+    let borrowed = FixedSizeStack::default();
+    let token = borrowed.borrow_token();
+    drop(FixedSizeStack::default());
+
+    // Dropping a stack a suspended context still borrows is diagnosed right away.
+    #[cfg(panic = "unwind")]
+    {
+        let _suspended = unsafe { Context::new(&borrowed, context_function).resume(1) };
+        let err = panic::catch_unwind(AssertUnwindSafe(|| drop(borrowed))).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("dropped while 1 context(s) still borrow it"), "{}", msg);
+    }
+
+    drop(token);
+    assert_eq!(borrow::live_borrows(), 0);
+}
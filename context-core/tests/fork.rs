@@ -0,0 +1,49 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Forking a process running the test harness is only safe if the child refrains from
+// allocating, so this is tested in its own binary and the child reports by its exit code.
+#![cfg(unix)]
+
+extern crate context_core;
+extern crate libc;
+
+use context_core::{Context, Transfer};
+use context_core::stack::ProtectedFixedSizeStack;
+
+extern "C" fn counter(mut t: Transfer) -> ! {
+    let mut count = t.data;
+
+    loop {
+        count += 1;
+        t = unsafe { t.context.resume(count) };
+    }
+}
+
+#[test]
+fn fork_while_suspended() {
+    let stack = ProtectedFixedSizeStack::default();
+    let t = unsafe { Context::new(&stack, counter).resume(10) };
+    assert_eq!(t.data, 11);
+
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+
+    // Both processes resume their own copy of the suspended context.
+    let t = unsafe { t.context.resume(0) };
+
+    if pid == 0 {
+        unsafe { libc::_exit(if t.data == 12 { 0 } else { 1 }) };
+    }
+
+    assert_eq!(t.data, 12);
+
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+}
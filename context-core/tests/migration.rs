@@ -0,0 +1,50 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The migration callbacks are process-wide and are thus
+// tested in their own binary using a single test function.
+#![cfg(feature = "thread-migration")]
+
+extern crate context_core;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use context_core::migration;
+use context_core::stack::ProtectedFixedSizeStack;
+use context_core::{Context, Transfer};
+
+static MIGRATIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn callback() {
+    MIGRATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+extern "C" fn context_function(t: Transfer) -> ! {
+    let t = unsafe { t.context.resume(0) };
+    unsafe { t.context.finish(MIGRATIONS.load(Ordering::Relaxed)) }
+}
+
+#[test]
+fn migration() {
+    assert!(migration::register(callback));
+
+    let stack = ProtectedFixedSizeStack::default();
+    let t = unsafe { Context::new(&stack, context_function).resume(0) };
+    assert_eq!(MIGRATIONS.load(Ordering::Relaxed), 0);
+
+    // Resuming the context on another thread makes its call to resume() return there.
+    let data = thread::spawn(move || unsafe { t.context.resume(0).data }).join().unwrap();
+    assert_eq!(data, 1);
+    assert_eq!(MIGRATIONS.load(Ordering::Relaxed), 1);
+
+    for _ in 1..migration::MAX_CALLBACKS {
+        assert!(migration::register(callback));
+    }
+
+    assert!(!migration::register(callback));
+}
@@ -0,0 +1,56 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Counting heap allocations requires replacing the global allocator,
+// so this is tested in its own binary.
+
+extern crate context_core;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use context_core::stack::{FixedSizeStack, ProtectedFixedSizeStack, RawStackError, Stack};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|allocations| allocations.set(allocations.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn try_new_nonalloc() {
+    let size = Stack::default_size();
+
+    assert_eq!(allocations(|| drop(ProtectedFixedSizeStack::try_new_nonalloc(size).unwrap())), 0);
+    assert_eq!(allocations(|| drop(FixedSizeStack::try_new_nonalloc(size).unwrap())), 0);
+
+    assert_eq!(allocations(|| {
+                   let err = ProtectedFixedSizeStack::try_new_nonalloc(usize::MAX).unwrap_err();
+                   assert!(matches!(err, RawStackError::ExceedsMaximumSize(_)));
+               }),
+               0);
+}
@@ -0,0 +1,59 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// RLIMIT_STACK is process-wide and is thus changed in its own binary.
+#![cfg(unix)]
+
+extern crate context_core;
+extern crate libc;
+
+use std::mem;
+use std::ptr;
+
+use context_core::stack::{self, ProtectedFixedSizeStack, Stack};
+use context_core::{Context, Transfer};
+
+fn set_stack_rlimit(soft: libc::rlim_t) -> bool {
+    unsafe {
+        let mut limit: libc::rlimit = mem::zeroed();
+        assert_eq!(libc::getrlimit(libc::RLIMIT_STACK, &mut limit), 0);
+
+        limit.rlim_cur = soft;
+        libc::setrlimit(libc::RLIMIT_STACK, &limit) == 0
+    }
+}
+
+extern "C" fn fill(t: Transfer) -> ! {
+    // Touch most of the stack to make sure it's usable despite the rlimit.
+    let mut buffer = [0u8; 512 * 1024];
+    unsafe { ptr::write_volatile(&mut buffer[0], 1) };
+    let data = buffer.iter().map(|&b| b as usize).sum();
+
+    unsafe { t.context.finish(data) }
+}
+
+#[test]
+fn rlimit_independent() {
+    let limits = stack::limits();
+    assert_eq!(limits.max_size, 1024 * 1024 * 1024);
+    assert!(limits.min_size <= limits.practical_min_size);
+    assert!(limits.practical_min_size <= limits.default_size);
+
+    for &soft in [64 * 1024, libc::RLIM_INFINITY].iter() {
+        if !set_stack_rlimit(soft) {
+            // Raising the soft limit above the hard limit is refused.
+            continue;
+        }
+
+        assert_eq!(stack::limits(), limits);
+        assert_eq!(Stack::max_size(), limits.max_size);
+
+        let stack = ProtectedFixedSizeStack::new(1024 * 1024).unwrap();
+        let t = unsafe { Context::new(&stack, fill).resume(0) };
+        assert_eq!(t.data, 1);
+    }
+}
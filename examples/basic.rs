@@ -11,7 +11,7 @@ use context::{Context, Transfer};
 use context::stack::ProtectedFixedSizeStack;
 
 // Print the natural numbers from 0 to 9 using a "generator" preserving state on the stack.
-fn main() {
+pub fn run() {
     // This method will always `resume()` immediately back to the
     // previous `Context` with a `data` value incremented by one starting at 0.
     // You could thus describe this method as a "natural number generator".
@@ -43,3 +43,7 @@ fn main() {
 
     println!("Finished!");
 }
+
+fn main() {
+    run();
+}
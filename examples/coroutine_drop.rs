@@ -0,0 +1,59 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+extern crate context;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use context::compat::corosensei::Coroutine;
+
+// Increments the shared counter once it's dropped.
+struct Dropper(Rc<Cell<usize>>);
+
+impl Drop for Dropper {
+    fn drop(&mut self) {
+        println!("Dropping a Dropper!");
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+// Drops a suspended coroutine and shows that its stack is unwound beforehand,
+// which runs the destructors of the values still living on it.
+pub fn run() {
+    let drops = Rc::new(Cell::new(0));
+    let deferred = Rc::new(Cell::new(0));
+
+    let d = drops.clone();
+    let r = deferred.clone();
+    let mut coroutine: Coroutine<(), usize, ()> = Coroutine::new(move |yielder, ()| {
+        let _dropper = Dropper(d);
+        yielder.defer(move || {
+            println!("Running a deferred function!");
+            r.set(r.get() + 1);
+        });
+
+        for i in 0usize.. {
+            yielder.suspend(i);
+        }
+    });
+
+    for _ in 0..3 {
+        println!("Got {:?}", coroutine.resume(()).as_yield());
+    }
+
+    println!("Dropping the suspended coroutine!");
+    drop(coroutine);
+
+    assert_eq!(drops.get(), 1);
+    assert_eq!(deferred.get(), 1);
+    println!("Finished!");
+}
+
+fn main() {
+    run();
+}
@@ -11,7 +11,7 @@ use context::{Context, Transfer};
 use context::stack::ProtectedFixedSizeStack;
 
 // Print the natural numbers from 0 to 9 using a "generator" preserving state on the stack.
-fn main() {
+pub fn run() {
     // This method will always `resume()` immediately back to the
     // previous `Context` with a `data` value of the next number in the fibonacci sequence.
     // You could thus describe this method as a "fibonacci sequence generator".
@@ -48,3 +48,7 @@ fn main() {
 
     println!("Finished!");
 }
+
+fn main() {
+    run();
+}
@@ -23,8 +23,12 @@ impl Drop for Dropper {
 // This struct is used to carry the actual context
 struct Carrier {
     context: Option<Context>,
+    unwind: bool,
 }
 
+// The payload used to unwind the stack of `context_function()`.
+struct ForceUnwind;
+
 fn take_some_stack_from_transfer(t: &Transfer) -> Option<ProtectedFixedSizeStack> {
     let stack_ref = unsafe { &mut *(t.data as *mut Option<ProtectedFixedSizeStack>) };
     stack_ref.take()
@@ -36,32 +40,45 @@ fn stack_ref_from_some_stack(some_stack: &mut Option<ProtectedFixedSizeStack>) -
 
 // This method is used to force unwind a foreign context function.
 extern "C" fn unwind_stack(t: Transfer) -> Transfer {
-    println!("Unwinding stack by panicking!");
-
-    // We have to store the `t.context` in the `Carrier` object. because this function won't
-    // be able to return normally.
-    let Transfer { context: ctx, data } = t;
-    let carrier = unsafe { &mut *(data as *mut Carrier) };
-
-    carrier.context = Some(ctx);
-
-    // Unwind the current stack by panicking.
-    // We use std::panic::resume_unwind() here however because panic!() will call the panic handler
-    // which aborts the process if more than one panic is triggered on a thread.
-    // This is problematic for coroutines though (the most popular use case for this crate),
-    // because by their very definition multiple seperate stacks per thread are used.
-    //
-    // Thus the following problem can occur:
-    //   Let's say we have a `Context` function (a coroutine) which _owns_ a list of coroutines.
-    //   If that primary coroutine unwinds it's stack by panicking it will drop the list and thus
-    //   all the contained coroutines are dropped. When they unwind their stack due to this the
-    //   process will be aborted by Rust's runtime with "thread panicked while it is panicking".
-    //
-    // The downside of this technique is however that the internaL PANIC_COUNT is off by one
-    // (it's still zero) and thus it won't abort the process anymore if drop() panics.
-    // This could be fixed however by setting a custom panic handler using panic::set_handler().
-    struct ForceUnwind;
-    panic::resume_unwind(Box::new(ForceUnwind));
+    println!("Requesting to unwind the stack!");
+
+    // Panics must not unwind out of an `extern "C"` function, which aborts the process.
+    // Instead we mark the `Carrier` of the resumed context function, which will
+    // unwind its own stack right after it returns from it's call to `resume()`.
+    let carrier = unsafe { &mut *(t.data as *mut Carrier) };
+    carrier.unwind = true;
+
+    t
+}
+
+// Resumes the `Context` in the `carrier` and stores the one returned by `resume()` in it.
+// Unwinds the current stack if `unwind_stack()` was executed ontop of it in the meantime.
+fn resume_carrier(carrier: &mut Carrier, data: usize) {
+    let context = carrier.context.take().unwrap();
+    let Transfer { context, .. } = unsafe { context.resume(data) };
+    carrier.context = Some(context);
+
+    if carrier.unwind {
+        // Unwind the current stack by panicking.
+        // We use std::panic::resume_unwind() here however because panic!() will call the panic
+        // handler which aborts the process if more than one panic is triggered on a thread.
+        // This is problematic for coroutines though (the most popular use case for this crate),
+        // because by their very definition multiple seperate stacks per thread are used.
+        //
+        // Thus the following problem can occur:
+        //   Let's say we have a `Context` function (a coroutine) which _owns_ a list of
+        //   coroutines. If that primary coroutine unwinds it's stack by panicking it will drop
+        //   the list and thus all the contained coroutines are dropped. When they unwind their
+        //   stack due to this the process will be aborted by Rust's runtime with
+        //   "thread panicked while it is panicking".
+        //
+        // The downside of this technique is however that the internaL PANIC_COUNT is off by one
+        // (it's still zero) and thus it won't abort the process anymore if drop() panics.
+        // This could be fixed however by setting a custom panic handler using
+        // panic::set_handler().
+        println!("Unwinding stack by panicking!");
+        panic::resume_unwind(Box::new(ForceUnwind));
+    }
 }
 
 // This method is used to defer stack deallocation after it's not used anymore.
@@ -82,7 +99,10 @@ extern "C" fn context_function(t: Transfer) -> ! {
     let stack_ref = stack_ref_from_some_stack(&mut some_stack);
 
     let (result, context) = {
-        let mut carrier = Carrier { context: Some(t.context) };
+        let mut carrier = Carrier {
+            context: Some(t.context),
+            unwind: false,
+        };
 
         let carrier_ptr = &mut carrier as *mut _ as usize;
 
@@ -96,15 +116,11 @@ extern "C" fn context_function(t: Transfer) -> ! {
 
             // We've set everything up! Go back to `main()`!
             println!("Everything's set up!");
-            let context = carrier.context.take().unwrap();
-            let Transfer { context, .. } = unsafe { context.resume(carrier_ptr) };
-            carrier.context = Some(context);
+            resume_carrier(carrier, carrier_ptr);
 
             for i in 0usize.. {
                 print!("Yielding {} => ", i);
-                let context = carrier.context.take().unwrap();
-                let Transfer { context, .. } = unsafe { context.resume(i) };
-                carrier.context = Some(context);
+                resume_carrier(carrier, i);
             }
         });
 
@@ -113,7 +129,10 @@ extern "C" fn context_function(t: Transfer) -> ! {
 
     match result {
         Ok(..) => println!("Finished loop without panicking (this should not happen here)!"),
-        Err(..) => println!("Recovered from a panic!"),
+        Err(err) => {
+            assert!(err.is::<ForceUnwind>());
+            println!("Recovered from a panic!");
+        }
     }
 
     // We own the stack (`main()` gave it to us) and we need to delete it.
@@ -121,7 +140,7 @@ extern "C" fn context_function(t: Transfer) -> ! {
     // that particular stack, we defer deletion of it by resuming `main()` and running the ontop
     // function `delete_stack()` before `main()` returns from it's call to `resume_ontop()`.
     println!("Defer stack deallocation by returning to main()!");
-    unsafe { context.resume_ontop(stack_ref, delete_stack) };
+    let _ = unsafe { context.resume_ontop(stack_ref, delete_stack) };
 
     unreachable!();
 }
@@ -137,7 +156,8 @@ pub fn run() {
     // Yield to context_function(). This important since the returned `Context` reference is
     // different than the one returned by `Context::new()` (since it points to the entry function).
     // It's important that we do this first or else calling `Context::resume_ontop()` will crash.
-    // See documentation of `Context::resume_ontop()` for more information.
+    // See the safety section of `Context::resume_ontop()` for more information and
+    // `context::typed::Context` for a variant which rejects this at compile time.
     // Furthermore we pass a reference to the Option<ProtectedFixedSizeStack> along with it
     // so it can delete it's own stack (which is important for stackful coroutines).
     let Transfer { context, data } = unsafe { ctx.resume(stack_ref) };
@@ -158,9 +178,11 @@ pub fn run() {
     }
 
     // Resume `context_function()` with the ontop function `unwind_stack()`.
-    // Before it returns from it's own call to `resume()` it will call `unwind_stack()`.
+    // Before it returns from it's own call to `resume()` it will call `unwind_stack()`,
+    // which requests it to unwind its stack.
+    // The returned `Transfer` refers to the finished `context_function()` and is thus dropped.
     println!("Resuming context with unwind_stack() ontop!");
-    unsafe { ctx.resume_ontop(carrier_ptr, unwind_stack) };
+    let _ = unsafe { ctx.resume_ontop(carrier_ptr, unwind_stack) };
 
     match some_stack {
         Some(..) => println!("Stack is still there (this should not happen here)!"),
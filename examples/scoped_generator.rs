@@ -0,0 +1,60 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+extern crate context;
+
+use context::compat::corosensei::ScopedCoroutine;
+
+// A "generator" which can be iterated over. Since it wraps a `ScopedCoroutine`
+// the generator function may borrow from the stack frame of its creator.
+struct Generator<'a, T> {
+    coroutine: ScopedCoroutine<'a, (), T, ()>,
+}
+
+impl<'a, T> Iterator for Generator<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.coroutine.done() {
+            return None;
+        }
+
+        self.coroutine.resume(()).as_yield()
+    }
+}
+
+// Yields the words of `text` longer than `min_len` without copying them.
+fn long_words<'a>(text: &'a str, min_len: usize) -> Generator<'a, &'a str> {
+    Generator {
+        coroutine: ScopedCoroutine::new(move |yielder, ()| {
+            for word in text.split_whitespace() {
+                if word.len() > min_len {
+                    yielder.suspend(word);
+                }
+            }
+        }),
+    }
+}
+
+pub fn run() {
+    let text = String::from("the quick brown fox jumps over the lazy dog");
+
+    for word in long_words(&text, 3) {
+        println!("Got {}", word);
+    }
+
+    // Generators stopped early are unwound once they're dropped, which ends their borrows.
+    let first = long_words(&text, 4).next();
+    println!("First long word: {:?}", first);
+
+    drop(text);
+    println!("Finished!");
+}
+
+fn main() {
+    run();
+}
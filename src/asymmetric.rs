@@ -0,0 +1,304 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+
+use context::{Context, Transfer};
+use stack::{ProtectedFixedSizeStack, StackOwner};
+
+/// The function executed by a `Callee`.
+///
+/// It receives the `Caller` to suspend itself with and the `data` of the first call.
+/// The value it returns is the result of the call which resumed it the last time.
+pub type CalleeFn = fn(&mut Caller, usize) -> usize;
+
+/// The result of `Callee::call()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallResult {
+    /// The callee suspended itself using `Caller::suspend()` and can be called again.
+    Suspended(usize),
+
+    /// The callee returned from its `CalleeFn` and is finished.
+    Returned(usize),
+}
+
+// Lives on the stack of the side calling a Callee for the duration of each call.
+// Its address is the `data` of every switch into the callee.
+struct Exchange {
+    data: usize,
+    returned: bool,
+}
+
+/// A context which can only be called and returns to whoever called it, like a function.
+///
+/// Contrary to the symmetric `Context` a callee has no way to switch to any other context
+/// than its caller: Its `CalleeFn` only receives a `Caller`, which can't be duplicated,
+/// handed out or resumed by anyone else. Schedulers can thus rely on a callee always
+/// returning control to the point it was called from.
+///
+/// The callee owns its stack, so no other context can run on it meanwhile. A callee which is
+/// suspended can't be unwound, so dropping it leaks its stack along with everything living on
+/// it. The memory of values pinned on the stack is thus never reused without dropping them.
+///
+/// # Examples
+///
+/// ```
+/// use context::asymmetric::{Callee, CallResult, Caller};
+/// use context::stack::ProtectedFixedSizeStack;
+///
+/// fn counter(caller: &mut Caller, mut step: usize) -> usize {
+///     let mut count = 0;
+///
+///     while step != 0 {
+///         count += step;
+///         step = caller.suspend(count);
+///     }
+///
+///     count
+/// }
+///
+/// let mut callee = Callee::new(ProtectedFixedSizeStack::default(), counter);
+///
+/// assert_eq!(callee.call(1), CallResult::Suspended(1));
+/// assert_eq!(callee.call(2), CallResult::Suspended(3));
+/// assert_eq!(callee.call(0), CallResult::Returned(3));
+/// assert!(callee.is_finished());
+/// ```
+pub struct Callee<S: StackOwner = ProtectedFixedSizeStack> {
+    context: Option<Context>,
+    started: bool,
+    // Owned for as long as the callee may run on it, and leaked if it never finishes.
+    stack: ManuallyDrop<S>,
+    // The callee's stack might hold non-Send values.
+    marker: PhantomData<*mut ()>,
+}
+
+impl<S: StackOwner> Callee<S> {
+    /// Creates a new callee prepared to execute `f` at the beginning of `stack`, which it keeps
+    /// until it's dropped.
+    ///
+    /// `f` is not executed until the first call to `call()`. Borrowed stacks are rejected,
+    /// since a second callee could be started on them:
+    ///
+    /// ```compile_fail
+    /// use context::asymmetric::{Callee, Caller};
+    /// use context::stack::ProtectedFixedSizeStack;
+    ///
+    /// fn identity(_: &mut Caller, data: usize) -> usize {
+    ///     data
+    /// }
+    ///
+    /// let stack = ProtectedFixedSizeStack::default();
+    /// let callee = Callee::new(&*stack, identity);
+    /// ```
+    pub fn new(stack: S, f: CalleeFn) -> Callee<S> {
+        // Hands `f` over to the callee, which waits for the first call afterwards.
+        let t = unsafe { Context::new(&stack, callee_function).resume(f as usize) };
+
+        Callee {
+            context: Some(t.context),
+            started: false,
+            stack: ManuallyDrop::new(stack),
+            marker: PhantomData,
+        }
+    }
+
+    /// Calls the callee, passing `data` to it.
+    ///
+    /// On the first call `data` is passed as the argument of the `CalleeFn`,
+    /// otherwise it's returned by the call to `Caller::suspend()` the callee is suspended in.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the callee is finished.
+    pub fn call(&mut self, data: usize) -> CallResult {
+        let context = self.context.take().expect("called a finished callee");
+        self.started = true;
+
+        let mut exchange = Exchange {
+            data,
+            returned: false,
+        };
+
+        let t = unsafe { context.resume(&mut exchange as *mut Exchange as usize) };
+
+        if exchange.returned {
+            CallResult::Returned(exchange.data)
+        } else {
+            self.context = Some(t.context);
+            CallResult::Suspended(exchange.data)
+        }
+    }
+
+    /// Returns whether the callee has returned from its `CalleeFn`.
+    pub fn is_finished(&self) -> bool {
+        self.context.is_none()
+    }
+}
+
+impl<S: StackOwner> Drop for Callee<S> {
+    fn drop(&mut self) {
+        // A callee which was never called only waits in callee_function(), which owns nothing.
+        if self.context.is_none() || !self.started {
+            unsafe { ManuallyDrop::drop(&mut self.stack) };
+        }
+    }
+}
+
+/// Passed to the `CalleeFn` of a `Callee` to return to its caller.
+pub struct Caller {
+    context: Option<Context>,
+    exchange: *mut Exchange,
+}
+
+impl Caller {
+    /// Suspends the callee, returning `data` from the call to `Callee::call()`.
+    ///
+    /// Returns the `data` of the next call to `Callee::call()`.
+    pub fn suspend(&mut self, data: usize) -> usize {
+        unsafe { (*self.exchange).data = data };
+
+        let Transfer { context, data } = unsafe { self.context.take().unwrap().resume(0) };
+        self.context = Some(context);
+        self.exchange = data as *mut Exchange;
+
+        unsafe { (*self.exchange).data }
+    }
+}
+
+extern "C" fn callee_function(t: Transfer) -> ! {
+    let f: CalleeFn = unsafe { mem::transmute(t.data) };
+
+    // Return to Callee::new() and wait for the first call.
+    let Transfer { context, data } = unsafe { t.context.resume(0) };
+    let mut caller = Caller {
+        context: Some(context),
+        exchange: data as *mut Exchange,
+    };
+
+    let data = unsafe { (*caller.exchange).data };
+    let data = f(&mut caller, data);
+
+    unsafe {
+        *caller.exchange = Exchange {
+            data,
+            returned: true,
+        };
+
+        caller.context.take().unwrap().finish(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::ops::Deref;
+
+    use stack::Stack;
+    use super::*;
+
+    thread_local! {
+        static DROPPED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Counts the stacks dropped on the current thread.
+    struct CountedStack(ProtectedFixedSizeStack);
+
+    impl Deref for CountedStack {
+        type Target = Stack;
+
+        fn deref(&self) -> &Stack {
+            &self.0
+        }
+    }
+
+    impl Drop for CountedStack {
+        fn drop(&mut self) {
+            DROPPED.with(|dropped| dropped.set(dropped.get() + 1));
+        }
+    }
+
+    unsafe impl StackOwner for CountedStack {}
+
+    #[test]
+    fn call_and_return() {
+        fn double(_: &mut Caller, data: usize) -> usize {
+            data * 2
+        }
+
+        let mut callee = Callee::new(ProtectedFixedSizeStack::default(), double);
+
+        assert!(!callee.is_finished());
+        assert_eq!(callee.call(21), CallResult::Returned(42));
+        assert!(callee.is_finished());
+    }
+
+    #[test]
+    fn nested() {
+        fn inner(caller: &mut Caller, data: usize) -> usize {
+            caller.suspend(data + 1) + 1
+        }
+
+        fn outer(caller: &mut Caller, data: usize) -> usize {
+            let mut callee = Callee::new(ProtectedFixedSizeStack::default(), inner);
+
+            let first = match callee.call(data) {
+                CallResult::Suspended(data) => caller.suspend(data),
+                CallResult::Returned(_) => unreachable!(),
+            };
+
+            match callee.call(first) {
+                CallResult::Returned(data) => data * 10,
+                CallResult::Suspended(_) => unreachable!(),
+            }
+        }
+
+        let mut callee = Callee::new(ProtectedFixedSizeStack::default(), outer);
+
+        assert_eq!(callee.call(1), CallResult::Suspended(2));
+        assert_eq!(callee.call(5), CallResult::Returned(60));
+    }
+
+    #[test]
+    fn drop_leaks_suspended() {
+        fn suspend(caller: &mut Caller, data: usize) -> usize {
+            caller.suspend(data)
+        }
+
+        let stack = || CountedStack(ProtectedFixedSizeStack::default());
+        let dropped = || DROPPED.with(Cell::get);
+
+        drop(Callee::new(stack(), suspend));
+        assert_eq!(dropped(), 1);
+
+        let mut callee = Callee::new(stack(), suspend);
+        assert_eq!(callee.call(1), CallResult::Suspended(1));
+        drop(callee);
+        assert_eq!(dropped(), 1);
+
+        let mut callee = Callee::new(stack(), suspend);
+        callee.call(1);
+        assert_eq!(callee.call(2), CallResult::Returned(2));
+        drop(callee);
+        assert_eq!(dropped(), 2);
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    #[should_panic(expected = "called a finished callee")]
+    fn call_finished() {
+        fn identity(_: &mut Caller, data: usize) -> usize {
+            data
+        }
+
+        let mut callee = Callee::new(ProtectedFixedSizeStack::default(), identity);
+
+        callee.call(0);
+        callee.call(0);
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::fmt;
+use std::ptr;
+
+use compat::corosensei::{Coroutine, CoroutineResult, Yielder};
+use stack::{ProtectedFixedSizeStack, StackError};
+
+/// Passes every item of a producer to several consumers, each running on a stack of its own,
+/// by reference.
+///
+/// Every call to `step()` takes the next item out of the producer and resumes all subscribed
+/// consumers one after another, in the order they subscribed, with a reference to it. The
+/// item is dropped once the last consumer suspended again, before the producer is advanced
+/// once more. A consumer may thus keep arbitrary state in local variables, but can't keep
+/// references to an item beyond its next call to `Receiver::recv()`, which the borrow checker
+/// enforces.
+///
+/// The producer is any `Iterator`, for instance one returned by `iter::lazy_from_fn()` to
+/// produce the items on a stack of its own as well.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// use context::broadcast::Broadcast;
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let mut broadcast = Broadcast::new(vec![String::from("a"), String::from("b")]);
+///
+/// for name in &["x", "y"] {
+///     let log = log.clone();
+///     broadcast.subscribe(64 * 1024, move |receiver| {
+///         while let Some(item) = receiver.recv() {
+///             log.borrow_mut().push(format!("{}{}", name, item));
+///         }
+///     })
+///     .unwrap();
+/// }
+///
+/// broadcast.run();
+/// assert_eq!(*log.borrow(), ["xa", "ya", "xb", "yb"]);
+/// ```
+pub struct Broadcast<T> {
+    producer: Box<dyn Iterator<Item = T>>,
+    // The consumers which haven't returned yet, in the order they subscribed.
+    consumers: Vec<Coroutine<*const T, (), ()>>,
+    done: bool,
+}
+
+impl<T: 'static> Broadcast<T> {
+    /// Creates a broadcast of the items of `producer` without any consumers.
+    pub fn new<I>(producer: I) -> Broadcast<T>
+        where I: IntoIterator<Item = T>,
+              I::IntoIter: 'static
+    {
+        Broadcast {
+            producer: Box::new(producer.into_iter()),
+            consumers: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Subscribes a consumer executing `f` on a `ProtectedFixedSizeStack` of **at least**
+    /// `stack_size` bytes, which reads the items using its `Receiver`.
+    ///
+    /// `f` doesn't run until the next item is broadcast and only receives the items broadcast
+    /// from then on. The consumer is unsubscribed once `f` returns, whether it received all
+    /// items or not. Dropping the `Broadcast` unwinds all consumers which haven't returned yet,
+    /// like dropping a `Coroutine` does.
+    pub fn subscribe<F>(&mut self, stack_size: usize, f: F) -> Result<(), StackError>
+        where F: FnOnce(&mut Receiver<T>) + 'static
+    {
+        let stack = ProtectedFixedSizeStack::new(stack_size)?;
+
+        self.consumers.push(Coroutine::with_stack(stack, move |yielder, item| {
+            f(&mut Receiver {
+                yielder,
+                first: Some(item),
+            })
+        }));
+
+        Ok(())
+    }
+
+    /// Broadcasts the next item of the producer to all consumers and returns whether there was
+    /// one.
+    ///
+    /// Once the producer is exhausted the consumers are resumed a last time, with
+    /// `Receiver::recv()` returning `None`, which lets them return.
+    ///
+    /// # Panics
+    ///
+    /// If a consumer panics, the panic is propagated to the caller of this method.
+    pub fn step(&mut self) -> bool {
+        if self.done {
+            return false;
+        }
+
+        match self.producer.next() {
+            Some(item) => {
+                self.deliver(&item);
+                true
+            },
+            None => {
+                self.done = true;
+                self.deliver(ptr::null());
+                false
+            },
+        }
+    }
+
+    /// Broadcasts all remaining items of the producer.
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// Returns the number of consumers which haven't returned yet.
+    pub fn subscribers(&self) -> usize {
+        self.consumers.len()
+    }
+
+    // Resumes every consumer with `item`, which stays alive until this method returns, and
+    // unsubscribes the ones which returned.
+    fn deliver(&mut self, item: *const T) {
+        self.consumers.retain_mut(|consumer| {
+            matches!(consumer.resume(item), CoroutineResult::Yield(()))
+        });
+    }
+}
+
+impl<T> fmt::Debug for Broadcast<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Broadcast")
+            .field("subscribers", &self.consumers.len())
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+/// The items of a `Broadcast`, as read by a consumer.
+pub struct Receiver<'a, T: 'a> {
+    yielder: &'a Yielder<*const T, ()>,
+    // The item the consumer was started with, until it's received.
+    first: Option<*const T>,
+}
+
+impl<'a, T> Receiver<'a, T> {
+    /// Returns a reference to the next item of the `Broadcast`, or `None` once the producer
+    /// is exhausted.
+    ///
+    /// Unless the consumer just started, this suspends it until the item was passed to all
+    /// other consumers and the next one was produced.
+    pub fn recv(&mut self) -> Option<&T> {
+        let item = match self.first.take() {
+            Some(item) => item,
+            None => self.yielder.suspend(()),
+        };
+
+        if item.is_null() {
+            // Suspending again after the end would never be resumed.
+            self.first = Some(item);
+            None
+        } else {
+            // The item is alive until the consumer suspends, which requires `&mut self`.
+            Some(unsafe { &*item })
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for Receiver<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Receiver").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use iter;
+    use super::*;
+
+    #[test]
+    fn switch_order() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let l = log.clone();
+        let producer = iter::lazy_from_fn(64 * 1024, move |yielder| {
+            for n in 0..2 {
+                l.borrow_mut().push(format!("produce {}", n));
+                yielder.suspend(n);
+            }
+        });
+        let mut broadcast = Broadcast::new(producer.unwrap());
+
+        for name in &["a", "b"] {
+            let log = log.clone();
+            broadcast.subscribe(64 * 1024, move |receiver| {
+                while let Some(n) = receiver.recv() {
+                    log.borrow_mut().push(format!("{} {}", name, n));
+                }
+                log.borrow_mut().push(format!("{} end", name));
+            })
+            .unwrap();
+        }
+
+        broadcast.run();
+        assert_eq!(*log.borrow(),
+                   ["produce 0", "a 0", "b 0", "produce 1", "a 1", "b 1", "a end", "b end"]);
+        assert_eq!(broadcast.subscribers(), 0);
+        assert!(!broadcast.step());
+    }
+
+    #[test]
+    fn unsubscribe() {
+        let mut broadcast = Broadcast::new(0..);
+
+        // Returns as soon as it received the second item.
+        broadcast.subscribe(64 * 1024, |receiver| {
+            receiver.recv();
+            receiver.recv();
+        })
+        .unwrap();
+        broadcast.subscribe(64 * 1024, |receiver| {
+            while receiver.recv().is_some() {}
+        })
+        .unwrap();
+
+        assert!(broadcast.step());
+        assert_eq!(broadcast.subscribers(), 2);
+        assert!(broadcast.step());
+        assert_eq!(broadcast.subscribers(), 1);
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use compat::corosensei::{Coroutine, Yielder};
+use stack::{ProtectedFixedSizeStack, StackError};
+
+struct Slot<Input, Yield, Return> {
+    coroutine: Coroutine<Input, Yield, Return>,
+    last_used: u64,
+}
+
+/// A map from keys to suspended coroutines, holding at most a fixed number of them.
+///
+/// Inserting a coroutine into a full cache evicts the least recently used one: It is cleaned up
+/// using `Coroutine::force_unwind()` and its `ProtectedFixedSizeStack` is reused for the new
+/// coroutine instead of being deallocated. This keeps the memory used for stacks bounded by
+/// `capacity` times the stack size, however many distinct keys are used, except for the stacks
+/// leaked by `UnwindStrategy::Deferred`, which are replaced by new ones.
+///
+/// Finding the least recently used coroutine scans the whole cache, which is meant for small
+/// capacities.
+///
+/// # Examples
+///
+/// ```
+/// use context::cache::GeneratorCache;
+///
+/// let mut cache = GeneratorCache::new(2, 64 * 1024);
+///
+/// for name in &["header", "footer", "header", "body"] {
+///     let renderer = cache.get_or_insert_with(name.to_string(), move |yielder, mut n: u32| {
+///         loop {
+///             n = yielder.suspend(n + 1);
+///         }
+///     });
+///
+///     renderer.unwrap().resume(0);
+/// }
+///
+/// // "footer" was used least recently and has been evicted for "body".
+/// assert!(cache.contains_key("header"));
+/// assert!(!cache.contains_key("footer"));
+/// assert_eq!(cache.evictions(), 1);
+/// ```
+pub struct GeneratorCache<K, Input, Yield, Return> {
+    slots: HashMap<K, Slot<Input, Yield, Return>>,
+    capacity: usize,
+    stack_size: usize,
+    clock: u64,
+    evictions: u64,
+}
+
+impl<K, Input, Yield, Return> GeneratorCache<K, Input, Yield, Return>
+    where K: Hash + Eq + Clone
+{
+    /// Creates an empty cache holding up to `capacity` coroutines, each running on a
+    /// `ProtectedFixedSizeStack` of **at least** `stack_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize, stack_size: usize) -> Self {
+        assert!(capacity > 0, "the capacity of a GeneratorCache must not be 0");
+
+        GeneratorCache {
+            slots: HashMap::new(),
+            capacity,
+            stack_size,
+            clock: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Returns the coroutine cached for `key`, creating one executing `f` if there is none,
+    /// and marks it as the most recently used one.
+    ///
+    /// A cached coroutine which has finished is replaced like a missing one. If the cache is
+    /// full, the least recently used coroutine is evicted to make room for the new one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a stack for the new coroutine couldn't be allocated, in which case
+    /// no coroutine is cached for `key` anymore.
+    pub fn get_or_insert_with<F>(&mut self,
+                                 key: K,
+                                 f: F)
+                                 -> Result<&mut Coroutine<Input, Yield, Return>, StackError>
+        where F: FnOnce(&Yielder<Input, Yield>, Input) -> Return + 'static
+    {
+        let stack = match self.slots.get(&key) {
+            Some(slot) if !slot.coroutine.done() => None,
+            Some(_) => self.slots.remove(&key).and_then(|slot| slot.coroutine.into_stack()),
+            None if self.slots.len() >= self.capacity => self.evict(),
+            None => None,
+        };
+
+        let stack = match stack {
+            None if !self.slots.contains_key(&key) => {
+                Some(ProtectedFixedSizeStack::new(self.stack_size)?)
+            },
+            stack => stack,
+        };
+
+        self.clock += 1;
+        let clock = self.clock;
+        let slot = self.slots.entry(key).or_insert_with(move || {
+            Slot {
+                coroutine: Coroutine::with_stack(stack.expect("no stack for a new coroutine"), f),
+                last_used: 0,
+            }
+        });
+
+        slot.last_used = clock;
+        Ok(&mut slot.coroutine)
+    }
+
+    /// Returns the coroutine cached for `key`, if any, and marks it as the most recently
+    /// used one.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&mut Coroutine<Input, Yield, Return>>
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized
+    {
+        self.clock += 1;
+        let clock = self.clock;
+
+        self.slots.get_mut(key).map(|slot| {
+            slot.last_used = clock;
+            &mut slot.coroutine
+        })
+    }
+
+    /// Returns whether a coroutine is cached for `key`, without marking it as used.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized
+    {
+        self.slots.contains_key(key)
+    }
+
+    /// Removes the coroutine cached for `key` from the cache and returns it.
+    ///
+    /// The coroutine is cleaned up once it's dropped, as usual.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Coroutine<Input, Yield, Return>>
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized
+    {
+        self.slots.remove(key).map(|slot| slot.coroutine)
+    }
+
+    /// Returns the number of cached coroutines.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns whether no coroutine is cached.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Returns the maximum number of cached coroutines.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of coroutines evicted so far to make room for new ones.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    // Cleans up the least recently used coroutine and returns its stack, unless it was leaked.
+    fn evict(&mut self) -> Option<ProtectedFixedSizeStack> {
+        let victim = self.slots
+            .iter()
+            .min_by_key(|&(_, slot)| slot.last_used)
+            .map(|(key, _)| key.clone())?;
+
+        let slot = self.slots.remove(&victim)?;
+        self.evictions += 1;
+        slot.coroutine.into_stack()
+    }
+}
+
+impl<K, Input, Yield, Return> fmt::Debug for GeneratorCache<K, Input, Yield, Return> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GeneratorCache")
+            .field("len", &self.slots.len())
+            .field("capacity", &self.capacity)
+            .field("evictions", &self.evictions)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use compat::corosensei::CoroutineResult;
+    #[cfg(feature = "current")]
+    use current;
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cleaned_up = Rc::new(Cell::new(0));
+        let mut cache = GeneratorCache::new(2, 64 * 1024);
+
+        for key in &[1, 2, 1, 3] {
+            let cleaned_up = cleaned_up.clone();
+            let coroutine = cache.get_or_insert_with(*key, move |yielder, ()| {
+                yielder.defer(move || cleaned_up.set(cleaned_up.get() + 1));
+                for resumes in 1.. {
+                    yielder.suspend(resumes);
+                }
+            });
+
+            coroutine.unwrap().resume(());
+        }
+
+        assert_eq!(cleaned_up.get(), 1);
+        assert_eq!(cache.evictions(), 1);
+        assert!(!cache.contains_key(&2));
+        assert_eq!(cache.get(&1).unwrap().resume(()), CoroutineResult::Yield(3));
+
+        drop(cache);
+        assert_eq!(cleaned_up.get(), 3);
+    }
+
+    #[test]
+    #[cfg(all(panic = "unwind", feature = "current"))]
+    fn recycles_stacks() {
+        let mut cache = GeneratorCache::new(1, 64 * 1024);
+        let mut bottoms = Vec::new();
+
+        for key in 0..3 {
+            let coroutine = cache.get_or_insert_with(key, |yielder, ()| {
+                let bottom = current::current_stack_bounds().unwrap().bottom() as usize;
+                loop {
+                    yielder.suspend(bottom);
+                }
+            });
+
+            bottoms.push(coroutine.unwrap().resume(()).as_yield().unwrap());
+        }
+
+        assert_eq!(cache.len(), 1);
+        assert!(bottoms.iter().all(|&bottom| bottom == bottoms[0]));
+    }
+
+    #[test]
+    fn replaces_finished() {
+        let mut cache: GeneratorCache<_, u32, (), u32> = GeneratorCache::new(1, 64 * 1024);
+
+        let coroutine = cache.get_or_insert_with("once", |_, n: u32| n).unwrap();
+        assert_eq!(coroutine.resume(1), CoroutineResult::Return(1));
+
+        let coroutine = cache.get_or_insert_with("once", |_, n: u32| n + 1).unwrap();
+        assert_eq!(coroutine.resume(1), CoroutineResult::Return(2));
+        assert_eq!(cache.evictions(), 0);
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::process;
+use std::ptr;
+
+/// Identifies a context-scoped value of type `T`, which is bound using `scoped()`.
+///
+/// Keys are compared by address and must thus be `static`s.
+///
+/// # Examples
+///
+/// ```
+/// use context::cls::{self, Key};
+///
+/// static REQUEST_ID: Key<u64> = Key::new();
+///
+/// cls::scoped(&REQUEST_ID, 42, || {
+///     assert_eq!(cls::get(&REQUEST_ID), Some(42));
+/// });
+/// assert_eq!(cls::get(&REQUEST_ID), None);
+/// ```
+pub struct Key<T: 'static> {
+    // Keeps the key from being zero-sized, which gives every static its own address.
+    _byte: u8,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: 'static> Key<T> {
+    /// Creates a new key.
+    pub const fn new() -> Key<T> {
+        Key {
+            _byte: 0,
+            marker: PhantomData,
+        }
+    }
+
+    fn id(&'static self) -> usize {
+        self as *const Key<T> as usize
+    }
+}
+
+impl<T: 'static> Default for Key<T> {
+    fn default() -> Key<T> {
+        Key::new()
+    }
+}
+
+// The values bound by a thread or coroutine, innermost last, as pairs of the address of their key
+// and of the value, which lives in the frame of the call to scoped() binding it.
+pub(crate) struct Bindings {
+    entries: Vec<(usize, *const ())>,
+    // The bindings of the resumer while the coroutine runs, which are visible unless shadowed.
+    parent: *const Bindings,
+}
+
+impl Bindings {
+    pub(crate) const fn new() -> Bindings {
+        Bindings {
+            entries: Vec::new(),
+            parent: ptr::null(),
+        }
+    }
+}
+
+thread_local! {
+    static ROOT: UnsafeCell<Bindings> = const { UnsafeCell::new(Bindings::new()) };
+    // The bindings of the innermost coroutine running on this thread, or null outside of one.
+    static CURRENT: Cell<*mut Bindings> = const { Cell::new(ptr::null_mut()) };
+}
+
+fn current() -> *mut Bindings {
+    let current = CURRENT.with(Cell::get);
+
+    if current.is_null() {
+        // The root bindings are gone once the thread-locals of an exiting thread were destroyed,
+        // while coroutines dropped by the remaining destructors still enter bindings of their own.
+        ROOT.try_with(UnsafeCell::get).unwrap_or(ptr::null_mut())
+    } else {
+        current
+    }
+}
+
+/// Binds `value` to `key` while `f` runs and returns the result of `f`.
+///
+/// The value is visible to `get()` and `with()` within the context calling this function,
+/// including coroutines it resumes, and shadows any value bound to `key` before. It survives
+/// suspensions of the current coroutine: While it's suspended the value is hidden from its
+/// resumer, and visible again once it's resumed, even by another resumer. This gives coroutines
+/// the equivalent of scoped thread-locals, for instance to propagate a request context.
+///
+/// Only `compat::corosensei` coroutines have bindings of their own. Code running in contexts
+/// switched to by other means, like an `asymmetric::Callee`, shares the bindings of the
+/// coroutine or thread resuming it, so its values stay visible there while it's suspended.
+///
+/// # Aborts
+///
+/// Aborts the process if `f` returns or unwinds while the bindings `value` was added to aren't
+/// visible, which only happens if a context bound it and is then resumed by another coroutine
+/// or thread than the one it was suspended in, since `value` would otherwise stay bound after
+/// it's gone.
+pub fn scoped<T, R, F>(key: &'static Key<T>, value: T, f: F) -> R
+    where F: FnOnce() -> R
+{
+    struct Pop((usize, *const ()));
+
+    impl Drop for Pop {
+        fn drop(&mut self) {
+            // Contexts other than coroutines may suspend with values bound in the bindings of
+            // their resumer, so the entry isn't necessarily the last one, or in the current
+            // bindings themselves.
+            let mut bindings = current();
+
+            while !bindings.is_null() {
+                let bindings_mut = unsafe { &mut *bindings };
+
+                if let Some(i) = bindings_mut.entries.iter().rposition(|entry| *entry == self.0) {
+                    bindings_mut.entries.remove(i);
+                    return;
+                }
+
+                bindings = bindings_mut.parent as *mut Bindings;
+            }
+
+            eprintln!("context: a value bound by cls::scoped() outlived its frame");
+            process::abort();
+        }
+    }
+
+    let bindings = current();
+    if bindings.is_null() {
+        return f();
+    }
+
+    let entry = (key.id(), &value as *const T as *const ());
+    unsafe { (*bindings).entries.push(entry) };
+    let _pop = Pop(entry);
+    f()
+}
+
+/// Calls `f` with the innermost value bound to `key` visible to the current context,
+/// or `None` if there is none, and returns its result.
+pub fn with<T, R, F>(key: &'static Key<T>, f: F) -> R
+    where F: FnOnce(Option<&T>) -> R
+{
+    let id = key.id();
+    let mut bindings = current() as *const Bindings;
+
+    while !bindings.is_null() {
+        let bindings_ref = unsafe { &*bindings };
+
+        if let Some(&(_, value)) = bindings_ref.entries.iter().rev().find(|entry| entry.0 == id) {
+            return f(Some(unsafe { &*(value as *const T) }));
+        }
+
+        bindings = bindings_ref.parent;
+    }
+
+    f(None)
+}
+
+/// Returns a clone of the innermost value bound to `key` visible to the current context,
+/// or `None` if there is none.
+pub fn get<T: Clone>(key: &'static Key<T>) -> Option<T> {
+    with(key, |value| value.cloned())
+}
+
+// Makes `bindings` current until the returned guard is dropped, with the bindings of the
+// caller as their parent. The guard restores the previous state like `current::enter()`.
+pub(crate) fn enter(bindings: &mut Bindings) -> Enter {
+    bindings.parent = current();
+    Enter(CURRENT.with(|current| current.replace(bindings)))
+}
+
+pub(crate) struct Enter(*mut Bindings);
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        let _ = CURRENT.try_with(|current| current.set(self.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::thread;
+
+    use asymmetric::{Callee, Caller};
+    use compat::corosensei::{Coroutine, CoroutineResult};
+    use stack::ProtectedFixedSizeStack;
+    use super::*;
+
+    static REQUEST: Key<u32> = Key::new();
+    static USER: Key<&'static str> = Key::new();
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(get(&REQUEST), None);
+
+        scoped(&REQUEST, 1, || {
+            scoped(&REQUEST, 2, || assert_eq!(get(&REQUEST), Some(2)));
+            assert_eq!(get(&REQUEST), Some(1));
+            assert_eq!(get(&USER), None);
+        });
+
+        assert_eq!(get(&REQUEST), None);
+    }
+
+    #[test]
+    fn survives_suspension() {
+        let mut coroutine = Coroutine::new(|yielder, ()| {
+            scoped(&REQUEST, 1, || {
+                yielder.suspend(get(&REQUEST));
+                get(&REQUEST)
+            })
+        });
+
+        scoped(&REQUEST, 7, || {
+            assert_eq!(coroutine.resume(()), CoroutineResult::Yield(Some(1)));
+            assert_eq!(get(&REQUEST), Some(7));
+        });
+
+        assert_eq!(get(&REQUEST), None);
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(Some(1)));
+    }
+
+    #[test]
+    fn inherited_from_resumer() {
+        let mut coroutine = Coroutine::new(|yielder, ()| {
+            yielder.suspend(get(&USER));
+            scoped(&USER, "inner", || get(&USER))
+        });
+
+        scoped(&USER, "outer", || {
+            assert_eq!(coroutine.resume(()), CoroutineResult::Yield(Some("outer")));
+        });
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(Some("inner")));
+        assert_eq!(get(&USER), None);
+    }
+
+    #[test]
+    fn callee_shares_bindings() {
+        static NAME: Key<String> = Key::new();
+
+        fn bind_and_suspend(caller: &mut Caller, _: usize) -> usize {
+            scoped(&USER, "callee", || caller.suspend(0))
+        }
+
+        let mut callee = Callee::new(ProtectedFixedSizeStack::default(), bind_and_suspend);
+
+        // The callee suspends with its value bound on top of the one bound here.
+        scoped(&NAME, String::from("aaaa"), || {
+            callee.call(0);
+            assert_eq!(get(&NAME).as_deref(), Some("aaaa"));
+        });
+
+        assert_eq!(get(&NAME), None);
+        assert_eq!(get(&USER), Some("callee"));
+
+        callee.call(0);
+        assert_eq!(get(&USER), None);
+    }
+
+    #[test]
+    fn dropped_after_root_bindings() {
+        thread_local! {
+            static SUSPENDED: RefCell<Option<Coroutine<(), (), ()>>> = const { RefCell::new(None) };
+        }
+
+        thread::spawn(|| {
+            // Thread-locals are destroyed in the reverse order of their first use, so the root
+            // bindings are gone by the time the coroutine is dropped.
+            SUSPENDED.with(|_| ());
+
+            let mut coroutine = Coroutine::new(|yielder, ()| {
+                scoped(&REQUEST, 1, || yielder.suspend(()));
+            });
+            scoped(&USER, "alice", || coroutine.resume(()));
+            SUSPENDED.with(|suspended| *suspended.borrow_mut() = Some(coroutine));
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,1975 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "coroutine-introspection")]
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+#[cfg(feature = "coroutine-introspection")]
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "coroutine-panic-hooks")]
+use std::panic::PanicHookInfo;
+use std::ptr;
+#[cfg(feature = "coroutine-introspection")]
+use std::process;
+#[cfg(feature = "coroutine-introspection")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "coroutine-introspection")]
+use std::sync::{Mutex, MutexGuard, OnceLock};
+#[cfg(feature = "coroutine-profiling")]
+use std::sync::Arc;
+use std::thread;
+#[cfg(feature = "coroutine-profiling")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "coroutine-introspection")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "cls")]
+use cls;
+use context::{Context, Transfer};
+#[cfg(feature = "current")]
+use current;
+#[cfg(feature = "coroutine-introspection")]
+use stack::{GrowthDirection, Stack};
+use stack::{ProtectedFixedSizeStack, StackOwner};
+#[cfg(feature = "coroutine-profiling")]
+use trace;
+
+/// The stack type used by `Coroutine::new()`.
+pub type DefaultStack = ProtectedFixedSizeStack;
+
+/// The value returned by `Coroutine::resume()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoroutineResult<Yield, Return> {
+    /// The coroutine suspended itself using `Yielder::suspend()`.
+    Yield(Yield),
+
+    /// The coroutine finished by returning from its function.
+    Return(Return),
+}
+
+impl<Yield, Return> CoroutineResult<Yield, Return> {
+    /// Returns the yielded value, or `None` if the coroutine returned.
+    pub fn as_yield(self) -> Option<Yield> {
+        match self {
+            CoroutineResult::Yield(val) => Some(val),
+            CoroutineResult::Return(_) => None,
+        }
+    }
+
+    /// Returns the returned value, or `None` if the coroutine yielded.
+    pub fn as_return(self) -> Option<Return> {
+        match self {
+            CoroutineResult::Yield(_) => None,
+            CoroutineResult::Return(val) => Some(val),
+        }
+    }
+}
+
+/// The live region of the stack of a suspended coroutine, as passed to a `StackHook`.
+///
+/// Everything the coroutine can still reference from its stack, including the registers saved
+/// by the context switch, lies between `low` and `high`. Scanning this region conservatively
+/// therefore finds all pointers held by the coroutine.
+#[cfg(feature = "coroutine-introspection")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiveStack {
+    /// The address of the register block saved by the context switch.
+    pub registers: *const c_void,
+
+    /// The lowest address of the live region.
+    pub low: *const c_void,
+
+    /// The address one past the highest byte of the live region.
+    pub high: *const c_void,
+}
+
+/// The reason a `StackHook` is invoked.
+#[cfg(feature = "coroutine-introspection")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackEvent {
+    /// The coroutine has just been suspended by `Yielder::suspend()`.
+    Suspended,
+
+    /// The coroutine is about to be resumed by `Coroutine::resume()`.
+    Resuming,
+}
+
+/// A function invoked with the live region of a coroutine's stack whenever it's suspended or
+/// resumed, registered using `Coroutine::set_stack_hook()`.
+///
+/// This allows language runtimes to enumerate roots living on suspended stacks, for instance
+/// for a conservative garbage collector. The hook runs on the stack of the owner of the
+/// coroutine, so the region passed to it isn't in use while it runs.
+#[cfg(feature = "coroutine-introspection")]
+pub type StackHook = fn(StackEvent, &LiveStack);
+
+/// The coroutine whose stack exceeded its soft quota, as passed to a `QuotaHook`.
+#[cfg(feature = "coroutine-introspection")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    /// The soft quota set using `Coroutine::set_stack_quota()`.
+    pub quota: usize,
+
+    /// The high-water mark of the coroutine's stack, as returned by `Stack::resident_size()`.
+    pub used: usize,
+
+    /// The size of the coroutine's stack.
+    pub size: usize,
+
+    /// The profile tag of the coroutine, if any.
+    #[cfg(feature = "coroutine-profiling")]
+    pub profile_tag: Option<&'static str>,
+
+    /// The identity of the coroutine.
+    pub identity: u64,
+}
+
+/// A function invoked once the stack of a coroutine exceeded its soft quota,
+/// registered using `Coroutine::set_stack_quota()`.
+#[cfg(feature = "coroutine-introspection")]
+pub type QuotaHook = fn(&QuotaExceeded);
+
+/// A panic hook applying only while a particular coroutine runs,
+/// registered using `Coroutine::set_panic_hook()`.
+#[cfg(feature = "coroutine-panic-hooks")]
+pub type PanicHook = Box<dyn Fn(&PanicHookInfo) + 'static>;
+
+#[cfg(feature = "coroutine-panic-hooks")]
+type RawPanicHook = *const (dyn Fn(&PanicHookInfo) + 'static);
+
+#[cfg(feature = "coroutine-panic-hooks")]
+thread_local! {
+    // The panic hook of the innermost coroutine with one which runs on this thread.
+    static PANIC_HOOK: Cell<Option<RawPanicHook>> = const { Cell::new(None) };
+}
+
+/// Calls the `PanicHook` of the innermost running coroutine with one, returning `false`
+/// if there is none.
+///
+/// This crate never installs a process-wide panic hook by itself. Applications using
+/// `Coroutine::set_panic_hook()` install one which dispatches to the coroutine hooks through
+/// this function, and decide what happens with the remaining panics.
+///
+/// # Examples
+///
+/// ```
+/// use std::panic;
+///
+/// use context::compat::corosensei::dispatch_panic_hook;
+///
+/// let previous = panic::take_hook();
+///
+/// panic::set_hook(Box::new(move |info| {
+///     if !dispatch_panic_hook(info) {
+///         previous(info);
+///     }
+/// }));
+/// ```
+#[cfg(feature = "coroutine-panic-hooks")]
+pub fn dispatch_panic_hook(info: &PanicHookInfo) -> bool {
+    match PANIC_HOOK.try_with(Cell::get).ok().and_then(|hook| hook) {
+        Some(hook) => {
+            unsafe { (*hook)(info) };
+            true
+        }
+        None => false,
+    }
+}
+
+// Makes `hook` the panic hook of the current thread until the returned guard is dropped.
+// Without a hook of its own the coroutine inherits the one of its resumer.
+#[cfg(feature = "coroutine-panic-hooks")]
+fn enter_panic_hook(hook: &Option<PanicHook>) -> PanicHookGuard {
+    let previous = PANIC_HOOK.try_with(|current| {
+        let hook = hook.as_ref().map(|hook| &**hook as RawPanicHook).or(current.get());
+        current.replace(hook)
+    });
+
+    PanicHookGuard(previous.unwrap_or(None))
+}
+
+#[cfg(feature = "coroutine-panic-hooks")]
+struct PanicHookGuard(Option<RawPanicHook>);
+
+#[cfg(feature = "coroutine-panic-hooks")]
+impl Drop for PanicHookGuard {
+    fn drop(&mut self) {
+        let _ = PANIC_HOOK.try_with(|current| current.set(self.0));
+    }
+}
+
+/// Suspends the coroutine at a named yield point using `Yielder::suspend_at()`.
+///
+/// The location of the invocation is recorded alongside the `name`, which is reported by
+/// `Coroutine::parked_at()` and `parked_yield_points()` while the coroutine is suspended there.
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate context;
+///
+/// use context::compat::corosensei::Coroutine;
+///
+/// # fn main() {
+/// let mut coroutine: Coroutine<(), (), ()> = Coroutine::new(|yielder, ()| {
+///     yield_point!(yielder, "db_read", ());
+/// });
+///
+/// coroutine.resume(());
+/// assert_eq!(coroutine.parked_at().unwrap().name, "db_read");
+/// # }
+/// ```
+#[cfg(feature = "coroutine-introspection")]
+#[macro_export]
+macro_rules! yield_point {
+    ($yielder:expr, $name:expr, $val:expr) => {
+        $yielder.suspend_at($crate::compat::corosensei::YieldPoint {
+                                name: $name,
+                                file: file!(),
+                                line: line!(),
+                            },
+                            $val)
+    };
+}
+
+/// A named point a coroutine can be suspended at, usually created by `yield_point!`.
+#[cfg(feature = "coroutine-introspection")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct YieldPoint {
+    /// The name describing what the coroutine is waiting for, e.g. `"db_read"`.
+    pub name: &'static str,
+
+    /// The source file containing the yield point.
+    pub file: &'static str,
+
+    /// The line in `file` containing the yield point.
+    pub line: u32,
+}
+
+// The yield points all suspended coroutines on all threads are parked at,
+// keyed by the address of their Yielder. Only touched by coroutines using `suspend_at()`.
+#[cfg(feature = "coroutine-introspection")]
+static PARKED: Mutex<BTreeMap<usize, YieldPoint>> = Mutex::new(BTreeMap::new());
+
+#[cfg(feature = "coroutine-introspection")]
+fn parked() -> MutexGuard<'static, BTreeMap<usize, YieldPoint>> {
+    PARKED.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// Returns whether a coroutine whose Yielder lives in low..high is parked at a yield point.
+#[cfg(all(feature = "coroutine-introspection", feature = "current"))]
+pub(crate) fn is_parked_within(low: usize, high: usize) -> bool {
+    parked().range(low..high).next().is_some()
+}
+
+/// Returns the `YieldPoint` of every coroutine in the process which is currently
+/// suspended at one, in no particular order.
+///
+/// This allows auditing what a large number of coroutines are waiting on, for instance by
+/// counting the occurrences of each name. Coroutines suspended using `Yielder::suspend()`
+/// are not included.
+#[cfg(feature = "coroutine-introspection")]
+pub fn parked_yield_points() -> Vec<YieldPoint> {
+    parked().values().cloned().collect()
+}
+
+#[cfg(feature = "coroutine-profiling")]
+const RUN_LENGTH_BUCKETS: usize = 65;
+
+/// A log-bucketed histogram of the time coroutines ran between being resumed and suspending
+/// themselves or finishing, recorded per tag set using `Coroutine::set_profile_tag()`.
+///
+/// This allows finding out which kinds of coroutines hog the thread resuming them,
+/// without attributing their time to the caller as external profilers do.
+#[cfg(feature = "coroutine-profiling")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunLengths {
+    buckets: [u64; RUN_LENGTH_BUCKETS],
+}
+
+#[cfg(feature = "coroutine-profiling")]
+impl RunLengths {
+    fn new() -> RunLengths {
+        RunLengths { buckets: [0; RUN_LENGTH_BUCKETS] }
+    }
+
+    /// Returns the number of runs counted in each bucket.
+    ///
+    /// Bucket 0 counts the runs shorter than a nanosecond and bucket `i`
+    /// the ones lasting from `2^(i - 1)` up to `2^i - 1` nanoseconds.
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Returns the total number of runs.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    fn record(&mut self, run: Duration) {
+        let nanos = run.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[64 - nanos.leading_zeros() as usize] += 1;
+    }
+
+    fn merge(&mut self, other: &RunLengths) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+    }
+}
+
+#[cfg(feature = "coroutine-profiling")]
+type RunLengthsByTag = BTreeMap<&'static str, RunLengths>;
+
+#[cfg(feature = "coroutine-profiling")]
+fn merge_run_lengths(into: &mut RunLengthsByTag, from: &RunLengthsByTag) {
+    for (&tag, lengths) in from {
+        into.entry(tag).or_insert_with(RunLengths::new).merge(lengths);
+    }
+}
+
+// The run lengths are recorded into a map owned by the resuming thread, whose lock is only
+// contended while they are read. The maps of running threads are merged on read and the ones
+// of exited threads into `retired`.
+#[cfg(feature = "coroutine-profiling")]
+struct RunLengthsRegistry {
+    threads: Vec<Arc<Mutex<RunLengthsByTag>>>,
+    retired: RunLengthsByTag,
+}
+
+#[cfg(feature = "coroutine-profiling")]
+static RUN_LENGTHS: Mutex<RunLengthsRegistry> = Mutex::new(RunLengthsRegistry {
+    threads: Vec::new(),
+    retired: BTreeMap::new(),
+});
+
+#[cfg(feature = "coroutine-profiling")]
+fn run_lengths_registry() -> MutexGuard<'static, RunLengthsRegistry> {
+    RUN_LENGTHS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(feature = "coroutine-profiling")]
+fn lock_run_lengths<'a>(lengths: &'a Mutex<RunLengthsByTag>) -> MutexGuard<'a, RunLengthsByTag> {
+    lengths.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(feature = "coroutine-profiling")]
+struct ThreadRunLengths(Arc<Mutex<RunLengthsByTag>>);
+
+#[cfg(feature = "coroutine-profiling")]
+impl ThreadRunLengths {
+    fn new() -> ThreadRunLengths {
+        let lengths = Arc::new(Mutex::new(BTreeMap::new()));
+        run_lengths_registry().threads.push(lengths.clone());
+        ThreadRunLengths(lengths)
+    }
+}
+
+#[cfg(feature = "coroutine-profiling")]
+impl Drop for ThreadRunLengths {
+    fn drop(&mut self) {
+        let mut registry = run_lengths_registry();
+        registry.threads.retain(|lengths| !Arc::ptr_eq(lengths, &self.0));
+        merge_run_lengths(&mut registry.retired, &lock_run_lengths(&self.0));
+    }
+}
+
+#[cfg(feature = "coroutine-profiling")]
+thread_local! {
+    static THREAD_RUN_LENGTHS: ThreadRunLengths = ThreadRunLengths::new();
+}
+
+#[cfg(feature = "coroutine-profiling")]
+fn record_run_length(tag: &'static str, run: Duration) {
+    let record = |lengths: &mut RunLengthsByTag| {
+        lengths.entry(tag).or_insert_with(RunLengths::new).record(run)
+    };
+
+    // Runs recorded while the thread exits go straight to the exited threads' total.
+    let recorded = THREAD_RUN_LENGTHS.try_with(|lengths| record(&mut lock_run_lengths(&lengths.0)));
+
+    if recorded.is_err() {
+        record(&mut run_lengths_registry().retired);
+    }
+}
+
+/// Returns the `RunLengths` recorded so far for every profile tag, ordered by tag.
+#[cfg(feature = "coroutine-profiling")]
+pub fn run_lengths() -> Vec<(&'static str, RunLengths)> {
+    let registry = run_lengths_registry();
+    let mut merged = registry.retired.clone();
+
+    for lengths in &registry.threads {
+        merge_run_lengths(&mut merged, &lock_run_lengths(lengths));
+    }
+
+    merged.into_iter().collect()
+}
+
+/// Discards the `RunLengths` recorded so far, for instance to start a new measurement period.
+#[cfg(feature = "coroutine-profiling")]
+pub fn reset_run_lengths() {
+    let mut registry = run_lengths_registry();
+    registry.retired.clear();
+
+    for lengths in &registry.threads {
+        lock_run_lengths(lengths).clear();
+    }
+}
+
+/// The reason why `Coroutine::try_resume()` failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ResumeErrorKind {
+    /// The coroutine has already finished.
+    Finished,
+    /// Resuming the coroutine would exceed the maximum nesting depth set using
+    /// `current::set_max_depth()`, which is contained.
+    #[cfg(feature = "current")]
+    DepthExceeded(usize),
+}
+
+/// The error returned by `Coroutine::try_resume()` if the coroutine couldn't be resumed.
+///
+/// Contains the `Input` which was passed to `try_resume()`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ResumeError<Input> {
+    input: Input,
+    kind: ResumeErrorKind,
+}
+
+impl<Input> ResumeError<Input> {
+    /// Returns the reason why the coroutine couldn't be resumed.
+    pub fn kind(&self) -> ResumeErrorKind {
+        self.kind
+    }
+
+    /// Returns the `Input` which was passed to `try_resume()`.
+    pub fn into_input(self) -> Input {
+        self.input
+    }
+}
+
+impl<Input> fmt::Debug for ResumeError<Input> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResumeError")
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Input> fmt::Display for ResumeError<Input> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ResumeErrorKind::Finished => f.write_str("attempted to resume a finished coroutine"),
+            #[cfg(feature = "current")]
+            ResumeErrorKind::DepthExceeded(max) => {
+                write!(f,
+                       "resuming the coroutine would exceed the maximum nesting depth of {} \
+                        set for this thread",
+                       max)
+            },
+        }
+    }
+}
+
+impl<Input> Error for ResumeError<Input> {}
+
+// The finalizer of SplitMix64, which is fixed here instead of using a std Hasher, whose
+// algorithm may change between Rust releases.
+#[cfg(feature = "coroutine-introspection")]
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// Derives the identity of a new coroutine from a process-wide generation counter, which keeps
+// reused stacks from repeating identities, the address of its stack, and an epoch drawn once per
+// process, which tells apart coroutines of different processes.
+#[cfg(feature = "coroutine-introspection")]
+fn new_identity(stack: &Stack) -> u64 {
+    static GENERATION: AtomicU64 = AtomicU64::new(0);
+    static PROCESS_EPOCH: OnceLock<u64> = OnceLock::new();
+
+    let epoch = *PROCESS_EPOCH.get_or_init(|| {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        mix(now.as_nanos() as u64 ^ (u64::from(process::id()) << 32))
+    });
+    let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+
+    mix(epoch ^ mix(generation ^ mix(stack.bottom() as u64)))
+}
+
+// The slots a resume() exchanges values through. They live on the stack of the resuming side
+// and a pointer to them is passed as the `data` of the `Transfer`. A `data` of 0 instead
+// requests the coroutine to unwind its stack.
+//
+// `Yielder` only knows about the input and yield types and thus accesses them through the
+// `Exchange` prefix, which is valid due to the `repr(C)` layout.
+#[repr(C)]
+struct Exchange<Input, Yield> {
+    #[cfg(feature = "coroutine-introspection")]
+    epoch: u64,
+    input: Option<Input>,
+    yielded: Option<Yield>,
+    #[cfg(feature = "coroutine-introspection")]
+    yield_point: Option<YieldPoint>,
+}
+
+#[repr(C)]
+struct Slots<Input, Yield, Return> {
+    exchange: Exchange<Input, Yield>,
+    returned: Option<thread::Result<Return>>,
+}
+
+// The payload used to unwind the stack of a coroutine.
+struct ForceUnwind;
+
+/// Determines how `Coroutine::force_unwind()` cleans up a coroutine which hasn't finished yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnwindStrategy {
+    /// Unwinds the stack of the coroutine by panicking, running the destructors of all values
+    /// living on it, followed by the functions registered using `Yielder::defer()`.
+    ///
+    /// This requires `panic = "unwind"` and aborts the process otherwise.
+    Panic,
+
+    /// Runs the functions registered using `Yielder::defer()` on top of the coroutine's stack
+    /// and abandons the stack afterwards, without unwinding it.
+    ///
+    /// The values living on the stack are leaked as if passed to `mem::forget()`,
+    /// so every resource which must be released has to be registered using `Yielder::defer()`.
+    /// The stack itself is leaked as well, instead of being deallocated or returned by
+    /// `Coroutine::into_stack()`, so the memory of values pinned on it is never reused without
+    /// dropping them. This is the only strategy available for builds using `panic = "abort"`.
+    Deferred,
+}
+
+impl Default for UnwindStrategy {
+    /// Returns `Panic` if the crate is built with `panic = "unwind"` and `Deferred` otherwise.
+    fn default() -> UnwindStrategy {
+        if cfg!(panic = "unwind") {
+            UnwindStrategy::Panic
+        } else {
+            UnwindStrategy::Deferred
+        }
+    }
+}
+
+/// A stackful coroutine mirroring `corosensei::Coroutine`.
+///
+/// The coroutine executes a closure on its own stack, which receives a `Yielder` to suspend
+/// itself with and the `Input` passed to the first call to `resume()`.
+/// Dropping a coroutine which has not finished yet cleans it up using `force_unwind()`, which
+/// by default unwinds its stack, thereby running the destructors of all values living on it.
+/// See `UnwindStrategy` for builds which can't unwind, which leak the stack instead.
+///
+/// The API of `corosensei` is extended by `try_resume()`, `Yielder::defer()` and
+/// `UnwindStrategy`. Everything else which makes resuming a coroutine more expensive is only
+/// compiled with a feature of its own:
+///
+/// - `coroutine-introspection`: the epoch and identity of a coroutine, yield points, as well as
+///   stack hooks and quotas.
+/// - `coroutine-profiling`: profile tags, run lengths and the `trace` module.
+/// - `coroutine-panic-hooks`: panic hooks applying while a coroutine runs.
+/// - `cls`: coroutine-local values bound using the `cls` module.
+/// - `current`: the `current` module and its limit on nested resumes.
+///
+/// Coroutines may create and resume other coroutines, to any depth. Dropping a suspended
+/// coroutine which owns suspended coroutines in turn unwinds them from the innermost one outwards,
+/// since unwinding a stack drops the coroutines living on it.
+///
+/// # Examples
+///
+/// ```
+/// use context::compat::corosensei::{Coroutine, CoroutineResult};
+///
+/// let mut coroutine = Coroutine::new(|yielder, input: usize| {
+///     let input = yielder.suspend(input + 1);
+///     input * 2
+/// });
+///
+/// assert_eq!(coroutine.resume(1), CoroutineResult::Yield(2));
+/// assert_eq!(coroutine.resume(5), CoroutineResult::Return(10));
+/// assert!(coroutine.done());
+/// ```
+pub struct Coroutine<Input, Yield, Return, S = DefaultStack>
+    where S: StackOwner
+{
+    stack: ManuallyDrop<S>,
+    // Set once the stack mustn't be dropped anymore, since it was abandoned with frames living on
+    // it or taken by into_stack().
+    leak_stack: bool,
+    context: Option<Context>,
+    // Lives on the coroutine's stack and is valid until the coroutine finishes.
+    yielder: *const Yielder<Input, Yield>,
+    started: bool,
+    unwind_strategy: UnwindStrategy,
+    #[cfg(feature = "coroutine-introspection")]
+    epoch: u64,
+    #[cfg(feature = "coroutine-introspection")]
+    identity: u64,
+    #[cfg(feature = "coroutine-introspection")]
+    stack_hook: Option<StackHook>,
+    #[cfg(feature = "coroutine-introspection")]
+    stack_quota: Option<(usize, QuotaHook)>,
+    #[cfg(feature = "coroutine-introspection")]
+    parked_at: Option<YieldPoint>,
+    #[cfg(feature = "coroutine-profiling")]
+    profile_tag: Option<&'static str>,
+    #[cfg(feature = "coroutine-panic-hooks")]
+    panic_hook: Option<PanicHook>,
+    #[cfg(feature = "cls")]
+    locals: cls::Bindings,
+    // Coroutines might capture non-Send values and are thus neither Send nor Sync.
+    marker: PhantomData<*mut (Input, Yield, Return)>,
+}
+
+impl<Input, Yield, Return> Coroutine<Input, Yield, Return, DefaultStack> {
+    /// Creates a new coroutine executing `f` on a newly allocated `DefaultStack`.
+    ///
+    /// `f` is not executed until the first call to `resume()`.
+    pub fn new<F>(f: F) -> Self
+        where F: FnOnce(&Yielder<Input, Yield>, Input) -> Return + 'static
+    {
+        Coroutine::with_stack(DefaultStack::default(), f)
+    }
+}
+
+impl<Input, Yield, Return, S> Coroutine<Input, Yield, Return, S>
+    where S: StackOwner
+{
+    /// Creates a new coroutine executing `f` on the given `stack`.
+    ///
+    /// `f` is not executed until the first call to `resume()`. The coroutine owns `stack`
+    /// until it's dropped or returned by `into_stack()`, which `StackOwner` ensures. A borrowed
+    /// stack is rejected, since it could be shared with another coroutine:
+    ///
+    /// ```compile_fail
+    /// use context::compat::corosensei::Coroutine;
+    /// use context::stack::{ProtectedFixedSizeStack, Stack};
+    ///
+    /// let stack = ProtectedFixedSizeStack::default();
+    /// let first = Coroutine::<(), (), (), &Stack>::with_stack(&stack, |_, ()| {});
+    /// ```
+    pub fn with_stack<F>(stack: S, f: F) -> Self
+        where F: FnOnce(&Yielder<Input, Yield>, Input) -> Return + 'static
+    {
+        unsafe { Coroutine::with_stack_unchecked(stack, f) }
+    }
+
+    // The caller must ensure that the coroutine doesn't outlive anything borrowed by `f`,
+    // which `ScopedCoroutine` does by tracking the lifetime of `f` in its type. Exclusive use
+    // of `stack` is guaranteed by `StackOwner`.
+    unsafe fn with_stack_unchecked<F>(stack: S, f: F) -> Self
+        where F: FnOnce(&Yielder<Input, Yield>, Input) -> Return
+    {
+        let mut f = Some(f);
+        let Transfer { context, data } = unsafe {
+            let context = Context::new(&stack, coroutine_function::<F, Input, Yield, Return>);
+
+            // Moves `f` onto the coroutine's stack, where it waits for the first resume().
+            context.resume(&mut f as *mut Option<F> as usize)
+        };
+
+        let yielder = data as *const Yielder<Input, Yield>;
+        #[cfg(feature = "coroutine-introspection")]
+        let identity = new_identity(&stack);
+        #[cfg(feature = "coroutine-introspection")]
+        unsafe { (*yielder).identity.set(identity) };
+
+        Coroutine {
+            stack: ManuallyDrop::new(stack),
+            leak_stack: false,
+            context: Some(context),
+            yielder,
+            started: false,
+            unwind_strategy: UnwindStrategy::default(),
+            #[cfg(feature = "coroutine-introspection")]
+            epoch: 0,
+            #[cfg(feature = "coroutine-introspection")]
+            identity,
+            #[cfg(feature = "coroutine-introspection")]
+            stack_hook: None,
+            #[cfg(feature = "coroutine-introspection")]
+            stack_quota: None,
+            #[cfg(feature = "coroutine-introspection")]
+            parked_at: None,
+            #[cfg(feature = "coroutine-profiling")]
+            profile_tag: None,
+            #[cfg(feature = "coroutine-panic-hooks")]
+            panic_hook: None,
+            #[cfg(feature = "cls")]
+            locals: cls::Bindings::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Resumes the coroutine, passing `input` to it.
+    ///
+    /// On the first call `input` is passed as the argument of the coroutine's function,
+    /// otherwise it's returned by the call to `Yielder::suspend()` the coroutine is suspended in.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine has already finished. If the coroutine panics,
+    /// the panic is propagated to the caller of this method.
+    pub fn resume(&mut self, input: Input) -> CoroutineResult<Yield, Return> {
+        match self.try_resume(input) {
+            Ok(result) => result,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Resumes the coroutine like `resume()`, but returns `input` as a `ResumeError`
+    /// instead of panicking if the coroutine has already finished, or if resuming it would
+    /// exceed the maximum nesting depth set using `current::set_max_depth()`, which requires
+    /// the `current` feature.
+    ///
+    /// # Panics
+    ///
+    /// If the coroutine panics, the panic is propagated to the caller of this method.
+    pub fn try_resume(&mut self,
+                      input: Input)
+                      -> Result<CoroutineResult<Yield, Return>, ResumeError<Input>> {
+        let context = match self.context.take() {
+            Some(context) => context,
+            None => {
+                return Err(ResumeError {
+                    input,
+                    kind: ResumeErrorKind::Finished,
+                })
+            },
+        };
+
+        #[cfg(feature = "current")]
+        {
+            if let Err(max) = current::check_depth() {
+                self.context = Some(context);
+                return Err(ResumeError {
+                    input,
+                    kind: ResumeErrorKind::DepthExceeded(max),
+                });
+            }
+        }
+
+        self.started = true;
+
+        #[cfg(feature = "coroutine-introspection")]
+        {
+            self.epoch += 1;
+            self.unpark();
+            self.call_stack_hook(StackEvent::Resuming, &context);
+        }
+
+        let mut slots: Slots<Input, Yield, Return> = Slots {
+            exchange: Exchange {
+                #[cfg(feature = "coroutine-introspection")]
+                epoch: self.epoch,
+                input: Some(input),
+                yielded: None,
+                #[cfg(feature = "coroutine-introspection")]
+                yield_point: None,
+            },
+            returned: None,
+        };
+
+        #[cfg(feature = "coroutine-profiling")]
+        let tracing = trace::is_enabled();
+        #[cfg(feature = "coroutine-profiling")]
+        let start = match self.profile_tag {
+            Some(_) => Some(Instant::now()),
+            None if tracing => Some(Instant::now()),
+            None => None,
+        };
+        let t = {
+            let _enter = self.enter();
+            unsafe { context.resume(&mut slots as *mut Slots<Input, Yield, Return> as usize) }
+        };
+
+        #[cfg(feature = "coroutine-profiling")]
+        {
+            if let (Some(tag), Some(start)) = (self.profile_tag, start) {
+                record_run_length(tag, start.elapsed());
+            }
+
+            if let (true, Some(start)) = (tracing, start) {
+                let outcome = if slots.exchange.yielded.is_some() {
+                    "suspended"
+                } else if let Some(Err(_)) = slots.returned {
+                    "panicked"
+                } else {
+                    "returned"
+                };
+
+                trace::switch(self.profile_tag.unwrap_or("coroutine"),
+                              start,
+                              self.identity,
+                              self.epoch,
+                              outcome);
+            }
+        }
+
+        #[cfg(feature = "coroutine-introspection")]
+        self.check_stack_quota();
+
+        if let Some(val) = slots.exchange.yielded.take() {
+            #[cfg(feature = "coroutine-introspection")]
+            {
+                if let Some(point) = slots.exchange.yield_point {
+                    parked().insert(self.yielder as usize, point);
+                    self.parked_at = Some(point);
+                }
+
+                self.call_stack_hook(StackEvent::Suspended, &t.context);
+            }
+
+            self.context = Some(t.context);
+            return Ok(CoroutineResult::Yield(val));
+        }
+
+        match slots.returned.take().expect("coroutine finished without a result") {
+            Ok(val) => Ok(CoroutineResult::Return(val)),
+            Err(err) => panic::resume_unwind(err),
+        }
+    }
+
+    /// Returns whether the coroutine has been resumed at least once.
+    pub fn started(&self) -> bool {
+        self.started
+    }
+
+    /// Returns the `YieldPoint` the coroutine is suspended at, or `None` if it isn't suspended
+    /// or was suspended using `Yielder::suspend()`.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn parked_at(&self) -> Option<YieldPoint> {
+        self.parked_at
+    }
+
+    #[cfg(feature = "coroutine-introspection")]
+    fn unpark(&mut self) {
+        if self.parked_at.take().is_some() {
+            parked().remove(&(self.yielder as usize));
+        }
+    }
+
+    /// Returns the tag the run lengths of this coroutine are recorded under.
+    #[cfg(feature = "coroutine-profiling")]
+    pub fn profile_tag(&self) -> Option<&'static str> {
+        self.profile_tag
+    }
+
+    /// Records the time the coroutine runs for every call to `resume()` under `tag`,
+    /// which is retrievable using `run_lengths()`, or stops doing so if `None` is passed.
+    ///
+    /// Coroutines of the same kind should share a tag, for instance the name of their function.
+    #[cfg(feature = "coroutine-profiling")]
+    pub fn set_profile_tag(&mut self, tag: Option<&'static str>) {
+        self.profile_tag = tag;
+    }
+
+    /// Registers a `PanicHook` which is called instead of the process-wide panic hook for panics
+    /// inside of the coroutine, or removes it if `None` is passed.
+    ///
+    /// The hook applies while the coroutine runs, including inside coroutines resumed by it which
+    /// don't have a hook of their own, and never while it's suspended. This allows routing the
+    /// panics of a coroutine to a log of its own without perturbing other threads.
+    ///
+    /// Coroutine hooks are only called if the process-wide hook set using
+    /// `std::panic::set_hook()` dispatches to them using `dispatch_panic_hook()`.
+    #[cfg(feature = "coroutine-panic-hooks")]
+    pub fn set_panic_hook(&mut self, hook: Option<PanicHook>) {
+        self.panic_hook = hook;
+    }
+
+    /// Registers a `StackHook` invoked whenever the coroutine is suspended or resumed,
+    /// or removes it if `None` is passed.
+    ///
+    /// The hook is not invoked for a coroutine which has never been resumed, since its stack
+    /// only holds the coroutine's function at that point.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn set_stack_hook(&mut self, hook: Option<StackHook>) {
+        self.stack_hook = hook;
+    }
+
+    /// Sets a soft quota for the stack of the coroutine, which is smaller than its size, along
+    /// with a `QuotaHook` invoked once the coroutine used more than `quota` bytes of it, or
+    /// removes the quota if `None` is passed.
+    ///
+    /// This allows allocating stacks generously to rule out overflows, while still detecting
+    /// coroutines whose stack usage grows in production. After every resume the high-water mark
+    /// of the stack is compared to the quota, and the hook is invoked on the stack of the owner
+    /// the first time it's exceeded, at most once per call to this method.
+    ///
+    /// The high-water mark is the number of resident bytes of the stack as returned by
+    /// `Stack::resident_size()`, so it's only as precise as a memory page, includes the memory
+    /// touched by earlier users of a reused stack, and is never exceeded on platforms which
+    /// don't support measuring it. Measuring it costs a system call per resume.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn set_stack_quota(&mut self, quota: Option<(usize, QuotaHook)>) {
+        self.stack_quota = quota;
+    }
+
+    // Invokes the QuotaHook, and removes it, if the stack exceeds its soft quota.
+    #[cfg(feature = "coroutine-introspection")]
+    fn check_stack_quota(&mut self) {
+        let (quota, hook) = match self.stack_quota {
+            Some(quota) => quota,
+            None => return,
+        };
+
+        match self.stack.resident_size() {
+            Ok(used) if used > quota => {
+                self.stack_quota = None;
+                hook(&QuotaExceeded {
+                    quota,
+                    used,
+                    size: self.stack.len(),
+                    #[cfg(feature = "coroutine-profiling")]
+                    profile_tag: self.profile_tag,
+                    identity: self.identity,
+                });
+            },
+            _ => (),
+        }
+    }
+
+    #[cfg(feature = "coroutine-introspection")]
+    fn call_stack_hook(&self, event: StackEvent, context: &Context) {
+        let hook = match self.stack_hook {
+            Some(hook) => hook,
+            None => return,
+        };
+
+        // Resuming a coroutine for the first time enters its function from scratch.
+        if event == StackEvent::Resuming && self.epoch == 1 {
+            return;
+        }
+
+        let registers = context.as_ptr();
+        let start = self.stack.start() as *const c_void;
+        let (low, high) = match GrowthDirection::native() {
+            GrowthDirection::Downward => (registers, start),
+            GrowthDirection::Upward => (start, registers),
+        };
+
+        hook(event, &LiveStack { registers, low, high });
+    }
+
+    /// Returns the number of times the coroutine has been resumed.
+    ///
+    /// The epoch is incremented by every call to `resume()` and can be compared to the value
+    /// returned by `Yielder::epoch()` inside the coroutine, for instance to detect whether the
+    /// coroutine has been suspended and resumed between two observations.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns a 64-bit value identifying this coroutine, for instance in the spans of a
+    /// distributed trace.
+    ///
+    /// The identity is fixed when the coroutine is created and remains the same for its whole
+    /// life, whichever thread resumes it. It's derived from a process-wide generation counter,
+    /// the address of its stack and an epoch drawn once per process, mixed by a fixed function,
+    /// which makes it unique within the process, even if a stack is reused, and unlikely to
+    /// collide with the identity of a coroutine of another process. `Yielder::identity()`
+    /// returns the same value inside the coroutine.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn identity(&self) -> u64 {
+        self.identity
+    }
+
+    /// Returns whether the coroutine has finished, either by returning, panicking or unwinding.
+    pub fn done(&self) -> bool {
+        self.context.is_none()
+    }
+
+    // Makes the per-coroutine state of the enabled features current on this thread until the
+    // returned guard is dropped, which must happen once the coroutine switched back.
+    fn enter(&mut self) -> Enter {
+        Enter {
+            #[cfg(feature = "current")]
+            _current: current::enter(&self.stack),
+            #[cfg(feature = "coroutine-panic-hooks")]
+            _panic_hook: enter_panic_hook(&self.panic_hook),
+            #[cfg(feature = "cls")]
+            _locals: cls::enter(&mut self.locals),
+        }
+    }
+
+    /// Returns the `UnwindStrategy` used by `force_unwind()`.
+    pub fn unwind_strategy(&self) -> UnwindStrategy {
+        self.unwind_strategy
+    }
+
+    /// Sets the `UnwindStrategy` used by `force_unwind()`.
+    ///
+    /// Defaults to `UnwindStrategy::default()`.
+    pub fn set_unwind_strategy(&mut self, strategy: UnwindStrategy) {
+        self.unwind_strategy = strategy;
+    }
+
+    /// Cleans up the coroutine if it hasn't finished yet, using its `UnwindStrategy`.
+    /// The coroutine is finished afterwards.
+    ///
+    /// A coroutine which was never resumed is always cleaned up by dropping its function.
+    /// With `UnwindStrategy::Deferred` the stack of a coroutine which was resumed is leaked.
+    pub fn force_unwind(&mut self) {
+        #[cfg(feature = "coroutine-introspection")]
+        self.unpark();
+
+        if let Some(context) = self.context.take() {
+            let _enter = self.enter();
+
+            if self.started && self.unwind_strategy == UnwindStrategy::Deferred {
+                let f = run_deferred::<Input, Yield>;
+                let _ = unsafe { context.resume_ontop(self.yielder as usize, f) };
+                self.leak_stack = true;
+            } else {
+                let _ = unsafe { context.resume(0) };
+            }
+        }
+    }
+
+    /// Unwinds the coroutine using `force_unwind()` and returns its stack, or `None` if the
+    /// stack had to be leaked since it was abandoned with values living on it.
+    pub fn into_stack(mut self) -> Option<S> {
+        self.force_unwind();
+
+        if self.leak_stack {
+            return None;
+        }
+
+        self.leak_stack = true;
+        Some(unsafe { ManuallyDrop::take(&mut self.stack) })
+    }
+}
+
+impl<Input, Yield, Return, S> Drop for Coroutine<Input, Yield, Return, S>
+    where S: StackOwner
+{
+    fn drop(&mut self) {
+        self.force_unwind();
+
+        if !self.leak_stack {
+            unsafe { ManuallyDrop::drop(&mut self.stack) };
+        }
+    }
+}
+
+// The guards returned by Coroutine::enter(), dropped in declaration order.
+struct Enter {
+    #[cfg(feature = "current")]
+    _current: current::Enter,
+    #[cfg(feature = "coroutine-panic-hooks")]
+    _panic_hook: PanicHookGuard,
+    #[cfg(feature = "cls")]
+    _locals: cls::Enter,
+}
+
+/// A `Coroutine` whose function may borrow data living for `'a`, mirroring
+/// `corosensei::ScopedCoroutine`.
+///
+/// This allows writing generators over borrowed data without extending its lifetime to
+/// `'static`. The coroutine can't outlive `'a` and is cleaned up using `force_unwind()` once it's
+/// dropped at the end of the scope, so its function never observes the borrowed data afterwards.
+///
+/// Contrary to `Coroutine` no mutable reference to the underlying `Coroutine` is handed out,
+/// since swapping it with an unscoped one would allow it to escape the scope.
+///
+/// # Examples
+///
+/// ```
+/// use context::compat::corosensei::{CoroutineResult, ScopedCoroutine};
+///
+/// let mut words = vec!["foo", "bar"];
+///
+/// {
+///     let mut coroutine = ScopedCoroutine::new(|yielder, ()| {
+///         for word in words.iter_mut() {
+///             yielder.suspend(word.len());
+///             *word = "baz";
+///         }
+///     });
+///
+///     assert_eq!(coroutine.resume(()), CoroutineResult::Yield(3));
+///     assert_eq!(coroutine.resume(()), CoroutineResult::Yield(3));
+/// }
+///
+/// assert_eq!(words, ["baz", "bar"]);
+/// ```
+///
+/// The borrowed data must outlive the coroutine:
+///
+/// ```compile_fail
+/// use context::compat::corosensei::ScopedCoroutine;
+///
+/// let coroutine: ScopedCoroutine<(), (), i32> = {
+///     let value = 1;
+///     ScopedCoroutine::new(|_, ()| value + 1)
+/// };
+/// ```
+pub struct ScopedCoroutine<'a, Input, Yield, Return, S = DefaultStack>
+    where S: StackOwner
+{
+    inner: Coroutine<Input, Yield, Return, S>,
+    // The function borrows data for 'a. Invariant, since the function may hold `&'a mut T`.
+    scope: PhantomData<&'a mut &'a ()>,
+}
+
+impl<'a, Input, Yield, Return> ScopedCoroutine<'a, Input, Yield, Return, DefaultStack> {
+    /// Creates a new coroutine executing `f` on a newly allocated `DefaultStack`.
+    ///
+    /// `f` is not executed until the first call to `resume()`.
+    pub fn new<F>(f: F) -> Self
+        where F: FnOnce(&Yielder<Input, Yield>, Input) -> Return + 'a
+    {
+        ScopedCoroutine::with_stack(DefaultStack::default(), f)
+    }
+}
+
+impl<'a, Input, Yield, Return, S> ScopedCoroutine<'a, Input, Yield, Return, S>
+    where S: StackOwner
+{
+    /// Creates a new coroutine executing `f` on the given `stack`.
+    ///
+    /// `f` is not executed until the first call to `resume()`. Like `Coroutine::with_stack()`
+    /// this requires a `StackOwner`, so a borrowed stack can't be shared between coroutines:
+    ///
+    /// ```compile_fail
+    /// use context::compat::corosensei::ScopedCoroutine;
+    /// use context::stack::{ProtectedFixedSizeStack, Stack};
+    ///
+    /// let stack = ProtectedFixedSizeStack::default();
+    /// let first = ScopedCoroutine::<(), (), (), &Stack>::with_stack(&stack, |_, ()| {});
+    /// ```
+    pub fn with_stack<F>(stack: S, f: F) -> Self
+        where F: FnOnce(&Yielder<Input, Yield>, Input) -> Return + 'a
+    {
+        ScopedCoroutine {
+            // The stack is owned exclusively and the scope of `f` is tracked by 'a.
+            inner: unsafe { Coroutine::with_stack_unchecked(stack, f) },
+            scope: PhantomData,
+        }
+    }
+
+    /// See `Coroutine::resume()`.
+    pub fn resume(&mut self, input: Input) -> CoroutineResult<Yield, Return> {
+        self.inner.resume(input)
+    }
+
+    /// See `Coroutine::try_resume()`.
+    pub fn try_resume(&mut self,
+                      input: Input)
+                      -> Result<CoroutineResult<Yield, Return>, ResumeError<Input>> {
+        self.inner.try_resume(input)
+    }
+
+    /// See `Coroutine::started()`.
+    pub fn started(&self) -> bool {
+        self.inner.started()
+    }
+
+    /// See `Coroutine::parked_at()`.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn parked_at(&self) -> Option<YieldPoint> {
+        self.inner.parked_at()
+    }
+
+    /// See `Coroutine::set_panic_hook()`.
+    #[cfg(feature = "coroutine-panic-hooks")]
+    pub fn set_panic_hook(&mut self, hook: Option<PanicHook>) {
+        self.inner.set_panic_hook(hook)
+    }
+
+    /// See `Coroutine::set_stack_hook()`.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn set_stack_hook(&mut self, hook: Option<StackHook>) {
+        self.inner.set_stack_hook(hook)
+    }
+
+    /// See `Coroutine::set_stack_quota()`.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn set_stack_quota(&mut self, quota: Option<(usize, QuotaHook)>) {
+        self.inner.set_stack_quota(quota)
+    }
+
+    /// See `Coroutine::epoch()`.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn epoch(&self) -> u64 {
+        self.inner.epoch()
+    }
+
+    /// See `Coroutine::identity()`.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn identity(&self) -> u64 {
+        self.inner.identity()
+    }
+
+    /// See `Coroutine::done()`.
+    pub fn done(&self) -> bool {
+        self.inner.done()
+    }
+
+    /// See `Coroutine::unwind_strategy()`.
+    pub fn unwind_strategy(&self) -> UnwindStrategy {
+        self.inner.unwind_strategy()
+    }
+
+    /// See `Coroutine::set_unwind_strategy()`.
+    pub fn set_unwind_strategy(&mut self, strategy: UnwindStrategy) {
+        self.inner.set_unwind_strategy(strategy)
+    }
+
+    /// See `Coroutine::force_unwind()`.
+    pub fn force_unwind(&mut self) {
+        self.inner.force_unwind()
+    }
+
+    /// See `Coroutine::into_stack()`.
+    pub fn into_stack(self) -> Option<S> {
+        self.inner.into_stack()
+    }
+}
+
+/// Passed to the function of a `Coroutine` to suspend it, mirroring `corosensei::Yielder`.
+pub struct Yielder<Input, Yield> {
+    caller: Cell<Option<Context>>,
+    exchange: Cell<*mut Exchange<Input, Yield>>,
+    #[cfg(feature = "coroutine-introspection")]
+    epoch: Cell<u64>,
+    // Set by Coroutine::with_stack() before the coroutine is resumed for the first time.
+    #[cfg(feature = "coroutine-introspection")]
+    identity: Cell<u64>,
+    deferred: RefCell<Vec<Box<dyn FnOnce()>>>,
+}
+
+impl<Input, Yield> Yielder<Input, Yield> {
+    /// Suspends the coroutine, returning `val` from the call to `Coroutine::resume()`.
+    ///
+    /// Returns the `Input` of the next call to `Coroutine::resume()`.
+    /// If the coroutine is dropped or `Coroutine::force_unwind()` is called instead,
+    /// the stack of the coroutine is unwound starting from here.
+    pub fn suspend(&self, val: Yield) -> Input {
+        let exchange = self.exchange.get();
+
+        if exchange.is_null() {
+            // The coroutine is being unwound and someone caught the ForceUnwind panic.
+            panic::resume_unwind(Box::new(ForceUnwind));
+        }
+
+        unsafe { (*exchange).yielded = Some(val) };
+
+        self.switch(0);
+        self.take_input()
+    }
+
+    /// Suspends the coroutine like `suspend()`, recording that it's parked at `point`
+    /// until it's resumed again.
+    ///
+    /// The `yield_point!` macro creates the `YieldPoint` from a name and its own location.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn suspend_at(&self, point: YieldPoint, val: Yield) -> Input {
+        let exchange = self.exchange.get();
+
+        if !exchange.is_null() {
+            unsafe { (*exchange).yield_point = Some(point) };
+        }
+
+        self.suspend(val)
+    }
+
+    /// Registers `f` to be called once the coroutine finishes.
+    ///
+    /// Deferred functions are called in reverse order of their registration after the
+    /// coroutine returned, panicked or was cleaned up by `Coroutine::force_unwind()`.
+    /// Contrary to the destructors of values living on the coroutine's stack they are called
+    /// with `UnwindStrategy::Deferred` as well, which makes them suitable to release
+    /// resources in builds using `panic = "abort"`.
+    pub fn defer<F>(&self, f: F)
+        where F: FnOnce() + 'static
+    {
+        self.deferred.borrow_mut().push(Box::new(f));
+    }
+
+    /// Returns the number of times the coroutine has been resumed so far.
+    ///
+    /// This is the value `Coroutine::epoch()` returned to the owner of the coroutine
+    /// after the call to `Coroutine::resume()` which resumed it the last time.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn epoch(&self) -> u64 {
+        self.epoch.get()
+    }
+
+    /// Returns the identity of the coroutine, which is the value `Coroutine::identity()`
+    /// returns to its owner.
+    #[cfg(feature = "coroutine-introspection")]
+    pub fn identity(&self) -> u64 {
+        self.identity.get()
+    }
+
+    fn switch(&self, data: usize) {
+        let caller = self.caller.take().unwrap();
+        let Transfer { context, data } = unsafe { caller.resume(data) };
+
+        self.caller.set(Some(context));
+        self.exchange.set(data as *mut Exchange<Input, Yield>);
+    }
+
+    fn take_input(&self) -> Input {
+        let exchange = self.exchange.get();
+
+        if exchange.is_null() {
+            panic::resume_unwind(Box::new(ForceUnwind));
+        }
+
+        #[cfg(feature = "coroutine-introspection")]
+        self.epoch.set(unsafe { (*exchange).epoch });
+
+        unsafe { (*exchange).input.take().unwrap() }
+    }
+
+    fn run_deferred(&self) {
+        // Deferred functions may register further ones, so the list mustn't stay borrowed.
+        loop {
+            let f = self.deferred.borrow_mut().pop();
+
+            match f {
+                Some(f) => f(),
+                None => break,
+            }
+        }
+    }
+}
+
+// Executed ontop of a suspended coroutine by Coroutine::force_unwind() with the address of its
+// Yielder as `data`. Runs the deferred functions and returns to the caller without ever
+// returning into the coroutine, whose stack is thus abandoned without unwinding it.
+extern "C" fn run_deferred<Input, Yield>(t: Transfer) -> Transfer {
+    let yielder = unsafe { &*(t.data as *const Yielder<Input, Yield>) };
+    yielder.run_deferred();
+
+    unsafe { t.context.finish(0) }
+}
+
+extern "C" fn coroutine_function<F, Input, Yield, Return>(t: Transfer) -> !
+    where F: FnOnce(&Yielder<Input, Yield>, Input) -> Return
+{
+    let f = unsafe { (*(t.data as *mut Option<F>)).take().unwrap() };
+    let yielder = Yielder {
+        caller: Cell::new(Some(t.context)),
+        exchange: Cell::new(ptr::null_mut()),
+        #[cfg(feature = "coroutine-introspection")]
+        epoch: Cell::new(0),
+        #[cfg(feature = "coroutine-introspection")]
+        identity: Cell::new(0),
+        deferred: RefCell::new(Vec::new()),
+    };
+
+    // Return to Coroutine::with_stack() and wait for the first resume().
+    yielder.switch(&yielder as *const Yielder<Input, Yield> as usize);
+
+    if yielder.exchange.get().is_null() {
+        // Dropped before it was ever resumed.
+        drop(f);
+    } else {
+        let mut result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let input = yielder.take_input();
+            f(&yielder, input)
+        }));
+
+        let deferred = panic::catch_unwind(AssertUnwindSafe(|| yielder.run_deferred()));
+
+        if let (&Ok(_), Err(err)) = (&result, deferred) {
+            result = Err(err);
+        }
+
+        // The exchange is null if the stack was unwound on request,
+        // in which case the result is the ForceUnwind payload.
+        let exchange = yielder.exchange.get();
+
+        if !exchange.is_null() {
+            let slots = exchange as *mut Slots<Input, Yield, Return>;
+            unsafe { (*slots).returned = Some(result) };
+        }
+    }
+
+    // Nothing owned is left on this stack, so it's safe to deallocate it after this switch.
+    let caller = yielder.caller.take().unwrap();
+    unsafe { caller.finish(0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    #[cfg(feature = "coroutine-introspection")]
+    use std::hint;
+    #[cfg(panic = "unwind")]
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+    #[cfg(all(panic = "unwind", feature = "coroutine-panic-hooks"))]
+    use std::sync::Once;
+
+    use super::*;
+
+    struct Dropper(Rc<Cell<usize>>);
+
+    impl Drop for Dropper {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn yield_and_return() {
+        let mut coroutine = Coroutine::new(|yielder, mut input: usize| {
+            for i in 0..3 {
+                input = yielder.suspend(input + i);
+            }
+
+            input
+        });
+
+        assert!(!coroutine.started());
+        assert_eq!(coroutine.resume(10), CoroutineResult::Yield(10));
+        assert_eq!(coroutine.resume(20), CoroutineResult::Yield(21));
+        assert_eq!(coroutine.resume(30), CoroutineResult::Yield(32));
+        assert_eq!(coroutine.resume(40), CoroutineResult::Return(40));
+        assert!(coroutine.started());
+        assert!(coroutine.done());
+    }
+
+    #[test]
+    fn try_resume() {
+        let mut coroutine: Coroutine<usize, (), usize> = Coroutine::new(|_, val| val);
+
+        assert_eq!(coroutine.try_resume(1), Ok(CoroutineResult::Return(1)));
+        let err = coroutine.try_resume(2).unwrap_err();
+        assert_eq!(err.kind(), ResumeErrorKind::Finished);
+        assert_eq!(err.into_input(), 2);
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn resume_finished() {
+        let mut coroutine: Coroutine<usize, (), usize> = Coroutine::new(|_, val| val);
+        assert_eq!(coroutine.resume(1), CoroutineResult::Return(1));
+
+        let err = panic::catch_unwind(AssertUnwindSafe(|| coroutine.resume(3))).unwrap_err();
+        assert_eq!(err.downcast_ref::<String>().map(|s| &s[..]),
+                   Some("attempted to resume a finished coroutine"));
+    }
+
+    #[test]
+    #[cfg(feature = "coroutine-introspection")]
+    fn yield_point() {
+        let mut coroutine: Coroutine<(), (), ()> = Coroutine::new(|yielder, ()| {
+            yield_point!(yielder, "yield_point_test", ());
+            yielder.suspend(());
+            yield_point!(yielder, "yield_point_test", ());
+        });
+
+        let count = || {
+            parked_yield_points().iter().filter(|point| point.name == "yield_point_test").count()
+        };
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        let point = coroutine.parked_at().unwrap();
+        assert_eq!(point.name, "yield_point_test");
+        assert_eq!(point.file, file!());
+        assert_eq!(count(), 1);
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        assert_eq!(coroutine.parked_at(), None);
+        assert_eq!(count(), 0);
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        assert_eq!(count(), 1);
+
+        drop(coroutine);
+        assert_eq!(count(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "coroutine-profiling")]
+    fn run_length_buckets() {
+        let mut lengths = RunLengths::new();
+        lengths.record(Duration::from_nanos(0));
+        lengths.record(Duration::from_nanos(1));
+        lengths.record(Duration::from_nanos(1023));
+        lengths.record(Duration::from_nanos(1024));
+        lengths.record(Duration::from_secs(1 << 40));
+
+        let mut expected = [0; RUN_LENGTH_BUCKETS];
+        expected[0] = 1;
+        expected[1] = 1;
+        expected[10] = 1;
+        expected[11] = 1;
+        expected[64] = 1;
+        assert_eq!(lengths.buckets(), &expected[..]);
+
+        let copy = lengths;
+        lengths.merge(&copy);
+        assert_eq!(lengths.count(), 10);
+        assert_eq!(lengths.buckets()[10], 2);
+    }
+
+    #[test]
+    #[cfg(feature = "coroutine-profiling")]
+    fn run_lengths() {
+        fn count(tag: &str) -> u64 {
+            super::run_lengths()
+                .into_iter()
+                .find(|&(t, _)| t == tag)
+                .map_or(0, |(_, lengths)| lengths.count())
+        }
+
+        let mut coroutine: Coroutine<(), (), ()> = Coroutine::new(|yielder, ()| {
+            yielder.suspend(());
+            yielder.suspend(());
+        });
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        coroutine.set_profile_tag(Some("run_lengths_test"));
+        assert_eq!(coroutine.profile_tag(), Some("run_lengths_test"));
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(()));
+        assert_eq!(count("run_lengths_test"), 2);
+
+        // The runs recorded by other threads are merged in, even after they exited.
+        thread::spawn(|| {
+            let mut coroutine: Coroutine<(), (), ()> = Coroutine::new(|_, ()| {});
+            coroutine.set_profile_tag(Some("run_lengths_test"));
+            assert_eq!(coroutine.resume(()), CoroutineResult::Return(()));
+        }).join().unwrap();
+
+        assert_eq!(count("run_lengths_test"), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "coroutine-introspection")]
+    fn stack_hook() {
+        thread_local! {
+            static EVENTS: RefCell<Vec<(StackEvent, LiveStack)>> =
+                const { RefCell::new(Vec::new()) };
+        }
+
+        fn hook(event: StackEvent, stack: &LiveStack) {
+            EVENTS.with(|events| events.borrow_mut().push((event, *stack)));
+        }
+
+        let mut coroutine = Coroutine::new(|yielder, ()| {
+            let local = 0u64;
+            yielder.suspend(&local as *const u64 as usize);
+        });
+        coroutine.set_stack_hook(Some(hook));
+
+        let local = coroutine.resume(()).as_yield().unwrap();
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(()));
+
+        EVENTS.with(|events| {
+            let events = events.borrow();
+            assert_eq!(events.len(), 2);
+            assert_eq!(events[0].0, StackEvent::Suspended);
+            assert_eq!(events[1], (StackEvent::Resuming, events[0].1));
+
+            // The local variable of the suspended coroutine lies within its live region.
+            let stack = events[0].1;
+            assert_eq!(stack.low, stack.registers);
+            assert!(stack.low as usize <= local && local < stack.high as usize);
+        });
+    }
+
+    #[test]
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), feature = "coroutine-introspection"))]
+    fn stack_quota() {
+        thread_local! {
+            static EXCEEDED: RefCell<Vec<QuotaExceeded>> = const { RefCell::new(Vec::new()) };
+        }
+
+        fn hook(exceeded: &QuotaExceeded) {
+            EXCEEDED.with(|e| e.borrow_mut().push(*exceeded));
+        }
+
+        #[inline(never)]
+        fn touch() {
+            hint::black_box([1u8; 64 * 1024]);
+        }
+
+        let stack = ProtectedFixedSizeStack::new(256 * 1024).unwrap();
+        let mut coroutine = Coroutine::with_stack(stack, |yielder, mut deep: bool| loop {
+            if deep {
+                touch();
+            }
+            deep = yielder.suspend(());
+        });
+        coroutine.set_stack_quota(Some((32 * 1024, hook)));
+
+        coroutine.resume(false);
+        assert!(EXCEEDED.with(|e| e.borrow().is_empty()));
+
+        coroutine.resume(true);
+        coroutine.resume(true);
+
+        EXCEEDED.with(|e| {
+            let e = e.borrow();
+            assert_eq!(e.len(), 1);
+            assert_eq!((e[0].quota, e[0].identity), (32 * 1024, coroutine.identity()));
+            assert!(e[0].used > 32 * 1024 && e[0].used <= e[0].size);
+        });
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn drop_unwinds() {
+        let drops = Rc::new(Cell::new(0));
+
+        let d = drops.clone();
+        let mut coroutine = Coroutine::new(move |yielder, ()| {
+            let _dropper = Dropper(d);
+            yielder.suspend(());
+            unreachable!();
+        });
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        assert_eq!(drops.get(), 0);
+
+        drop(coroutine);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn nested() {
+        struct Logger(usize, Rc<RefCell<Vec<usize>>>);
+
+        impl Drop for Logger {
+            fn drop(&mut self) {
+                self.1.borrow_mut().push(self.0);
+            }
+        }
+
+        // Every level forwards the values yielded by two consecutive children before yielding
+        // its own depth, reusing the stack of the first child for the second one.
+        fn nest(depth: usize,
+                stack: DefaultStack,
+                log: Rc<RefCell<Vec<usize>>>)
+                -> Coroutine<(), usize, ()> {
+            Coroutine::with_stack(stack, move |yielder, ()| {
+                let _logger = Logger(depth, log.clone());
+
+                if depth > 0 {
+                    let mut stack = DefaultStack::default();
+
+                    for _ in 0..2 {
+                        let mut child = nest(depth - 1, stack, log.clone());
+
+                        while let CoroutineResult::Yield(val) = child.resume(()) {
+                            yielder.suspend(val);
+                        }
+
+                        stack = child.into_stack().unwrap();
+                    }
+                }
+
+                yielder.suspend(depth);
+            })
+        }
+
+        fn expected(depth: usize, values: &mut Vec<usize>) {
+            if depth > 0 {
+                expected(depth - 1, values);
+                expected(depth - 1, values);
+            }
+
+            values.push(depth);
+        }
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut coroutine = nest(3, DefaultStack::default(), log.clone());
+        let mut values = Vec::new();
+
+        while let CoroutineResult::Yield(val) = coroutine.resume(()) {
+            values.push(val);
+        }
+
+        let mut expected_values = Vec::new();
+        expected(3, &mut expected_values);
+        assert_eq!(values, expected_values);
+        assert_eq!(log.borrow().len(), 15);
+
+        // Dropping the outermost coroutine while all levels are suspended unwinds them inside out.
+        log.borrow_mut().clear();
+        let mut coroutine = nest(3, DefaultStack::default(), log.clone());
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(0));
+
+        drop(coroutine);
+        assert_eq!(*log.borrow(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "current")]
+    fn current() {
+        assert!(!current::is_in_context());
+
+        let mut coroutine = Coroutine::new(|yielder, ()| {
+            let outer = current::current_stack_bounds().unwrap();
+
+            let mut inner = Coroutine::new(|yielder, ()| {
+                let bounds = current::current_stack_bounds().unwrap();
+                yielder.suspend((bounds.top(), bounds.bottom()));
+            });
+
+            let inner_bounds = inner.resume(()).as_yield().unwrap();
+            assert_ne!(inner_bounds, (outer.top(), outer.bottom()));
+
+            let bounds = current::current_stack_bounds().unwrap();
+            assert_eq!((bounds.top(), bounds.bottom()), (outer.top(), outer.bottom()));
+
+            let local = 0u8;
+            let addr = &local as *const u8 as usize;
+            assert!(outer.bottom() as usize <= addr && addr < outer.top() as usize);
+
+            yielder.suspend(());
+            assert!(current::is_in_context());
+        });
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        assert!(!current::is_in_context());
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(()));
+        assert!(!current::is_in_context());
+    }
+
+    #[test]
+    #[cfg(feature = "coroutine-introspection")]
+    fn identity() {
+        let mut coroutine = Coroutine::new(|yielder, ()| {
+            yielder.suspend(yielder.identity());
+            yielder.identity()
+        });
+        let identity = coroutine.identity();
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(identity));
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(identity));
+        assert_eq!(coroutine.identity(), identity);
+
+        // A coroutine reusing the stack gets an identity of its own.
+        let stack = coroutine.into_stack().unwrap();
+        let reused: Coroutine<(), (), ()> = Coroutine::with_stack(stack, |_, ()| {});
+        assert_ne!(reused.identity(), identity);
+    }
+
+    #[test]
+    #[cfg(feature = "current")]
+    fn max_depth() {
+        // Resumes nested coroutines until the limit is reached and yields the depth of the
+        // innermost one through all of them.
+        fn nest(yielder: &Yielder<(), usize>) {
+            let mut child = Coroutine::new(|yielder, ()| nest(yielder));
+
+            match child.try_resume(()) {
+                Ok(CoroutineResult::Yield(depth)) => yielder.suspend(depth),
+                Ok(_) => unreachable!(),
+                Err(err) => {
+                    assert_eq!(err.kind(), ResumeErrorKind::DepthExceeded(3));
+                    yielder.suspend(current::depth())
+                },
+            }
+        }
+
+        assert_eq!(current::depth(), 0);
+        current::set_max_depth(Some(3));
+
+        let mut coroutine = Coroutine::new(|yielder, ()| nest(yielder));
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(3));
+        assert_eq!(current::depth(), 0);
+
+        // Unwinding the suspended chain isn't limited.
+        drop(coroutine);
+        current::set_max_depth(None);
+    }
+
+    #[test]
+    fn drop_before_start() {
+        let drops = Rc::new(Cell::new(0));
+
+        let dropper = Dropper(drops.clone());
+        let coroutine: Coroutine<(), (), ()> = Coroutine::new(move |_, ()| {
+            let _dropper = dropper;
+        });
+
+        drop(coroutine);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn panic_propagates() {
+        let mut coroutine: Coroutine<(), (), ()> = Coroutine::new(|_, ()| panic!("foobar"));
+
+        let err = panic::catch_unwind(AssertUnwindSafe(|| coroutine.resume(()))).unwrap_err();
+        assert_eq!(err.downcast_ref::<&str>(), Some(&"foobar"));
+        assert!(coroutine.done());
+    }
+
+    #[test]
+    #[cfg(all(panic = "unwind", feature = "coroutine-panic-hooks"))]
+    fn panic_hook() {
+        static INSTALL: Once = Once::new();
+
+        INSTALL.call_once(|| {
+            let previous = panic::take_hook();
+
+            panic::set_hook(Box::new(move |info| {
+                if !dispatch_panic_hook(info) {
+                    previous(info);
+                }
+            }));
+        });
+
+        let messages = Rc::new(RefCell::new(Vec::new()));
+
+        let mut coroutine: Coroutine<(), (), ()> = Coroutine::new(|yielder, ()| {
+            let mut inner: Coroutine<(), (), ()> = Coroutine::new(|_, ()| panic!("inner"));
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| inner.resume(())));
+
+            yielder.suspend(());
+            panic!("outer");
+        });
+
+        let m = messages.clone();
+        coroutine.set_panic_hook(Some(Box::new(move |info| {
+            let message = info.payload().downcast_ref::<&str>().unwrap();
+            m.borrow_mut().push(message.to_string());
+        })));
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        assert_eq!(*messages.borrow(), ["inner"]);
+
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| coroutine.resume(()))).is_err());
+        assert_eq!(*messages.borrow(), ["inner", "outer"]);
+        assert!(PANIC_HOOK.with(Cell::get).is_none());
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn into_stack() {
+        let mut coroutine = Coroutine::new(|yielder, ()| yielder.suspend(()));
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+
+        let stack = coroutine.into_stack().unwrap();
+        let mut coroutine: Coroutine<usize, (), usize> = Coroutine::with_stack(stack, |_, val| val);
+        assert_eq!(coroutine.resume(3), CoroutineResult::Return(3));
+        assert!(coroutine.into_stack().is_some());
+    }
+
+    #[test]
+    fn defer() {
+        let order = Rc::new(Cell::new(0));
+
+        let o = order.clone();
+        let mut coroutine = Coroutine::new(move |yielder, ()| {
+            let first = o.clone();
+            yielder.defer(move || {
+                assert_eq!(first.get(), 1);
+                first.set(2);
+            });
+
+            let second = o.clone();
+            yielder.defer(move || {
+                assert_eq!(second.get(), 0);
+                second.set(1);
+            });
+
+            yielder.suspend(());
+        });
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        assert_eq!(order.get(), 0);
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(()));
+        assert_eq!(order.get(), 2);
+    }
+
+    #[test]
+    fn deferred_unwind_strategy() {
+        let drops = Rc::new(Cell::new(0));
+        let deferred = Rc::new(Cell::new(0));
+
+        let d = drops.clone();
+        let r = deferred.clone();
+        let mut coroutine = Coroutine::new(move |yielder, ()| {
+            let _dropper = Dropper(d);
+            yielder.defer(move || r.set(r.get() + 1));
+            yielder.suspend(());
+            unreachable!();
+        });
+
+        assert_eq!(coroutine.unwind_strategy(), UnwindStrategy::default());
+        coroutine.set_unwind_strategy(UnwindStrategy::Deferred);
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        coroutine.force_unwind();
+        assert!(coroutine.done());
+
+        // The stack has been abandoned instead of unwinding it, and is leaked.
+        assert_eq!(deferred.get(), 1);
+        assert_eq!(drops.get(), 0);
+        assert!(coroutine.into_stack().is_none());
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn scoped() {
+        let drops = Rc::new(Cell::new(0));
+        let mut values = vec![1, 2, 3];
+
+        {
+            let dropper = Dropper(drops.clone());
+            let values = &mut values;
+            let mut coroutine = ScopedCoroutine::new(move |yielder, _: i32| {
+                let _dropper = dropper;
+
+                for value in values.iter_mut() {
+                    *value = yielder.suspend(*value);
+                }
+            });
+
+            assert_eq!(coroutine.resume(0), CoroutineResult::Yield(1));
+            assert_eq!(coroutine.resume(10), CoroutineResult::Yield(2));
+            assert_eq!(drops.get(), 0);
+        }
+
+        // Dropping the coroutine unwound its stack, ending the borrow.
+        assert_eq!(drops.get(), 1);
+        assert_eq!(values, [10, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "coroutine-introspection")]
+    fn epoch() {
+        let mut coroutine = Coroutine::new(|yielder, ()| {
+            let epoch = yielder.epoch();
+            yielder.suspend(epoch);
+            yielder.epoch()
+        });
+
+        assert_eq!(coroutine.epoch(), 0);
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(1));
+        assert_eq!(coroutine.epoch(), 1);
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(2));
+        assert_eq!(coroutine.epoch(), 2);
+    }
+}
@@ -0,0 +1,11 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/// Mirrors the API of the `corosensei` crate.
+///
+/// See the `compat::corosensei::Coroutine` struct for more information.
+pub mod corosensei;
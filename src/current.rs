@@ -0,0 +1,111 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::cell::Cell;
+use std::os::raw::c_void;
+
+use stack::Stack;
+
+thread_local! {
+    // The top and bottom of the stack of the innermost coroutine running on this thread.
+    static CURRENT: Cell<Option<(*mut c_void, *mut c_void)>> = const { Cell::new(None) };
+    // The number of coroutines the code running on this thread is nested in.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static MAX_DEPTH: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Returns whether the calling code runs on the stack of a coroutine created by this crate.
+///
+/// This is maintained by `compat::corosensei::Coroutine` whenever it switches into or out of
+/// a coroutine. Code running inside of a raw `Context` is not detected.
+#[inline]
+pub fn is_in_context() -> bool {
+    current_stack_bounds().is_some()
+}
+
+/// Returns a (non-owning) `Stack` describing the stack of the innermost coroutine the calling
+/// code runs in, or `None` if it runs on the stack of its thread.
+///
+/// Libraries can use it for instance to compare the remaining stack space against `limit()`.
+/// See `is_in_context()` for more information.
+#[inline]
+pub fn current_stack_bounds() -> Option<Stack> {
+    CURRENT.try_with(Cell::get)
+        .ok()
+        .and_then(|bounds| bounds)
+        .map(|(top, bottom)| unsafe { Stack::new(top, bottom) })
+}
+
+/// Returns the number of coroutines the calling code is nested in, which is 0 on the stack of
+/// its thread and 1 within a coroutine resumed from there.
+///
+/// This counts the coroutines of the current chain of `Coroutine::resume()` calls, each of
+/// which keeps a frame alive on the stack of its resumer.
+#[inline]
+pub fn depth() -> usize {
+    DEPTH.try_with(Cell::get).unwrap_or(0)
+}
+
+/// Returns the maximum nesting depth set for the current thread using `set_max_depth()`.
+pub fn max_depth() -> Option<usize> {
+    MAX_DEPTH.with(Cell::get)
+}
+
+/// Limits the number of coroutines code running on the current thread may be nested in,
+/// or removes the limit if `max` is `None`, which is the default.
+///
+/// Every nested resume keeps the frames of its resumer alive, so a deeply nested chain of
+/// resumes, as built by recursive schedulers, may overflow one of the resuming stacks. With a
+/// limit in place `Coroutine::try_resume()` returns a `ResumeError` of kind
+/// `ResumeErrorKind::DepthExceeded` instead of resuming a coroutine at a `depth()` of `max`
+/// or more, and `Coroutine::resume()` panics with a message naming the limit. Cleaning up
+/// coroutines using `Coroutine::force_unwind()` is never limited.
+///
+/// # Examples
+///
+/// ```
+/// use context::compat::corosensei::{Coroutine, ResumeErrorKind};
+/// use context::current;
+///
+/// current::set_max_depth(Some(1));
+///
+/// let mut outer = Coroutine::<(), (), _>::new(|_, ()| {
+///     let mut inner = Coroutine::<(), (), ()>::new(|_, ()| {});
+///     inner.try_resume(()).unwrap_err().kind()
+/// });
+///
+/// assert_eq!(outer.resume(()).as_return(), Some(ResumeErrorKind::DepthExceeded(1)));
+/// ```
+pub fn set_max_depth(max: Option<usize>) {
+    MAX_DEPTH.with(|max_depth| max_depth.set(max));
+}
+
+// Returns the maximum depth if resuming a coroutine from the calling code would exceed it.
+pub(crate) fn check_depth() -> Result<(), usize> {
+    match max_depth() {
+        Some(max) if depth() >= max => Err(max),
+        _ => Ok(()),
+    }
+}
+
+// Marks the code running until the returned guard is dropped as running on `stack`.
+// The guard restores the previous state, which makes nesting and panics propagating
+// out of a switch work.
+pub(crate) fn enter(stack: &Stack) -> Enter {
+    let previous = CURRENT.try_with(|current| current.replace(Some((stack.top(), stack.bottom()))));
+    let _ = DEPTH.try_with(|depth| depth.set(depth.get() + 1));
+    Enter(previous.unwrap_or(None))
+}
+
+pub(crate) struct Enter(Option<(*mut c_void, *mut c_void)>);
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        let _ = CURRENT.try_with(|current| current.set(self.0));
+        let _ = DEPTH.try_with(|depth| depth.set(depth.get() - 1));
+    }
+}
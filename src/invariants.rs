@@ -0,0 +1,75 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use context_core;
+
+#[cfg(all(feature = "current", feature = "coroutine-introspection"))]
+use compat::corosensei;
+#[cfg(feature = "current")]
+use current;
+
+/// Asserts the invariants this crate relies on, if debug assertions are enabled.
+///
+/// This checks everything `context_core::debug_assert_context_invariants()` does.
+/// With the `current` feature it additionally checks when called inside a coroutine that the
+/// caller runs within the bounds of the coroutine's stack, i.e. hasn't overflowed into its guard
+/// page, and with the `coroutine-introspection` feature as well that the coroutine isn't
+/// recorded as parked at a `YieldPoint`.
+///
+/// Integrators can call it at checkpoints in their own test suites. A violated invariant
+/// panics just like `debug_assert!()` does.
+pub fn debug_assert_context_invariants() {
+    context_core::debug_assert_context_invariants();
+
+    #[cfg(feature = "current")]
+    {
+        if let Some(stack) = current::current_stack_bounds() {
+            let (low, high) = (stack.bottom() as usize, stack.top() as usize);
+            let local = 0u8;
+            let address = &local as *const u8 as usize;
+
+            debug_assert!(low < address && address < high,
+                          "running at {:#x} outside of the coroutine's stack {:#x}..{:#x}",
+                          address,
+                          low,
+                          high);
+            #[cfg(feature = "coroutine-introspection")]
+            debug_assert!(!corosensei::is_parked_within(low, high),
+                          "the running coroutine is recorded as parked at a yield point");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn debug_assert_context_invariants() {
+        super::debug_assert_context_invariants();
+    }
+
+    #[cfg(all(feature = "current", feature = "coroutine-introspection"))]
+    #[test]
+    fn inside_coroutine() {
+        use compat::corosensei::{Coroutine, CoroutineResult, YieldPoint};
+
+        let mut coroutine: Coroutine<(), (), ()> = Coroutine::new(|yielder, ()| {
+            super::debug_assert_context_invariants();
+            let point = YieldPoint {
+                name: "invariants_test",
+                file: file!(),
+                line: line!(),
+            };
+
+            yielder.suspend_at(point, ());
+            super::debug_assert_context_invariants();
+        });
+
+        assert_eq!(coroutine.resume(()), CoroutineResult::Yield(()));
+        super::debug_assert_context_invariants();
+        assert_eq!(coroutine.resume(()), CoroutineResult::Return(()));
+    }
+}
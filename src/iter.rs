@@ -0,0 +1,126 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::fmt;
+use std::iter::FusedIterator;
+
+use compat::corosensei::{Coroutine, CoroutineResult, Yielder};
+use stack::{ProtectedFixedSizeStack, StackError};
+
+/// Creates an `Iterator` over the values `f` passes to `Yielder::suspend()`, executing `f`
+/// lazily on a `ProtectedFixedSizeStack` of **at least** `stack_size` bytes.
+///
+/// `f` only runs while the iterator is advanced, so it may produce the items using plain loops
+/// and recursion instead of an explicit state machine. The stack is deallocated as soon as `f`
+/// returns, or once the iterator is dropped, which unwinds `f` if it hasn't finished yet.
+///
+/// # Examples
+///
+/// ```
+/// use context::iter;
+///
+/// let squares = iter::lazy_from_fn(64 * 1024, |yielder| {
+///     for i in 1.. {
+///         yielder.suspend(i * i);
+///     }
+/// });
+///
+/// let squares: Vec<u32> = squares.unwrap().take(4).collect();
+/// assert_eq!(squares, [1, 4, 9, 16]);
+/// ```
+pub fn lazy_from_fn<T, F>(stack_size: usize, f: F) -> Result<LazyIter<T>, StackError>
+    where F: FnOnce(&Yielder<(), T>) + 'static
+{
+    let stack = ProtectedFixedSizeStack::new(stack_size)?;
+
+    Ok(LazyIter { coroutine: Some(Coroutine::with_stack(stack, move |yielder, ()| f(yielder))) })
+}
+
+/// The `Iterator` returned by `lazy_from_fn()`.
+pub struct LazyIter<T> {
+    coroutine: Option<Coroutine<(), T, ()>>,
+}
+
+impl<T> Iterator for LazyIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let item = match self.coroutine {
+            // A coroutine which panicked before has finished as well.
+            Some(ref mut coroutine) => coroutine.try_resume(()),
+            None => return None,
+        };
+
+        match item {
+            Ok(CoroutineResult::Yield(item)) => Some(item),
+            _ => {
+                // Deallocate the stack right away instead of when the iterator is dropped.
+                self.coroutine = None;
+                None
+            }
+        }
+    }
+}
+
+impl<T> FusedIterator for LazyIter<T> {}
+
+impl<T> fmt::Debug for LazyIter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LazyIter")
+            .field("done", &self.coroutine.is_none())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(panic = "unwind")]
+    use std::cell::Cell;
+    #[cfg(panic = "unwind")]
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn lazy_from_fn() {
+        // Yields the nodes of a complete binary tree of the given depth in-order using recursion.
+        fn walk(yielder: &Yielder<(), usize>, node: usize, depth: usize) {
+            if depth > 0 {
+                walk(yielder, 2 * node, depth - 1);
+                yielder.suspend(node);
+                walk(yielder, 2 * node + 1, depth - 1);
+            }
+        }
+
+        let mut iter = super::lazy_from_fn(64 * 1024, |yielder| walk(yielder, 1, 3)).unwrap();
+        assert_eq!(iter.by_ref().collect::<Vec<_>>(), [4, 2, 5, 1, 6, 3, 7]);
+        assert!(iter.coroutine.is_none());
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn lazy_and_unwound() {
+        let started = Rc::new(Cell::new(false));
+
+        let s = started.clone();
+        let mut iter = super::lazy_from_fn(64 * 1024, move |yielder| {
+            s.set(true);
+            loop {
+                yielder.suspend(());
+            }
+        }).unwrap();
+
+        assert!(!started.get());
+        assert_eq!(iter.next(), Some(()));
+        assert!(started.get());
+
+        // Dropping the iterator unwinds the infinite loop, releasing the captured Rc.
+        drop(iter);
+        assert_eq!(Rc::strong_count(&started), 1);
+    }
+}
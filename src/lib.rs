@@ -5,28 +5,117 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-#![cfg_attr(feature = "nightly", feature(repr_simd))]
 #![deny(missing_docs)]
 
 //! This project provides an easy interface to the famous **Boost.Context** library
 //! and thus the building blocks for higher-level abstractions, like coroutines,
 //! cooperative threads (userland threads) or an equivalent to the C# keyword "yield".
+//!
+//! The raw switching layer lives in the `context-core` crate and is re-exported unchanged.
+//! Depend on `context-core` directly if you don't need the higher-level modules.
 
-extern crate libc;
-#[cfg(windows)]
-extern crate kernel32;
-#[cfg(windows)]
-extern crate winapi;
+extern crate context_core;
 
-/// Provides the `Context` and `Transfer` types for
-/// saving and restoring the current state of execution.
+pub use context_core::bench;
+pub use context_core::context;
+pub use context_core::stack;
+
+#[cfg(feature = "accounting")]
+pub use context_core::accounting;
+
+#[cfg(feature = "borrow-tracking")]
+pub use context_core::borrow;
+
+#[cfg(feature = "debug-switch")]
+pub use context_core::debug;
+
+#[cfg(feature = "thread-migration")]
+pub use context_core::migration;
+
+#[cfg(any(all(target_arch = "x86_64", not(windows)), all(target_arch = "aarch64", not(windows))))]
+pub use context_core::record;
+
+/// Provides contexts with asymmetric call/return semantics.
+///
+/// See the `asymmetric::Callee` struct for more information.
+pub mod asymmetric;
+
+/// Provides broadcasts of items to several consumers, each running on a stack of its own.
 ///
-/// See the `Context` struct for more information.
-pub mod context;
+/// Requires the `corosensei-compat` feature.
+#[cfg(feature = "corosensei-compat")]
+pub mod broadcast;
 
-/// Provides utilities to allocate memory suitable as stack memory for `Context`.
-pub mod stack;
+/// Provides a bounded cache of suspended coroutines, evicting the least recently used one.
+///
+/// Requires the `corosensei-compat` feature.
+#[cfg(feature = "corosensei-compat")]
+pub mod cache;
+
+/// Provides values scoped to the coroutine they are bound in, which survive its suspensions.
+///
+/// Requires the `cls` feature.
+#[cfg(feature = "cls")]
+pub mod cls;
+
+/// Provides safe APIs mirroring other coroutine libraries on top of `Context`.
+///
+/// Requires the `corosensei-compat` feature.
+#[cfg(feature = "corosensei-compat")]
+pub mod compat;
+
+/// Provides information about the coroutine the current code is running in.
+///
+/// Requires the `current` feature.
+#[cfg(feature = "current")]
+pub mod current;
+
+/// Provides iterators executing their producer lazily on a separate stack.
+///
+/// Requires the `corosensei-compat` feature.
+#[cfg(feature = "corosensei-compat")]
+pub mod iter;
+
+/// Provides a pair of contexts alternating strictly with each other.
+///
+/// See the `pingpong::PingPong` struct for more information.
+pub mod pingpong;
+
+/// Provides pipelines of processing stages, each running on a stack of its own.
+///
+/// Requires the `corosensei-compat` feature.
+#[cfg(feature = "corosensei-compat")]
+pub mod pipeline;
+
+/// Provides coroutines resumed by C code through an exported function, for instance from the
+/// callbacks of a libuv or libevent loop.
+///
+/// Requires the `reactor` feature.
+#[cfg(feature = "reactor")]
+pub mod reactor;
+
+/// Provides a virtual clock for testing the timeouts of coroutines spawned using
+/// `reactor::spawn()` without waiting in real time.
+///
+/// Requires the `testkit` feature.
+#[cfg(feature = "testkit")]
+pub mod testkit;
+
+/// Provides a recorder of coroutine switches writing the Trace Event format, which can be
+/// viewed in chrome://tracing or Perfetto.
+///
+/// Requires the `coroutine-profiling` feature.
+#[cfg(feature = "coroutine-profiling")]
+pub mod trace;
+
+/// Provides a variant of `Context` which encodes its lifecycle state in its type.
+///
+/// See the `typed::Context` struct for more information.
+pub mod typed;
 
-mod sys;
+mod invariants;
 
-pub use context::{Context, Transfer, ContextFn, ResumeOntopFn};
+pub use invariants::debug_assert_context_invariants;
+pub use context::{Context, Transfer, ContextFn, ResumeOntopFn, IntoTransferData, FromTransferData,
+                  TaggedData, TypedTransfer, ScopedContext, ScopedSuspender,
+                  SMALL_TRANSFER_SIZE};
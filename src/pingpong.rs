@@ -0,0 +1,246 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+
+use context::{Context, Transfer};
+use stack::{ProtectedFixedSizeStack, StackOwner};
+
+/// The function executed by the peer of a `PingPong`.
+///
+/// It receives the `Peer` to swap data with and the `data` of the first swap.
+/// Since the two sides alternate strictly the function must never return.
+pub type PeerFn = fn(&mut Peer, usize) -> !;
+
+/// A pair of contexts alternating strictly with each other, like the stages of a codec pipeline.
+///
+/// This is a fast path for the common special case of exactly two parties: Whichever side is
+/// suspended, its `Context` is kept in the same cache line as the exchanged data and the
+/// number of swaps, and the address of that cache line is the only value passed by a switch.
+///
+/// The `PingPong` owns the stack of its peer, so no other context can run on it meanwhile.
+/// The peer never finishes and can't be unwound, so dropping a `PingPong` after its first swap
+/// leaks that stack along with everything living on it. The memory of values pinned on it is
+/// thus never reused without dropping them.
+///
+/// # Examples
+///
+/// ```
+/// use context::pingpong::{Peer, PingPong};
+/// use context::stack::ProtectedFixedSizeStack;
+///
+/// fn accumulate(peer: &mut Peer, mut data: usize) -> ! {
+///     let mut sum = 0;
+///
+///     loop {
+///         sum += data;
+///         data = peer.swap(sum);
+///     }
+/// }
+///
+/// let mut pingpong = PingPong::new(ProtectedFixedSizeStack::default(), accumulate);
+///
+/// assert_eq!(pingpong.swap(1), 1);
+/// assert_eq!(pingpong.swap(2), 3);
+/// assert_eq!(pingpong.swaps(), 2);
+/// ```
+pub struct PingPong<S: StackOwner = ProtectedFixedSizeStack> {
+    line: Line,
+    // Owned for as long as the peer may run on it, which is forever once it was started.
+    stack: ManuallyDrop<S>,
+    // The peer's stack might hold non-Send values.
+    marker: PhantomData<*mut ()>,
+}
+
+// The state both sides access on every swap, independent of the type of the stack.
+#[repr(C, align(64))]
+struct Line {
+    // The suspended side: The peer while the owner runs and vice versa.
+    context: Option<Context>,
+    data: usize,
+    swaps: u64,
+}
+
+impl<S: StackOwner> PingPong<S> {
+    /// Creates a new `PingPong`, whose peer executes `f` at the beginning of `stack`, which
+    /// it keeps until it's dropped.
+    ///
+    /// `f` is not executed until the first call to `swap()`. Borrowed stacks are rejected,
+    /// since a second peer could be started on them:
+    ///
+    /// ```compile_fail
+    /// use context::pingpong::{Peer, PingPong};
+    /// use context::stack::ProtectedFixedSizeStack;
+    ///
+    /// fn echo(peer: &mut Peer, mut data: usize) -> ! {
+    ///     loop {
+    ///         data = peer.swap(data);
+    ///     }
+    /// }
+    ///
+    /// let stack = ProtectedFixedSizeStack::default();
+    /// let pingpong = PingPong::new(&*stack, echo);
+    /// ```
+    pub fn new(stack: S, f: PeerFn) -> PingPong<S> {
+        // Hands `f` over to the peer, which waits for the first swap afterwards.
+        let t = unsafe { Context::new(&stack, peer_function).resume(f as usize) };
+
+        PingPong {
+            line: Line {
+                context: Some(t.context),
+                data: 0,
+                swaps: 0,
+            },
+            stack: ManuallyDrop::new(stack),
+            marker: PhantomData,
+        }
+    }
+
+    /// Hands `data` over to the peer and returns the data it swaps back.
+    #[inline]
+    pub fn swap(&mut self, data: usize) -> usize {
+        let line = &mut self.line;
+        line.data = data;
+
+        let context = line.context.take().unwrap();
+        let t = unsafe { context.resume(line as *mut Line as usize) };
+
+        line.context = Some(t.context);
+        line.swaps += 1;
+        line.data
+    }
+
+    /// Returns the number of completed calls to `swap()`, each of which is a round trip.
+    #[inline]
+    pub fn swaps(&self) -> u64 {
+        self.line.swaps
+    }
+}
+
+impl<S: StackOwner> Drop for PingPong<S> {
+    fn drop(&mut self) {
+        // A peer which was never swapped with only waits in peer_function(), which owns nothing.
+        if self.line.swaps == 0 {
+            unsafe { ManuallyDrop::drop(&mut self.stack) };
+        }
+    }
+}
+
+/// Passed to the `PeerFn` of a `PingPong` to swap data with its owner.
+pub struct Peer {
+    line: *mut Line,
+}
+
+impl Peer {
+    /// Hands `data` back to the owner of the `PingPong` and returns the data of its next swap.
+    #[inline]
+    pub fn swap(&mut self, data: usize) -> usize {
+        let owner = unsafe {
+            (*self.line).data = data;
+            (*self.line).context.take().unwrap()
+        };
+
+        // The PingPong might have been moved by its owner in between.
+        let Transfer { context, data } = unsafe { owner.resume(0) };
+        self.line = data as *mut Line;
+
+        unsafe {
+            (*self.line).context = Some(context);
+            (*self.line).data
+        }
+    }
+}
+
+extern "C" fn peer_function(t: Transfer) -> ! {
+    let f: PeerFn = unsafe { mem::transmute(t.data) };
+
+    // Return to PingPong::new() and wait for the first swap.
+    let Transfer { context, data } = unsafe { t.context.resume(0) };
+    let mut peer = Peer { line: data as *mut Line };
+
+    let data = unsafe {
+        (*peer.line).context = Some(context);
+        (*peer.line).data
+    };
+
+    f(&mut peer, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::mem;
+    use std::ops::Deref;
+
+    use stack::{ProtectedFixedSizeStack, Stack};
+
+    use super::*;
+
+    thread_local! {
+        static DROPPED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Counts the stacks dropped on the current thread.
+    struct CountedStack(ProtectedFixedSizeStack);
+
+    impl Deref for CountedStack {
+        type Target = Stack;
+
+        fn deref(&self) -> &Stack {
+            &self.0
+        }
+    }
+
+    impl Drop for CountedStack {
+        fn drop(&mut self) {
+            DROPPED.with(|dropped| dropped.set(dropped.get() + 1));
+        }
+    }
+
+    unsafe impl StackOwner for CountedStack {}
+
+    fn echo(peer: &mut Peer, mut data: usize) -> ! {
+        loop {
+            data = peer.swap(data * 2);
+        }
+    }
+
+    #[test]
+    fn layout() {
+        assert_eq!(mem::align_of::<Line>(), 64);
+        assert_eq!(mem::size_of::<Line>(), 64);
+    }
+
+    #[test]
+    fn swap() {
+        let mut pingpong = PingPong::new(ProtectedFixedSizeStack::default(), echo);
+        assert_eq!(pingpong.swaps(), 0);
+
+        for i in 0..10 {
+            assert_eq!(pingpong.swap(i), i * 2);
+        }
+
+        // Moving the PingPong in between swaps is fine.
+        let mut moved = Box::new(pingpong);
+        assert_eq!(moved.swap(21), 42);
+        assert_eq!(moved.swaps(), 11);
+    }
+
+    #[test]
+    fn drop_leaks_started() {
+        let stack = || CountedStack(ProtectedFixedSizeStack::default());
+
+        drop(PingPong::new(stack(), echo));
+        assert_eq!(DROPPED.with(Cell::get), 1);
+
+        let mut pingpong = PingPong::new(stack(), echo);
+        assert_eq!(pingpong.swap(1), 2);
+        drop(pingpong);
+        assert_eq!(DROPPED.with(Cell::get), 1);
+    }
+}
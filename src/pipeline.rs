@@ -0,0 +1,187 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::fmt;
+
+use compat::corosensei::Yielder;
+use iter::{self, LazyIter};
+use stack::StackError;
+
+/// A chain of processing stages, each running on a stack of its own, which is iterated over
+/// to pull the items out of its last stage.
+///
+/// Every stage reads the items of the previous one from an `Input` and passes its own on using
+/// `Output::push()`. There is room for a single item between two stages: `push()` suspends the
+/// stage until the next stage took the item, and reading from an empty `Input` switches to the
+/// previous stage until it pushed one. No stage thus ever runs ahead of the others, and a stage
+/// may keep arbitrary state in local variables, while the whole pipeline runs on the thread
+/// iterating over it, without any channels or threads.
+///
+/// # Examples
+///
+/// ```
+/// use context::pipeline::Pipeline;
+///
+/// let sums = Pipeline::new(1..=6)
+///     .stage(64 * 1024, |input, output| {
+///         for n in input {
+///             output.push(n * 10);
+///         }
+///     })
+///     .unwrap()
+///     .stage(64 * 1024, |input, output| {
+///         // Sums up pairs of items, which requires keeping the first one of each pair.
+///         while let (Some(a), Some(b)) = (input.next(), input.next()) {
+///             output.push(a + b);
+///         }
+///     })
+///     .unwrap();
+///
+/// assert_eq!(sums.collect::<Vec<_>>(), [30, 70, 110]);
+/// ```
+pub struct Pipeline<T> {
+    last: Box<dyn Iterator<Item = T>>,
+}
+
+impl<T: 'static> Pipeline<T> {
+    /// Creates a pipeline whose first stage produces the items of `source`.
+    ///
+    /// The source is advanced on the stack of the next stage, or on the thread iterating over
+    /// the pipeline if there is none.
+    pub fn new<I>(source: I) -> Pipeline<T>
+        where I: IntoIterator<Item = T>,
+              I::IntoIter: 'static
+    {
+        Pipeline { last: Box::new(source.into_iter()) }
+    }
+
+    /// Appends a stage executing `f` on a `ProtectedFixedSizeStack` of **at least** `stack_size`
+    /// bytes, which reads the items of the current last stage from its `Input`.
+    ///
+    /// `f` doesn't run until the pipeline is iterated over. The stage ends once `f` returns,
+    /// whether it consumed its whole input or not. Dropping the pipeline unwinds all stages
+    /// which haven't finished yet, like `iter::lazy_from_fn()` does.
+    pub fn stage<U, F>(self, stack_size: usize, f: F) -> Result<Pipeline<U>, StackError>
+        where U: 'static,
+              F: FnOnce(&mut Input<T>, &Output<U>) + 'static
+    {
+        let mut input = Input { upstream: self.last };
+        let stage: LazyIter<U> = iter::lazy_from_fn(stack_size, move |yielder| {
+            f(&mut input, &Output { yielder })
+        })?;
+
+        Ok(Pipeline { last: Box::new(stage) })
+    }
+}
+
+impl<T> Iterator for Pipeline<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.last.next()
+    }
+}
+
+impl<T> fmt::Debug for Pipeline<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pipeline").finish()
+    }
+}
+
+/// The items of the previous stage of a `Pipeline`, as read by a stage.
+///
+/// Advancing it switches to the previous stage until it pushed the next item.
+pub struct Input<T> {
+    upstream: Box<dyn Iterator<Item = T>>,
+}
+
+impl<T> Iterator for Input<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.upstream.next()
+    }
+}
+
+impl<T> fmt::Debug for Input<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Input").finish()
+    }
+}
+
+/// Passes the items of a stage of a `Pipeline` on to the next one.
+pub struct Output<'a, T: 'a> {
+    yielder: &'a Yielder<(), T>,
+}
+
+impl<'a, T> Output<'a, T> {
+    /// Passes `item` on to the next stage, suspending the current one until it took the item.
+    pub fn push(&self, item: T) {
+        self.yielder.suspend(item)
+    }
+}
+
+impl<'a, T> fmt::Debug for Output<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Output").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn backpressure() {
+        let produced = Rc::new(Cell::new(0));
+
+        let p = produced.clone();
+        let mut pipeline = Pipeline::new(0..)
+            .stage(64 * 1024, move |input, output| {
+                for n in input {
+                    p.set(p.get() + 1);
+                    output.push(n);
+                }
+            })
+            .unwrap()
+            .stage(64 * 1024, |input, output| {
+                for n in input.filter(|n| n % 2 == 1) {
+                    output.push(n);
+                }
+            })
+            .unwrap();
+
+        assert_eq!(produced.get(), 0);
+        assert_eq!(pipeline.next(), Some(1));
+        assert_eq!(produced.get(), 2);
+        assert_eq!(pipeline.next(), Some(3));
+        assert_eq!(produced.get(), 4);
+
+        // Dropping the pipeline unwinds the infinite stages, releasing the captured Rc.
+        drop(pipeline);
+        #[cfg(panic = "unwind")]
+        assert_eq!(Rc::strong_count(&produced), 1);
+    }
+
+    #[test]
+    fn early_return() {
+        let mut pipeline = Pipeline::new(vec![1, 2, 3])
+            .stage(64 * 1024, |input, output| output.push(input.sum::<i32>()))
+            .unwrap()
+            .stage(64 * 1024, |input, output| {
+                let first = input.next().unwrap();
+                output.push(first);
+            })
+            .unwrap();
+
+        assert_eq!(pipeline.next(), Some(6));
+        assert_eq!(pipeline.next(), None);
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::mem;
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+
+use compat::corosensei::{Coroutine, CoroutineResult, Yielder};
+use stack::{ProtectedFixedSizeStack, StackError};
+#[cfg(feature = "coroutine-profiling")]
+use trace;
+
+/// Identifies a coroutine spawned using `spawn()` to `context_resume_token()`.
+///
+/// Tokens are never 0 and never reused within a process.
+pub type Token = u64;
+
+/// Returned by `context_resume_token()` if the resume was scheduled, and carried out unless
+/// it was made from within a coroutine.
+pub const RESUME_OK: c_int = 0;
+
+/// Returned by `context_resume_token()` if the token doesn't belong to a coroutine which is
+/// still running.
+pub const RESUME_UNKNOWN_TOKEN: c_int = -1;
+
+/// Returned by `context_resume_token()` if the coroutine was spawned on another thread.
+pub const RESUME_WRONG_THREAD: c_int = -2;
+
+/// Returned by `context_resume_token()` if a coroutine panicked while the scheduled resumes
+/// were carried out. The panicking coroutine is dropped, the other resumes are carried out.
+pub const RESUME_PANICKED: c_int = -3;
+
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+// The thread every live token was spawned on, which is the only one able to resume it.
+static OWNERS: Mutex<Option<HashMap<Token, ThreadId>>> = Mutex::new(None);
+
+fn owners() -> MutexGuard<'static, Option<HashMap<Token, ThreadId>>> {
+    OWNERS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// The coroutines spawned on a thread along with their scheduled resumes.
+struct Reactor {
+    // Suspended coroutines. A coroutine is taken out of the map while it runs.
+    coroutines: HashMap<Token, Coroutine<usize, (), ()>>,
+    queue: VecDeque<(Token, usize)>,
+    // Whether the queue is being drained further up the call stack.
+    running: bool,
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        if let Some(ref mut owners) = *owners() {
+            for token in self.coroutines.keys() {
+                owners.remove(token);
+            }
+        }
+    }
+}
+
+thread_local! {
+    static REACTOR: RefCell<Reactor> = RefCell::new(Reactor {
+        coroutines: HashMap::new(),
+        queue: VecDeque::new(),
+        running: false,
+    });
+}
+
+/// Spawns a coroutine executing `f` on a `ProtectedFixedSizeStack` of **at least**
+/// `stack_size` bytes and returns the token C code resumes it with.
+///
+/// The coroutine is run right away until it waits for the first time, unless `spawn()` is
+/// called from within a coroutine, in which case it's started once the current resume
+/// finished. It's dropped once `f` returns, after which its token is unknown.
+///
+/// # Panics
+///
+/// If a coroutine panics while the new one is started, the panic is propagated to the caller.
+///
+/// # Examples
+///
+/// ```
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+///
+/// use context::reactor::{self, RESUME_OK};
+///
+/// let sum = Rc::new(Cell::new(0));
+///
+/// let s = sum.clone();
+/// let token = reactor::spawn(64 * 1024, move |suspender| {
+///     // A real coroutine would pass suspender.token() to a C callback, like the `data` of a
+///     // libuv handle, and wait for the callback to resume it with the result.
+///     for _ in 0..2 {
+///         s.set(s.get() + suspender.wait());
+///     }
+/// })
+/// .unwrap();
+///
+/// // What the C callbacks would call, through the exported symbol.
+/// assert_eq!(reactor::context_resume_token(token, 20), RESUME_OK);
+/// assert_eq!(reactor::context_resume_token(token, 22), RESUME_OK);
+/// assert_eq!(sum.get(), 42);
+/// ```
+pub fn spawn<F>(stack_size: usize, f: F) -> Result<Token, StackError>
+    where F: FnOnce(&Suspender) + 'static
+{
+    let stack = ProtectedFixedSizeStack::new(stack_size)?;
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let coroutine = Coroutine::with_stack(stack, move |yielder, _| {
+        f(&Suspender { yielder, token })
+    });
+
+    owners().get_or_insert_with(HashMap::new).insert(token, thread::current().id());
+    REACTOR.with(|reactor| {
+        let mut reactor = reactor.borrow_mut();
+        reactor.coroutines.insert(token, coroutine);
+        reactor.queue.push_back((token, 0));
+        #[cfg(feature = "coroutine-profiling")]
+        {
+            trace::wake(token, 0, "spawn");
+            trace::queue_depth(reactor.queue.len());
+        }
+    });
+
+    // The panics of the coroutines resumed while starting this one are propagated.
+    if let Some(payload) = drain() {
+        panic::resume_unwind(payload);
+    }
+
+    Ok(token)
+}
+
+/// Schedules the coroutine identified by `token` to be resumed with `data`, which is returned
+/// by the call to `Suspender::wait()` it waits in, and returns one of the `RESUME_*` codes.
+///
+/// This is the entry point for C code driving the coroutines, for instance from the callbacks
+/// of a libuv or libevent loop, and is exported under its name. Its C declaration is:
+///
+/// ```c
+/// int context_resume_token(uint64_t token, uintptr_t data);
+/// ```
+///
+/// It has to be called on the thread the coroutine was spawned on. Called outside of any
+/// coroutine, it resumes the coroutine right away and returns once it waits again, or
+/// finished. Resumes scheduled meanwhile, for instance by a coroutine calling back into C code
+/// which calls this function, are queued and carried out in order before it returns. Panics of
+/// the coroutines are caught and reported as `RESUME_PANICKED`, since they can't unwind into C.
+#[no_mangle]
+pub extern "C" fn context_resume_token(token: Token, data: usize) -> c_int {
+    let current = thread::current().id();
+
+    match owners().as_ref().and_then(|owners| owners.get(&token)) {
+        Some(&owner) if owner == current => (),
+        Some(_) => return RESUME_WRONG_THREAD,
+        None => return RESUME_UNKNOWN_TOKEN,
+    }
+
+    REACTOR.with(|reactor| {
+        let mut reactor = reactor.borrow_mut();
+        reactor.queue.push_back((token, data));
+        #[cfg(feature = "coroutine-profiling")]
+        {
+            trace::wake(token, data, if reactor.running { "reentrant" } else { "callback" });
+            trace::queue_depth(reactor.queue.len());
+        }
+    });
+
+    match drain() {
+        Some(_) => RESUME_PANICKED,
+        None => RESUME_OK,
+    }
+}
+
+// Carries out the scheduled resumes unless this is done further up the call stack already,
+// and returns the payload of the first panic of a coroutine, if any.
+fn drain() -> Option<Box<dyn Any + Send>> {
+    let started = REACTOR.with(|reactor| !mem::replace(&mut reactor.borrow_mut().running, true));
+    let mut panic = None;
+
+    if !started {
+        return None;
+    }
+
+    loop {
+        let next = REACTOR.with(|reactor| {
+            let mut reactor = reactor.borrow_mut();
+            let (token, data) = reactor.queue.pop_front()?;
+            #[cfg(feature = "coroutine-profiling")]
+            trace::queue_depth(reactor.queue.len());
+            // A coroutine which finished after the resume was scheduled is skipped.
+            Some(reactor.coroutines.remove(&token).map(|coroutine| (token, data, coroutine)))
+        });
+
+        let (token, data, mut coroutine) = match next {
+            Some(Some(next)) => next,
+            Some(None) => continue,
+            None => break,
+        };
+
+        match panic::catch_unwind(AssertUnwindSafe(|| coroutine.resume(data))) {
+            Ok(CoroutineResult::Yield(())) => {
+                REACTOR.with(|reactor| reactor.borrow_mut().coroutines.insert(token, coroutine));
+            },
+            result => {
+                if let Err(payload) = result {
+                    panic.get_or_insert(payload);
+                }
+                if let Some(ref mut owners) = *owners() {
+                    owners.remove(&token);
+                }
+            },
+        }
+    }
+
+    REACTOR.with(|reactor| reactor.borrow_mut().running = false);
+    panic
+}
+
+/// Lets a coroutine spawned using `spawn()` wait to be resumed by `context_resume_token()`.
+pub struct Suspender<'a> {
+    yielder: &'a Yielder<usize, ()>,
+    token: Token,
+}
+
+impl<'a> Suspender<'a> {
+    /// Returns the token identifying the coroutine, which is passed to C code to resume it.
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    /// Suspends the coroutine until `context_resume_token()` is called with its token and
+    /// returns the `data` passed to it.
+    pub fn wait(&self) -> usize {
+        self.yielder.suspend(())
+    }
+}
+
+impl<'a> fmt::Debug for Suspender<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Suspender").field("token", &self.token).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn reentrant_resumes_are_queued() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let l = log.clone();
+        let waiter = spawn(64 * 1024, move |suspender| {
+            let data = suspender.wait();
+            l.borrow_mut().push(("waiter", data));
+        })
+        .unwrap();
+
+        let l = log.clone();
+        let notifier = spawn(64 * 1024, move |suspender| {
+            let data = suspender.wait();
+            // Stands in for a C callback resuming another coroutine.
+            assert_eq!(context_resume_token(waiter, data + 1), RESUME_OK);
+            l.borrow_mut().push(("notifier", data));
+        })
+        .unwrap();
+
+        assert_eq!(context_resume_token(notifier, 1), RESUME_OK);
+        assert_eq!(*log.borrow(), [("notifier", 1), ("waiter", 2)]);
+        assert_eq!(context_resume_token(waiter, 0), RESUME_UNKNOWN_TOKEN);
+    }
+
+    #[test]
+    fn wrong_thread() {
+        let token = spawn(64 * 1024, |suspender| {
+            suspender.wait();
+        })
+        .unwrap();
+
+        let result = thread::spawn(move || context_resume_token(token, 0)).join().unwrap();
+        assert_eq!(result, RESUME_WRONG_THREAD);
+        assert_eq!(context_resume_token(token, 0), RESUME_OK);
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    fn panics_are_caught() {
+        let token = spawn(64 * 1024, |suspender| {
+            suspender.wait();
+            panic!("boom");
+        })
+        .unwrap();
+
+        assert_eq!(context_resume_token(token, 0), RESUME_PANICKED);
+        assert_eq!(context_resume_token(token, 0), RESUME_UNKNOWN_TOKEN);
+    }
+}
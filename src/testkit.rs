@@ -0,0 +1,309 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::os::raw::c_int;
+use std::rc::Rc;
+use std::time::Duration;
+
+use reactor::{self, Token};
+
+/// Identifies a timer scheduled using `Clock::schedule()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId {
+    deadline: Duration,
+    sequence: u64,
+}
+
+impl TimerId {
+    /// Returns the time on the clock at which the timer expires.
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+}
+
+/// A resume carried out by a `Clock` because a timer expired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fired {
+    /// The time on the clock at which the timer expired.
+    pub at: Duration,
+
+    /// The token of the resumed coroutine.
+    pub token: Token,
+
+    /// The `data` it was resumed with.
+    pub data: usize,
+
+    /// The `RESUME_*` code returned by `context_resume_token()`.
+    pub result: c_int,
+}
+
+#[derive(Default)]
+struct State {
+    now: Duration,
+    sequence: u64,
+    timers: BTreeMap<TimerId, (Token, usize)>,
+    fired: Vec<Fired>,
+}
+
+/// A virtual clock standing in for the timers of the C loop driving the coroutines spawned
+/// using `reactor::spawn()`.
+///
+/// A timer resumes a coroutine through `context_resume_token()` once the clock was advanced to
+/// its deadline. Time only passes by calling `advance()` or `run_until_idle()`, so tests of
+/// timeouts and cancellations take no real time and always observe the same order: timers
+/// expire in the order of their deadlines, and timers sharing a deadline in the order they
+/// were scheduled in.
+///
+/// Clones share the same clock, which lets coroutines schedule and cancel timers themselves.
+/// The clock has to be used on the thread the coroutines were spawned on.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use context::reactor::{self, RESUME_OK, RESUME_UNKNOWN_TOKEN};
+/// use context::testkit::Clock;
+///
+/// const TIMED_OUT: usize = 0;
+///
+/// let clock = Clock::new();
+///
+/// let c = clock.clone();
+/// let token = reactor::spawn(64 * 1024, move |suspender| {
+///     // Wait for a reply for at most a second.
+///     let timeout = c.schedule(Duration::from_secs(1), suspender.token(), TIMED_OUT);
+///     if suspender.wait() != TIMED_OUT {
+///         c.cancel(timeout);
+///     }
+/// })
+/// .unwrap();
+///
+/// // The reply arrives too late.
+/// clock.schedule(Duration::from_millis(1500), token, 42);
+/// clock.advance(Duration::from_secs(2));
+///
+/// let results: Vec<_> = clock.take_fired().iter().map(|fired| fired.result).collect();
+/// assert_eq!(results, [RESUME_OK, RESUME_UNKNOWN_TOKEN]);
+/// ```
+#[derive(Clone, Default)]
+pub struct Clock(Rc<RefCell<State>>);
+
+impl Clock {
+    /// Creates a clock at time zero without any timers.
+    pub fn new() -> Clock {
+        Clock::default()
+    }
+
+    /// Returns the time passed on the clock since it was created.
+    pub fn now(&self) -> Duration {
+        self.0.borrow().now
+    }
+
+    /// Schedules the coroutine identified by `token` to be resumed with `data` once `delay`
+    /// passed on the clock.
+    pub fn schedule(&self, delay: Duration, token: Token, data: usize) -> TimerId {
+        let mut state = self.0.borrow_mut();
+        let timer = TimerId {
+            deadline: state.now + delay,
+            sequence: state.sequence,
+        };
+
+        state.sequence += 1;
+        state.timers.insert(timer, (token, data));
+        timer
+    }
+
+    /// Cancels `timer` and returns whether it was still pending.
+    pub fn cancel(&self, timer: TimerId) -> bool {
+        self.0.borrow_mut().timers.remove(&timer).is_some()
+    }
+
+    /// Returns the number of timers which haven't expired or been cancelled yet.
+    pub fn pending(&self) -> usize {
+        self.0.borrow().timers.len()
+    }
+
+    /// Advances the clock by `by`, expiring all timers due until then in order.
+    ///
+    /// Timers scheduled by the resumed coroutines expire as well, if they're due until then.
+    pub fn advance(&self, by: Duration) {
+        let end = self.now() + by;
+
+        while let Some((timer, (token, data))) = self.expire(end) {
+            // The clock isn't borrowed, so that the coroutine can schedule further timers.
+            let result = reactor::context_resume_token(token, data);
+
+            self.0.borrow_mut().fired.push(Fired {
+                at: timer.deadline,
+                token,
+                data,
+                result,
+            });
+        }
+
+        self.0.borrow_mut().now = end;
+    }
+
+    /// Advances the clock until no timers are left and returns the time which passed.
+    ///
+    /// This never returns if the coroutines keep scheduling timers.
+    pub fn run_until_idle(&self) -> Duration {
+        let start = self.now();
+
+        loop {
+            let deadline = match self.0.borrow().timers.keys().next() {
+                Some(timer) => timer.deadline,
+                None => break,
+            };
+            self.advance(deadline - self.now());
+        }
+
+        self.now() - start
+    }
+
+    /// Returns the resumes carried out by expired timers since the last call, in order.
+    pub fn take_fired(&self) -> Vec<Fired> {
+        self.0.borrow_mut().fired.drain(..).collect()
+    }
+
+    /// Asserts that expired timers resumed exactly the coroutines identified by `tokens`,
+    /// in that order, since the last call to `take_fired()` or `assert_order()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the resumes which were carried out if they differ.
+    pub fn assert_order(&self, tokens: &[Token]) {
+        let fired = self.take_fired();
+        let order: Vec<Token> = fired.iter().map(|fired| fired.token).collect();
+
+        assert!(order == tokens,
+                "expected the coroutines to be resumed in the order {:?}, but the timers \
+                 carried out {:?}",
+                tokens,
+                fired);
+    }
+
+    // Removes the first timer due at `end` or earlier and moves the clock to its deadline.
+    fn expire(&self, end: Duration) -> Option<(TimerId, (Token, usize))> {
+        let mut state = self.0.borrow_mut();
+        let timer = *state.timers.keys().next().filter(|timer| timer.deadline <= end)?;
+
+        state.now = timer.deadline;
+        state.timers.remove(&timer).map(|resume| (timer, resume))
+    }
+}
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.0.borrow();
+        f.debug_struct("Clock")
+            .field("now", &state.now)
+            .field("pending", &state.timers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use reactor::{spawn, RESUME_OK};
+    use super::*;
+
+    // Spawns a coroutine recording the data it's resumed with until it was resumed `n` times.
+    fn recorder(n: usize, log: &Rc<RefCell<Vec<usize>>>) -> Token {
+        let log = log.clone();
+        spawn(64 * 1024, move |suspender| {
+            for _ in 0..n {
+                let data = suspender.wait();
+                log.borrow_mut().push(data);
+            }
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn deadline_order() {
+        let clock = Clock::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let first = recorder(2, &log);
+        let second = recorder(1, &log);
+
+        clock.schedule(Duration::from_millis(20), first, 3);
+        clock.schedule(Duration::from_millis(10), second, 1);
+        clock.schedule(Duration::from_millis(10), first, 2);
+
+        clock.advance(Duration::from_millis(15));
+        assert_eq!(clock.now(), Duration::from_millis(15));
+        assert_eq!(clock.pending(), 1);
+        assert_eq!(*log.borrow(), [1, 2]);
+
+        assert_eq!(clock.run_until_idle(), Duration::from_millis(5));
+        assert_eq!(*log.borrow(), [1, 2, 3]);
+
+        let fired = clock.take_fired();
+        assert_eq!(fired.len(), 3);
+        assert_eq!(fired[0].at, Duration::from_millis(10));
+        assert!(fired.iter().all(|fired| fired.result == RESUME_OK));
+        assert!(clock.take_fired().is_empty());
+    }
+
+    #[test]
+    fn cancel() {
+        let clock = Clock::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let token = recorder(1, &log);
+
+        let timer = clock.schedule(Duration::from_secs(1), token, 1);
+        assert_eq!(timer.deadline(), Duration::from_secs(1));
+        assert!(clock.cancel(timer));
+        assert!(!clock.cancel(timer));
+
+        clock.advance(Duration::from_secs(2));
+        assert!(log.borrow().is_empty());
+        assert!(clock.take_fired().is_empty());
+    }
+
+    #[test]
+    fn timers_scheduled_by_coroutines() {
+        let clock = Clock::new();
+
+        let c = clock.clone();
+        let ticker = spawn(64 * 1024, move |suspender| {
+            for _ in 0..3 {
+                c.schedule(Duration::from_millis(100), suspender.token(), 0);
+                suspender.wait();
+            }
+        })
+        .unwrap();
+
+        clock.advance(Duration::from_millis(250));
+        clock.assert_order(&[ticker, ticker]);
+        assert_eq!(clock.run_until_idle(), Duration::from_millis(50));
+        clock.assert_order(&[ticker]);
+    }
+
+    #[test]
+    #[cfg(panic = "unwind")]
+    #[should_panic(expected = "expected the coroutines to be resumed in the order")]
+    fn assert_order() {
+        let clock = Clock::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let first = recorder(1, &log);
+        let second = recorder(1, &log);
+
+        clock.schedule(Duration::from_millis(2), first, 0);
+        clock.schedule(Duration::from_millis(1), second, 0);
+        clock.run_until_idle();
+        clock.assert_order(&[first, second]);
+    }
+}
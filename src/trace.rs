@@ -0,0 +1,374 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+thread_local! {
+    // A small number identifying the current thread in the trace, assigned on first use.
+    static TID: u64 = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy)]
+enum Arg {
+    Int(u64),
+    Hex(u64),
+    Str(&'static str),
+}
+
+#[derive(Clone, Copy)]
+enum Phase {
+    Complete(f64),
+    #[cfg(feature = "reactor")]
+    Instant,
+    #[cfg(feature = "reactor")]
+    Counter,
+}
+
+struct Event {
+    name: &'static str,
+    category: &'static str,
+    phase: Phase,
+    ts: f64,
+    tid: u64,
+    args: [(&'static str, Arg); 3],
+    len: usize,
+}
+
+struct Recorder {
+    events: VecDeque<Event>,
+    capacity: usize,
+    dropped: u64,
+    // The names of the threads which recorded events, by tid.
+    threads: BTreeMap<u64, String>,
+}
+
+fn recorder() -> MutexGuard<'static, Option<Recorder>> {
+    RECORDER.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// The instant all timestamps are relative to.
+fn origin() -> Instant {
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    *ORIGIN.get_or_init(Instant::now)
+}
+
+fn micros(instant: Instant) -> f64 {
+    instant.saturating_duration_since(origin()).as_nanos() as f64 / 1000.0
+}
+
+/// Starts recording the coroutine switches of all threads, keeping the most recent `capacity`
+/// events, until `disable()` is called.
+///
+/// Any events recorded before are discarded. The following events are recorded:
+///
+/// - Every resume of a `compat::corosensei::Coroutine`, from the switch into it until it
+///   switched back, as a slice named after its profile tag, or `"coroutine"` if it has none.
+///   Its arguments are the identity and epoch of the coroutine and how the resume ended.
+///   Nested resumes show up as nested slices.
+/// - With the `reactor` feature, every resume scheduled through `reactor::spawn()` or
+///   `reactor::context_resume_token()` as an instant event named `"wake"`, whose `reason` is
+///   `"spawn"`, `"callback"`, or `"reentrant"` if it was scheduled from within a coroutine and
+///   thus queued, and the length of the queue of scheduled resumes as a counter.
+///
+/// While disabled, which is the default, recording costs a relaxed atomic load per switch.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+///
+/// # Examples
+///
+/// ```
+/// use context::compat::corosensei::Coroutine;
+/// use context::trace;
+///
+/// trace::enable(1024);
+///
+/// let mut coroutine = Coroutine::<(), (), ()>::new(|yielder, ()| yielder.suspend(()));
+/// coroutine.set_profile_tag(Some("example"));
+/// coroutine.resume(());
+/// coroutine.resume(());
+///
+/// trace::disable();
+///
+/// // Open this file in chrome://tracing or https://ui.perfetto.dev.
+/// let mut json = Vec::new();
+/// trace::write_json(&mut json).unwrap();
+/// assert!(String::from_utf8(json).unwrap().contains(r#""name":"example""#));
+/// ```
+pub fn enable(capacity: usize) {
+    assert!(capacity > 0, "tracing requires a positive capacity");
+
+    origin();
+    *recorder() = Some(Recorder {
+        events: VecDeque::with_capacity(capacity.min(4096)),
+        capacity,
+        dropped: 0,
+        threads: BTreeMap::new(),
+    });
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stops recording events. The events recorded so far are kept until `enable()` or `clear()`
+/// is called.
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Returns whether events are recorded.
+#[inline]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Discards the recorded events.
+pub fn clear() {
+    if let Some(ref mut recorder) = *recorder() {
+        recorder.events.clear();
+        recorder.dropped = 0;
+    }
+}
+
+/// Writes the recorded events to `out` in the Trace Event JSON format, which is understood by
+/// chrome://tracing and Perfetto.
+///
+/// Timestamps are in microseconds since tracing was first enabled. Threads are named after
+/// their Rust name, if any. The number of events dropped because the capacity was exceeded is
+/// stored as `droppedEvents` in the metadata.
+pub fn write_json<W: Write>(mut out: W) -> io::Result<()> {
+    let recorder = recorder();
+    let pid = process::id();
+
+    write!(out, r#"{{"traceEvents":["#)?;
+
+    let mut first = true;
+    if let Some(ref recorder) = *recorder {
+        for (tid, name) in &recorder.threads {
+            if !first {
+                out.write_all(b",")?;
+            }
+            first = false;
+
+            write!(out,
+                   r#"{{"name":"thread_name","ph":"M","pid":{},"tid":{},"args":{{"name":"#,
+                   pid,
+                   tid)?;
+            write_str(&mut out, name)?;
+            out.write_all(b"}}")?;
+        }
+
+        for event in &recorder.events {
+            if !first {
+                out.write_all(b",")?;
+            }
+            first = false;
+
+            out.write_all(br#"{"name":"#)?;
+            write_str(&mut out, event.name)?;
+            write!(out, r#","cat":"{}","#, event.category)?;
+            match event.phase {
+                Phase::Complete(dur) => write!(out, r#""ph":"X","dur":{:.3},"#, dur)?,
+                #[cfg(feature = "reactor")]
+                Phase::Instant => out.write_all(br#""ph":"i","s":"t","#)?,
+                #[cfg(feature = "reactor")]
+                Phase::Counter => out.write_all(br#""ph":"C","#)?,
+            }
+            write!(out, r#""ts":{:.3},"pid":{},"tid":{},"args":{{"#, event.ts, pid, event.tid)?;
+
+            for (i, &(key, value)) in event.args[..event.len].iter().enumerate() {
+                if i > 0 {
+                    out.write_all(b",")?;
+                }
+                write!(out, r#""{}":"#, key)?;
+                match value {
+                    Arg::Int(value) => write!(out, "{}", value)?,
+                    // Viewers parse numbers as doubles, which can't represent every u64.
+                    Arg::Hex(value) => write!(out, r#""{:#018x}""#, value)?,
+                    Arg::Str(value) => write_str(&mut out, value)?,
+                }
+            }
+            out.write_all(b"}}")?;
+        }
+    }
+
+    let dropped = recorder.as_ref().map_or(0, |recorder| recorder.dropped);
+    write!(out, r#"],"metadata":{{"droppedEvents":{}}}}}"#, dropped)
+}
+
+// Writes `s` as a JSON string literal.
+fn write_str<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+fn record(name: &'static str,
+          category: &'static str,
+          phase: Phase,
+          ts: f64,
+          args: &[(&'static str, Arg)]) {
+    let tid = match TID.try_with(|&tid| tid) {
+        Ok(tid) => tid,
+        Err(_) => return,
+    };
+
+    let mut event = Event {
+        name,
+        category,
+        phase,
+        ts,
+        tid,
+        args: [("", Arg::Int(0)); 3],
+        len: args.len(),
+    };
+    event.args[..args.len()].copy_from_slice(args);
+
+    let mut recorder = recorder();
+    let recorder = match *recorder {
+        Some(ref mut recorder) => recorder,
+        None => return,
+    };
+
+    recorder.threads.entry(tid).or_insert_with(|| {
+        let thread = thread::current();
+        thread.name().map_or_else(|| format!("thread {}", tid), str::to_owned)
+    });
+
+    if recorder.events.len() == recorder.capacity {
+        recorder.events.pop_front();
+        recorder.dropped += 1;
+    }
+    recorder.events.push_back(event);
+}
+
+// Records a resume of a coroutine which started at `start` and has just switched back.
+pub(crate) fn switch(name: &'static str,
+                     start: Instant,
+                     identity: u64,
+                     epoch: u64,
+                     outcome: &'static str) {
+    let ts = micros(start);
+    let dur = micros(Instant::now()) - ts;
+
+    record(name,
+           "switch",
+           Phase::Complete(dur),
+           ts,
+           &[("identity", Arg::Hex(identity)),
+             ("epoch", Arg::Int(epoch)),
+             ("outcome", Arg::Str(outcome))]);
+}
+
+// Records that the coroutine identified by `token` has been scheduled to be resumed.
+#[cfg(feature = "reactor")]
+pub(crate) fn wake(token: u64, data: usize, reason: &'static str) {
+    if is_enabled() {
+        record("wake",
+               "reactor",
+               Phase::Instant,
+               micros(Instant::now()),
+               &[("token", Arg::Int(token)),
+                 ("data", Arg::Int(data as u64)),
+                 ("reason", Arg::Str(reason))]);
+    }
+}
+
+// Records the number of resumes scheduled but not yet carried out.
+#[cfg(feature = "reactor")]
+pub(crate) fn queue_depth(depth: usize) {
+    if is_enabled() {
+        record("reactor queue",
+               "reactor",
+               Phase::Counter,
+               micros(Instant::now()),
+               &[("depth", Arg::Int(depth as u64))]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compat::corosensei::Coroutine;
+    use super::*;
+
+    fn json() -> String {
+        let mut json = Vec::new();
+        write_json(&mut json).unwrap();
+        String::from_utf8(json).unwrap()
+    }
+
+    // The only test enabling tracing, since it's process-wide.
+    #[test]
+    fn records_switches() {
+        enable(1 << 16);
+
+        let mut coroutine = Coroutine::<(), (), ()>::new(|yielder, ()| {
+            let mut inner = Coroutine::<(), (), ()>::new(|_, ()| {});
+            inner.set_profile_tag(Some("trace \"inner\""));
+            inner.resume(());
+            yielder.suspend(());
+        });
+        coroutine.set_profile_tag(Some("trace outer"));
+        coroutine.resume(());
+        coroutine.resume(());
+
+        #[cfg(feature = "reactor")]
+        {
+            use reactor;
+
+            let token = reactor::spawn(64 * 1024, |suspender| {
+                suspender.wait();
+            })
+            .unwrap();
+            assert_eq!(reactor::context_resume_token(token, 7), reactor::RESUME_OK);
+        }
+
+        disable();
+        let trace = json();
+
+        assert!(trace.starts_with(r#"{"traceEvents":["#));
+        assert!(trace.ends_with("}"));
+        assert_eq!(trace.matches(r#""name":"trace outer""#).count(), 2);
+        assert!(trace.contains(r#""name":"trace \"inner\"""#));
+        assert!(trace.contains(r#""outcome":"suspended""#));
+        assert!(trace.contains(r#""outcome":"returned""#));
+        assert!(trace.contains(r#""name":"thread_name""#));
+
+        #[cfg(feature = "reactor")]
+        {
+            assert!(trace.contains(r#""reason":"spawn""#));
+            assert!(trace.contains(r#""data":7,"reason":"callback""#));
+            assert!(trace.contains(r#""name":"reactor queue""#));
+        }
+
+        clear();
+        assert!(!json().contains("trace outer"));
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let mut out = Vec::new();
+        write_str(&mut out, "a\"b\\c\nd").unwrap();
+        assert_eq!(out, br#""a\"b\\c\u000ad""#);
+    }
+}
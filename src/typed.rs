@@ -0,0 +1,483 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::slice;
+
+use context::{self, ContextFn, ResumeOntopFn};
+use stack::Stack;
+
+/// Marker for a `Context` which was created by `Context::new()` but never resumed.
+#[derive(Debug)]
+pub enum Created {}
+
+/// Marker for a `Context` which has been executing before and yielded to another one.
+#[derive(Debug)]
+pub enum Suspended {}
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for super::Created {}
+    impl Sealed for super::Suspended {}
+}
+
+/// The lifecycle states a `Context` can be in while a handle to it exists.
+///
+/// This trait is sealed and only implemented by `Created` and `Suspended`.
+pub trait State: private::Sealed {}
+
+impl State for Created {}
+impl State for Suspended {}
+
+/// A `context::Context` which tracks its lifecycle state in its type.
+///
+/// A context goes through the states Created → Running → Suspended → Running → ... → Finished.
+/// Only the Created and Suspended states can be represented by a handle though:
+/// A running context is not reachable through any `Context` value, since resuming consumes it
+/// and the next handle to it is only handed out once it yields again, and a finished context
+/// is indistinguishable from a suspended one which is never resumed again.
+///
+/// Encoding the states as types turns some misuses into compile errors. Most notably
+/// `resume_ontop()` is only available for `Context<Suspended>`, since calling it on a context
+/// which has never been resumed skips the proper entry of its `ContextFn`:
+///
+/// ```compile_fail
+/// use context::stack::ProtectedFixedSizeStack;
+/// use context::typed::Context;
+/// use context::Transfer;
+///
+/// extern "C" fn context_function(t: Transfer) -> ! {
+///     unimplemented!()
+/// }
+///
+/// extern "C" fn ontop_function(t: Transfer) -> Transfer {
+///     t
+/// }
+///
+/// let stack = ProtectedFixedSizeStack::default();
+/// let ctx = unsafe { Context::new(&stack, context_function) };
+/// unsafe { ctx.resume_ontop(0, ontop_function) };
+/// ```
+#[repr(transparent)]
+#[must_use = "dropping a Context leaks its stack and everything living on it"]
+pub struct Context<S: State> {
+    inner: context::Context,
+    state: PhantomData<S>,
+}
+
+impl<S: State> Context<S> {
+    /// Returns the underlying, untyped `context::Context`.
+    #[inline(always)]
+    pub fn into_raw(self) -> context::Context {
+        self.inner
+    }
+}
+
+impl Context<Created> {
+    /// Creates a new `Context` prepared to execute `f` at the beginning of `stack`.
+    ///
+    /// See `context::Context::new()` for more information.
+    ///
+    /// # Safety
+    ///
+    /// See `context::Context::new()`.
+    #[inline(always)]
+    pub unsafe fn new(stack: &Stack, f: ContextFn) -> Context<Created> {
+        Context {
+            inner: context::Context::new(stack, f),
+            state: PhantomData,
+        }
+    }
+
+    /// Yields the execution to this freshly created `Context`, entering its `ContextFn`.
+    ///
+    /// See `context::Context::resume()` for more information.
+    ///
+    /// # Safety
+    ///
+    /// See `context::Context::resume()`.
+    #[inline(always)]
+    pub unsafe fn resume(self, data: usize) -> Transfer {
+        Transfer::from_raw(self.inner.resume(data))
+    }
+}
+
+impl Context<Suspended> {
+    /// Wraps a `context::Context` which is known to have been suspended.
+    ///
+    /// # Safety
+    ///
+    /// `context` must not have been created by `context::Context::new()` without being
+    /// resumed since. Every `Context` returned as part of a `context::Transfer` is suspended.
+    #[inline(always)]
+    pub unsafe fn from_raw(context: context::Context) -> Context<Suspended> {
+        Context {
+            inner: context,
+            state: PhantomData,
+        }
+    }
+
+    /// Yields the execution to this suspended `Context`.
+    ///
+    /// See `context::Context::resume()` for more information.
+    ///
+    /// # Safety
+    ///
+    /// See `context::Context::resume()`.
+    #[inline(always)]
+    pub unsafe fn resume(self, data: usize) -> Transfer {
+        Transfer::from_raw(self.inner.resume(data))
+    }
+
+    /// Yields the execution to this suspended `Context` and executes `f` "ontop" of it's stack.
+    ///
+    /// See `context::Context::resume_ontop()` for more information.
+    ///
+    /// # Safety
+    ///
+    /// See `context::Context::resume_ontop()`.
+    #[inline(always)]
+    pub unsafe fn resume_ontop(self, data: usize, f: ResumeOntopFn) -> Transfer {
+        Transfer::from_raw(self.inner.resume_ontop(data, f))
+    }
+
+    /// Yields the execution to this suspended `Context` and executes the closure `f` "ontop" of
+    /// it's stack, like `resume_ontop()`.
+    ///
+    /// Contrary to a `ResumeOntopFn` the closure may borrow data from the caller. This is sound,
+    /// because `f` is executed right after the switch and before the resumed `Context` returns
+    /// from it's call to `resume()`, while the caller is still suspended with all of its locals.
+    /// `f` receives the `Transfer` which would otherwise be returned by that call,
+    /// containing `data`, and returns the one which is returned instead.
+    ///
+    /// A panic inside of `f` aborts the process, since `f` is called from the assembly.
+    ///
+    /// # Safety
+    ///
+    /// See `context::Context::resume_ontop()`.
+    #[inline(always)]
+    pub unsafe fn resume_ontop_scoped<F>(self, data: usize, f: F) -> Transfer
+        where F: FnOnce(Transfer) -> Transfer
+    {
+        struct Ontop<F> {
+            f: Option<F>,
+            data: usize,
+        }
+
+        extern "C" fn ontop_function<F>(t: context::Transfer) -> context::Transfer
+            where F: FnOnce(Transfer) -> Transfer
+        {
+            let (f, data) = {
+                let ontop = unsafe { &mut *(t.data as *mut Ontop<F>) };
+                (ontop.f.take().unwrap(), ontop.data)
+            };
+
+            let t = Transfer {
+                context: unsafe { Context::from_raw(t.context) },
+                data,
+            };
+
+            f(t).into_raw()
+        }
+
+        let mut ontop = Ontop {
+            f: Some(f),
+            data,
+        };
+
+        let ptr = &mut ontop as *mut Ontop<F> as usize;
+        Transfer::from_raw(self.inner.resume_ontop(ptr, ontop_function::<F>))
+    }
+
+    /// Yields the execution to this suspended `Context`, handing over a borrowed slice of `items`.
+    ///
+    /// The resumed `Context` receives the slice by converting the `Transfer` returned from its
+    /// call to `resume()` using `Batch::from_transfer()`. The items stay borrowed until this
+    /// `Context` is resumed in turn, which is only possible by consuming that `Batch`.
+    /// This allows handing over any number of values with a single switch.
+    ///
+    /// # Safety
+    ///
+    /// See `context::Context::resume()`. The resumed `Context` must convert the `Transfer`
+    /// into a `Batch<T>` of the same `T`.
+    #[inline(always)]
+    pub unsafe fn resume_batch<T>(self, items: &[T]) -> Transfer {
+        self.resume(&items as *const &[T] as usize)
+    }
+}
+
+impl<S: State> fmt::Debug for Context<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+/// The typed equivalent of `context::Transfer`.
+///
+/// The `context` is always `Suspended`, since it's the one which just yielded.
+#[derive(Debug)]
+#[must_use = "dropping a Transfer leaks the stack of its Context and everything living on it"]
+pub struct Transfer {
+    /// The previously executed `Context` which yielded to resume the current one.
+    pub context: Context<Suspended>,
+
+    /// The `data` which was passed to resume the current `Context`.
+    pub data: usize,
+}
+
+impl Transfer {
+    /// Wraps a `context::Transfer` received by a `ContextFn` or returned from `resume()`.
+    ///
+    /// # Safety
+    ///
+    /// The `context` of `t` must be suspended, see `Context::from_raw()`.
+    #[inline(always)]
+    pub unsafe fn from_raw(t: context::Transfer) -> Transfer {
+        Transfer {
+            context: Context::from_raw(t.context),
+            data: t.data,
+        }
+    }
+
+    /// Returns the underlying, untyped `context::Transfer`.
+    #[inline(always)]
+    pub fn into_raw(self) -> context::Transfer {
+        context::Transfer::new(self.context.into_raw(), self.data)
+    }
+}
+
+/// A slice of values handed over by `Context::resume_batch()`.
+///
+/// The slice lives on the stack of the `Context` which handed it over and remains valid as long
+/// as that `Context` is suspended. It can thus only be accessed through the `Batch`, which must be
+/// consumed to resume the `Context` again.
+///
+/// # Examples
+///
+/// ```
+/// use context::stack::ProtectedFixedSizeStack;
+/// use context::typed::{Batch, Context};
+///
+/// extern "C" fn sum(t: context::Transfer) -> ! {
+///     // Return from the initial resume() and wait for the first batch.
+///     let mut t = unsafe { context::typed::Transfer::from_raw(t).context.resume(0) };
+///
+///     loop {
+///         let batch = unsafe { Batch::<u64>::from_transfer(t) };
+///         let sum: u64 = batch.items().iter().sum();
+///         t = unsafe { batch.resume(sum as usize) };
+///     }
+/// }
+///
+/// let stack = ProtectedFixedSizeStack::default();
+/// let t = unsafe { Context::new(&stack, sum).resume(0) };
+///
+/// let t = unsafe { t.context.resume_batch(&[1u64, 2, 3]) };
+/// assert_eq!(t.data, 6);
+/// ```
+pub struct Batch<T> {
+    context: Context<Suspended>,
+    items: *const [T],
+}
+
+impl<T> Batch<T> {
+    /// Converts the `Transfer` returned from a call to `resume()` which was resumed by
+    /// `Context::resume_batch()` into the handed over `Batch`.
+    ///
+    /// # Safety
+    ///
+    /// The `Context` of `t` must have called `resume_batch()` with a slice of `T`.
+    #[inline(always)]
+    pub unsafe fn from_transfer(t: Transfer) -> Batch<T> {
+        Batch {
+            context: t.context,
+            items: *(t.data as *const &[T]),
+        }
+    }
+
+    /// Returns the handed over items.
+    #[inline(always)]
+    pub fn items(&self) -> &[T] {
+        unsafe { &*self.items }
+    }
+
+    /// Returns the number of handed over items.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.items().len()
+    }
+
+    /// Returns whether the handed over slice is empty.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.items().is_empty()
+    }
+
+    /// Yields the execution back to the `Context` which handed over the batch,
+    /// ending the borrow of its items.
+    ///
+    /// # Safety
+    ///
+    /// See `context::Context::resume()`.
+    #[inline(always)]
+    pub unsafe fn resume(self, data: usize) -> Transfer {
+        self.context.resume(data)
+    }
+
+    /// Yields the execution back to the `Context` which handed over the batch,
+    /// ending the borrow of its items and handing over `items` in turn.
+    ///
+    /// # Safety
+    ///
+    /// See `Context::resume_batch()`.
+    #[inline(always)]
+    pub unsafe fn resume_batch<U>(self, items: &[U]) -> Transfer {
+        self.context.resume_batch(items)
+    }
+}
+
+impl<T> fmt::Debug for Batch<T>
+    where T: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Batch")
+            .field("context", &self.context)
+            .field("items", &self.items())
+            .finish()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Batch<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> slice::Iter<'a, T> {
+        self.items().iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use context;
+    use stack::ProtectedFixedSizeStack;
+    use super::*;
+
+    #[test]
+    fn type_sizes() {
+        assert_eq!(mem::size_of::<Context<Created>>(), mem::size_of::<context::Context>());
+        assert_eq!(mem::size_of::<Context<Suspended>>(), mem::size_of::<context::Context>());
+    }
+
+    #[test]
+    fn number_generator() {
+        extern "C" fn context_function(t: context::Transfer) -> ! {
+            let mut t = unsafe { Transfer::from_raw(t) };
+
+            for i in 0usize.. {
+                assert_eq!(t.data, i);
+                t = unsafe { t.context.resume(i) };
+            }
+
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let ctx = unsafe { Context::new(&stack, context_function) };
+        let mut t = unsafe { ctx.resume(0) };
+        assert_eq!(t.data, 0);
+
+        for i in 1..10usize {
+            t = unsafe { t.context.resume(i) };
+            assert_eq!(t.data, i);
+        }
+    }
+
+    #[test]
+    fn resume_ontop() {
+        extern "C" fn resume(t: context::Transfer) -> ! {
+            let t = unsafe { Transfer::from_raw(t) };
+            assert_eq!(t.data, 0);
+            let _ = unsafe { t.context.resume_ontop(1, resume_ontop) };
+            unreachable!();
+        }
+
+        extern "C" fn resume_ontop(mut t: context::Transfer) -> context::Transfer {
+            assert_eq!(t.data, 1);
+            t.data = 123;
+            t
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let ctx = unsafe { Context::new(&stack, resume) };
+
+        let t = unsafe { ctx.resume(0) };
+        assert_eq!(t.data, 123);
+    }
+
+    #[test]
+    fn resume_ontop_scoped() {
+        extern "C" fn context_function(t: context::Transfer) -> ! {
+            let t = unsafe { Transfer::from_raw(t).context.resume(0) };
+            assert_eq!(t.data, 6);
+            let _ = unsafe { t.context.resume(7) };
+            unreachable!();
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let t = unsafe { Context::new(&stack, context_function).resume(0) };
+
+        let mut log = Vec::new();
+        let t = unsafe {
+            t.context.resume_ontop_scoped(5, |mut t| {
+                log.push(t.data);
+                t.data += 1;
+                t
+            })
+        };
+
+        assert_eq!(t.data, 7);
+        assert_eq!(log, [5]);
+    }
+
+    #[test]
+    fn resume_batch() {
+        // Doubles every item of the batches it receives and hands them back.
+        extern "C" fn context_function(t: context::Transfer) -> ! {
+            let mut t = unsafe { Transfer::from_raw(t).context.resume(0) };
+            let mut doubled = Vec::new();
+
+            loop {
+                let batch = unsafe { Batch::<String>::from_transfer(t) };
+                doubled.clear();
+                doubled.extend(batch.items().iter().map(|item| item.repeat(2)));
+                t = unsafe { batch.resume_batch(&doubled) };
+            }
+        }
+
+        fn items(n: usize) -> Vec<String> {
+            (0..n).map(|i| i.to_string()).collect()
+        }
+
+        let stack = ProtectedFixedSizeStack::default();
+        let t = unsafe { Context::new(&stack, context_function).resume(0) };
+        let t = unsafe { t.context.resume_batch(&items(0)) };
+        let mut batch = unsafe { Batch::<String>::from_transfer(t) };
+
+        for n in 1..5 {
+            assert_eq!(batch.len(), n - 1);
+            for (i, item) in batch.items().iter().enumerate() {
+                assert_eq!(*item, i.to_string().repeat(2));
+            }
+
+            batch = unsafe { Batch::from_transfer(batch.resume_batch(&items(n))) };
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Runs every program in `examples/` so they can't silently rot.
+
+extern crate context;
+
+#[path = "../examples/basic.rs"]
+#[allow(dead_code)]
+mod basic;
+
+#[path = "../examples/fibonacci.rs"]
+#[allow(dead_code)]
+mod fibonacci;
+
+// Unwinding stacks is what these examples demonstrate, so they can't run under panic = "abort".
+#[cfg(panic = "unwind")]
+#[path = "../examples/how_to_ontop.rs"]
+#[allow(dead_code)]
+mod how_to_ontop;
+
+#[cfg(all(feature = "corosensei-compat", panic = "unwind"))]
+#[path = "../examples/coroutine_drop.rs"]
+#[allow(dead_code)]
+mod coroutine_drop;
+
+#[cfg(feature = "corosensei-compat")]
+#[path = "../examples/scoped_generator.rs"]
+#[allow(dead_code)]
+mod scoped_generator;
+
+#[test]
+fn basic() {
+    basic::run();
+}
+
+#[test]
+fn fibonacci() {
+    fibonacci::run();
+}
+
+#[cfg(panic = "unwind")]
+#[test]
+fn how_to_ontop() {
+    how_to_ontop::run();
+}
+
+#[cfg(all(feature = "corosensei-compat", panic = "unwind"))]
+#[test]
+fn coroutine_drop() {
+    coroutine_drop::run();
+}
+
+#[cfg(feature = "corosensei-compat")]
+#[test]
+fn scoped_generator() {
+    scoped_generator::run();
+}
@@ -0,0 +1,208 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Drives arbitrary sequences of resume, yield, return, panic and force-unwind operations across
+// a small pool of coroutines and checks that every destructor and deferred function ran exactly
+// as often as a simple model predicts.
+//
+// `run()` interprets arbitrary bytes and can thus be used as the body of a `cargo fuzz` target.
+// The test below feeds it pseudo-random inputs, whose number and seed can be changed using the
+// `CONTEXT_FUZZ_ITERATIONS` and `CONTEXT_FUZZ_SEED` environment variables.
+#![cfg(all(feature = "corosensei-compat", panic = "unwind"))]
+
+extern crate context;
+
+use std::cell::Cell;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+
+use context::compat::corosensei::{Coroutine, CoroutineResult, ResumeErrorKind, UnwindStrategy};
+
+const POOL_SIZE: usize = 4;
+
+// The panic payload used inside the coroutines. resume_unwind() skips the panic hook.
+struct Bail;
+
+#[derive(Clone, Copy, Debug)]
+enum Command {
+    Yield,
+    Return,
+    Panic,
+}
+
+#[derive(Default)]
+struct Counters {
+    drops: Cell<usize>,
+    deferred: Cell<usize>,
+}
+
+struct Dropper(Rc<Counters>);
+
+impl Drop for Dropper {
+    fn drop(&mut self) {
+        self.0.drops.set(self.0.drops.get() + 1);
+    }
+}
+
+// The expected state of a coroutine of the pool.
+struct Slot {
+    coroutine: Coroutine<Command, usize, usize>,
+    started: bool,
+    done: bool,
+    yields: usize,
+}
+
+impl Slot {
+    fn new(counters: &Rc<Counters>) -> Slot {
+        let counters = counters.clone();
+        let coroutine = Coroutine::new(move |yielder, mut command| {
+            let _dropper = Dropper(counters.clone());
+            yielder.defer(move || counters.deferred.set(counters.deferred.get() + 1));
+
+            let mut yields = 0;
+
+            loop {
+                match command {
+                    Command::Yield => {
+                        yields += 1;
+                        command = yielder.suspend(yields);
+                    }
+                    Command::Return => return yields,
+                    Command::Panic => panic::resume_unwind(Box::new(Bail)),
+                }
+            }
+        });
+
+        Slot {
+            coroutine,
+            started: false,
+            done: false,
+            yields: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Expected {
+    drops: usize,
+    deferred: usize,
+}
+
+impl Expected {
+    // Accounts for a coroutine which is about to be force-unwound or dropped.
+    fn unwind(&mut self, slot: &Slot) {
+        if slot.started && !slot.done {
+            self.deferred += 1;
+
+            if slot.coroutine.unwind_strategy() == UnwindStrategy::Panic {
+                self.drops += 1;
+            }
+        }
+    }
+}
+
+fn resume(slot: &mut Slot, expected: &mut Expected, command: Command) {
+    if slot.done {
+        match slot.coroutine.try_resume(command) {
+            Err(err) => {
+                assert_eq!(err.kind(), ResumeErrorKind::Finished);
+                return;
+            },
+            Ok(_) => panic!("resumed a finished coroutine"),
+        }
+    }
+
+    slot.started = true;
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| slot.coroutine.resume(command)));
+
+    match (command, result) {
+        (Command::Yield, Ok(CoroutineResult::Yield(yields))) => {
+            slot.yields += 1;
+            assert_eq!(yields, slot.yields);
+            return;
+        }
+        (Command::Return, Ok(CoroutineResult::Return(yields))) => assert_eq!(yields, slot.yields),
+        (Command::Panic, Err(err)) => assert!(err.is::<Bail>()),
+        (command, _) => panic!("unexpected result for {:?}", command),
+    }
+
+    slot.done = true;
+    expected.drops += 1;
+    expected.deferred += 1;
+}
+
+/// Interprets `data` as a sequence of operations on a pool of coroutines.
+pub fn run(data: &[u8]) {
+    let counters = Rc::new(Counters::default());
+    let mut expected = Expected::default();
+    let mut pool: Vec<Slot> = (0..POOL_SIZE).map(|_| Slot::new(&counters)).collect();
+
+    for &byte in data {
+        let slot = &mut pool[byte as usize % POOL_SIZE];
+
+        match (byte as usize / POOL_SIZE) % 8 {
+            0..=2 => resume(slot, &mut expected, Command::Yield),
+            3 => resume(slot, &mut expected, Command::Return),
+            4 => resume(slot, &mut expected, Command::Panic),
+            5 => {
+                expected.unwind(slot);
+                slot.coroutine.force_unwind();
+                slot.done = true;
+            }
+            6 => {
+                let strategy = match slot.coroutine.unwind_strategy() {
+                    UnwindStrategy::Panic => UnwindStrategy::Deferred,
+                    UnwindStrategy::Deferred => UnwindStrategy::Panic,
+                };
+                slot.coroutine.set_unwind_strategy(strategy);
+            }
+            _ => {
+                expected.unwind(slot);
+                *slot = Slot::new(&counters);
+            }
+        }
+
+        assert_eq!(slot.coroutine.done(), slot.done);
+        assert_eq!(counters.drops.get(), expected.drops);
+        assert_eq!(counters.deferred.get(), expected.deferred);
+    }
+
+    for slot in &pool {
+        expected.unwind(slot);
+    }
+
+    drop(pool);
+    assert_eq!(counters.drops.get(), expected.drops);
+    assert_eq!(counters.deferred.get(), expected.deferred);
+}
+
+fn env_or(name: &str, default: u64) -> u64 {
+    env::var(name).ok().and_then(|val| val.parse().ok()).unwrap_or(default)
+}
+
+#[test]
+fn fuzz() {
+    let iterations = env_or("CONTEXT_FUZZ_ITERATIONS", 64);
+    let seed = env_or("CONTEXT_FUZZ_SEED", 0x2545_f491_4f6c_dd1d);
+
+    // xorshift64*
+    let mut state = seed | 1;
+    let mut next = move || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    };
+
+    for _ in 0..iterations {
+        let len = next() as usize;
+        let data: Vec<u8> = (0..len).map(|_| next()).collect();
+        run(&data);
+    }
+}
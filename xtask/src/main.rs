@@ -0,0 +1,204 @@
+// Copyright 2016 coroutine-rs Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Development tasks of the workspace, run using `cargo xtask <task>`.
+//!
+//! * `qemu [TARGET...]` cross-compiles the test suite for every target whose assembly is shipped
+//!   and which qemu-user can run, or only the given ones, and runs it under qemu-user.
+//!   Targets whose toolchain, linker or emulator is missing are skipped.
+//! * `qemu --list` prints those targets along with the tools they require.
+
+use std::env;
+use std::process::{self, Command, Stdio};
+
+// A Linux target to run the test suite on, along with the tools required to do so.
+struct Target {
+    triple: &'static str,
+    // The prefix of the GNU cross toolchain, which provides the linker and assembler.
+    gnu_prefix: &'static str,
+    qemu: &'static str,
+    // Tier 3 targets have no prebuilt standard library and need nightly and `-Zbuild-std`.
+    build_std: bool,
+}
+
+const TARGETS: &[Target] = &[
+    Target {
+        triple: "i686-unknown-linux-gnu",
+        gnu_prefix: "i686-linux-gnu",
+        qemu: "qemu-i386",
+        build_std: false,
+    },
+    Target {
+        triple: "x86_64-unknown-linux-gnu",
+        gnu_prefix: "x86_64-linux-gnu",
+        qemu: "qemu-x86_64",
+        build_std: false,
+    },
+    Target {
+        triple: "armv7-unknown-linux-gnueabihf",
+        gnu_prefix: "arm-linux-gnueabihf",
+        qemu: "qemu-arm",
+        build_std: false,
+    },
+    Target {
+        triple: "aarch64-unknown-linux-gnu",
+        gnu_prefix: "aarch64-linux-gnu",
+        qemu: "qemu-aarch64",
+        build_std: false,
+    },
+    Target {
+        triple: "mips-unknown-linux-gnu",
+        gnu_prefix: "mips-linux-gnu",
+        qemu: "qemu-mips",
+        build_std: true,
+    },
+    Target {
+        triple: "mipsel-unknown-linux-gnu",
+        gnu_prefix: "mipsel-linux-gnu",
+        qemu: "qemu-mipsel",
+        build_std: true,
+    },
+    Target {
+        triple: "powerpc-unknown-linux-gnu",
+        gnu_prefix: "powerpc-linux-gnu",
+        qemu: "qemu-ppc",
+        build_std: false,
+    },
+    Target {
+        triple: "powerpc64-unknown-linux-gnu",
+        gnu_prefix: "powerpc64-linux-gnu",
+        qemu: "qemu-ppc64",
+        build_std: false,
+    },
+];
+
+// The features enabled for the cross-compiled test suite. Tracing and debugging features are
+// left out, since they don't touch the assembly and require additional tools.
+const FEATURES: &str = "accounting corosensei-compat coroutine-introspection current";
+
+enum Outcome {
+    Passed,
+    Failed,
+    Skipped(String),
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("qemu") if args.get(1).map(String::as_str) == Some("--list") => list(),
+        Some("qemu") => {
+            if !qemu(&args[1..]) {
+                process::exit(1);
+            }
+        },
+        _ => {
+            eprintln!("usage: cargo xtask qemu [--list | TARGET...]");
+            process::exit(2);
+        },
+    }
+}
+
+fn list() {
+    for target in TARGETS {
+        let std = if target.build_std { ", nightly with rust-src" } else { "" };
+        println!("{:32} {}-gcc, {}{}", target.triple, target.gnu_prefix, target.qemu, std);
+    }
+}
+
+// Runs the test suite for the selected targets and returns whether none of them failed.
+fn qemu(selected: &[String]) -> bool {
+    if let Some(unknown) = selected.iter().find(|s| !TARGETS.iter().any(|t| t.triple == *s)) {
+        eprintln!("unknown target {}, see `cargo xtask qemu --list`", unknown);
+        return false;
+    }
+
+    let installed = installed_targets();
+    let mut results = Vec::new();
+
+    for target in TARGETS {
+        if selected.is_empty() || selected.iter().any(|s| s == target.triple) {
+            println!("==> {}", target.triple);
+            results.push((target.triple, run(target, &installed)));
+        }
+    }
+
+    println!("\nqemu test matrix:");
+    let mut ok = true;
+
+    for (triple, outcome) in results {
+        match outcome {
+            Outcome::Passed => println!("  {:32} passed", triple),
+            Outcome::Failed => {
+                println!("  {:32} FAILED", triple);
+                ok = false;
+            },
+            Outcome::Skipped(reason) => println!("  {:32} skipped ({})", triple, reason),
+        }
+    }
+
+    ok
+}
+
+fn run(target: &Target, installed: &[String]) -> Outcome {
+    let gcc = format!("{}-gcc", target.gnu_prefix);
+
+    if !target.build_std && !installed.iter().any(|t| t == target.triple) {
+        return Outcome::Skipped(format!("run `rustup target add {}`", target.triple));
+    }
+
+    for tool in &[&gcc[..], target.qemu] {
+        if !is_available(tool) {
+            return Outcome::Skipped(format!("{} not found", tool));
+        }
+    }
+
+    let env_triple = target.triple.to_uppercase().replace('-', "_");
+    // Selecting another toolchain requires the rustup proxy instead of the current cargo.
+    let mut cargo = if target.build_std {
+        let mut cargo = Command::new("cargo");
+        cargo.arg("+nightly");
+        cargo
+    } else {
+        Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned()))
+    };
+
+    cargo.args(["test", "--workspace", "--exclude", "xtask", "--target", target.triple])
+        .args(["--features", FEATURES, "--target-dir"])
+        .arg("target/qemu")
+        .env(format!("CARGO_TARGET_{}_LINKER", env_triple), &gcc)
+        .env(format!("CARGO_TARGET_{}_RUNNER", env_triple),
+             format!("{} -L /usr/{}", target.qemu, target.gnu_prefix))
+        .env(format!("CC_{}", target.triple.replace('-', "_")), &gcc);
+
+    if target.build_std {
+        cargo.arg("-Zbuild-std");
+    }
+
+    match cargo.status() {
+        Ok(status) if status.success() => Outcome::Passed,
+        Ok(_) => Outcome::Failed,
+        Err(err) => Outcome::Skipped(format!("failed to run cargo: {}", err)),
+    }
+}
+
+fn installed_targets() -> Vec<String> {
+    Command::new("rustup")
+        .args(["target", "list", "--installed"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).lines().map(str::to_owned).collect())
+        .unwrap_or_default()
+}
+
+fn is_available(tool: &str) -> bool {
+    Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}